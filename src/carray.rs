@@ -0,0 +1,32 @@
+//! C source encoding of binary data.
+
+use std::io::{self, Write};
+
+/// Number of bytes in each line of the array.
+const LINE_LEN: usize = 12;
+
+/// Writes the given data as a `uint8_t` C array with the given name, followed by a `size_t`
+/// constant with its length, named as the array with a `_len` suffix.
+pub fn write<W: Write>(writer: &mut W, name: &str, data: &[u8]) -> io::Result<()> {
+    writeln!(writer, "const uint8_t {}[] = {{", name)?;
+    for line in data.chunks(LINE_LEN) {
+        write!(writer, "   ")?;
+        for byte in line {
+            write!(writer, " 0x{:02x},", byte)?;
+        }
+        writeln!(writer)?;
+    }
+    writeln!(writer, "}};")?;
+    writeln!(writer, "const size_t {}_len = {};", name, data.len())
+}
+
+/// Checks if the given name is a valid C identifier.
+pub fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
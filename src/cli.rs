@@ -1,16 +1,161 @@
-use clap::{crate_version, App, Arg, SubCommand};
+//! Command line interface definition.
 
+use clap::{crate_version, App, AppSettings, Arg, SubCommand};
+
+/// Generates the CLI of the tool.
 pub fn generate() -> App<'static, 'static> {
     App::new("Tools for Allwinner devices in FEL mode")
         .version(crate_version!())
         .author("Iban Eguia (Razican) <razican@protonmail.ch>")
         .about("Flashes bootloaders to Allwinner ARM devices")
         .arg(Arg::with_name("device")
-            .help("The USB bus and device address of the FEL device")
+            .help("The USB bus and device address of the FEL device. A comma separated list \
+                   runs the command in each of the devices. By default, the value of the \
+                   `FEL_DEVICE` environment variable, if set")
             .short("d")
             .long("device")
             .value_name("bus:addr")
             .takes_value(true))
+        .arg(Arg::with_name("all_devices")
+            .help("Run the command concurrently in all the connected FEL devices, and print a \
+                   summary of the results. Only supported by the spl, write and fill commands")
+            .long("all-devices")
+            .conflicts_with_all(&["device", "prefer_device", "serial"]))
+        .arg(Arg::with_name("serial")
+            .help("The USB serial number of the FEL device")
+            .long("serial")
+            .value_name("SERIAL")
+            .takes_value(true)
+            .conflicts_with_all(&["device", "prefer_device"]))
+        .arg(Arg::with_name("config")
+            .help("Configuration file with the default device and options. By default, \
+                   `fel.toml` in the current directory, if it exists. Options given in the \
+                   command line take precedence over the ones in the file")
+            .long("config")
+            .value_name("FILE")
+            .takes_value(true))
+        .arg(Arg::with_name("prefer_device")
+            .help("The USB bus and device address, or the USB serial number, of the preferred \
+                   FEL device. If it's not found, the first available device will be used")
+            .long("prefer-device")
+            .value_name("bus:addr|serial")
+            .takes_value(true)
+            .conflicts_with("device"))
+        .arg(Arg::with_name("keep_going")
+            .help("Continue with the next device if the command fails in one of them")
+            .long("keep-going"))
+        .arg(Arg::with_name("exit_zero_on_no_device")
+            .help("Exit successfully if no FEL device is found")
+            .long("exit-zero-on-no-device"))
+        .arg(Arg::with_name("dump_registers_on_error")
+            .help("Print the diagnostic registers of the device if a write, exec or spl \
+                   command fails")
+            .long("dump-registers-on-error"))
+        .arg(Arg::with_name("diagnostic_registers")
+            .help("Comma separated list of register addresses to print instead of the default \
+                   diagnostic registers of the SoC")
+            .long("diagnostic-registers")
+            .value_name("addr,...")
+            .takes_value(true)
+            .requires("dump_registers_on_error"))
+        .arg(Arg::with_name("validate_only")
+            .help("Parse and validate the command and its input files without touching the \
+                   device")
+            .long("validate-only"))
+        .arg(Arg::with_name("dry_run")
+            .help("Print what the command would do in the device, without touching it")
+            .long("dry-run")
+            .conflicts_with("validate_only"))
+        .arg(Arg::with_name("no_device")
+            .help("Do not require a FEL device to be connected in a dry run")
+            .long("no-device")
+            .requires("dry_run"))
+        .arg(Arg::with_name("strict_hex")
+            .help("Require an explicit radix prefix (`0x`, `0o` or `0b`) in all numeric \
+                   arguments, instead of parsing them as decimal")
+            .long("strict-hex"))
+        .arg(Arg::with_name("hex_uppercase")
+            .help("Use uppercase digits in hexadecimal output")
+            .long("hex-uppercase"))
+        .arg(Arg::with_name("json")
+            .help("Print the results of the command as JSON objects, one per line")
+            .long("json"))
+        .arg(Arg::with_name("quiet")
+            .help("Do not print success messages, status messages nor the progress of the \
+                   command. The requested output and errors are still printed")
+            .short("q")
+            .long("quiet"))
+        .arg(Arg::with_name("yes")
+            .help("Do not ask for confirmation before clearing or filling memory. It's required \
+                   if stdin is not a terminal")
+            .short("y")
+            .long("yes"))
+        .arg(Arg::with_name("verbose")
+            .help("Print debugging logs to stderr. Repeat it to print more details")
+            .short("v")
+            .long("verbose")
+            .multiple(true))
+        .arg(Arg::with_name("prewarm")
+            .help("Prime the USB link with a throwaway read before running the command, and \
+                   print its latency")
+            .long("prewarm"))
+        .arg(Arg::with_name("require_clean_exit")
+            .help("Fail if the device does not answer FEL requests anymore after running the \
+                   command")
+            .long("require-clean-exit"))
+        .arg(Arg::with_name("wait")
+            .help("Wait until a FEL device is connected before running the command, for at most \
+                   the given number of seconds, if any")
+            .long("wait")
+            .value_name("SECS")
+            .takes_value(true)
+            .min_values(0)
+            .require_equals(true))
+        .arg(Arg::with_name("stats")
+            .help("Print the time and throughput of memory transfers to stderr. Transfers bigger \
+                   than 1 MiB are always logged in verbose mode")
+            .long("stats"))
+        .arg(Arg::with_name("show_sid")
+            .help("Print the SID of the device after the command output")
+            .long("show-sid"))
+        .arg(Arg::with_name("summary_json")
+            .help("Write a JSON summary of the result of the command in each device to the given \
+                   file")
+            .long("summary-json")
+            .value_name("path")
+            .takes_value(true))
+        .arg(Arg::with_name("retry_file_read")
+            .help("Number of times to retry reading an input file if it fails")
+            .long("retry-file-read")
+            .value_name("N")
+            .takes_value(true)
+            .default_value("0"))
+        .arg(Arg::with_name("retries")
+            .help("Number of times to retry a memory read, write or fill if it fails because of a \
+                   USB error")
+            .long("retries")
+            .value_name("N")
+            .takes_value(true)
+            .default_value("0"))
+        .arg(Arg::with_name("chunk_size")
+            .help("Size of the chunks in which memory is read from and written to the device, a \
+                   power of two from 512 bytes to 16 MiB. Bigger chunks need fewer USB requests, \
+                   but each of them takes longer and must finish before the USB timeout. Accepts \
+                   K, M, G, KiB, MiB and GiB suffixes")
+            .long("chunk-size")
+            .value_name("bytes")
+            .takes_value(true)
+            .default_value("64KiB"))
+        .arg(Arg::with_name("scratch_region")
+            .help("Region where the commands that need a temporary buffer in the device, such as \
+                   `exec` with arguments, place it. `auto` chooses the free SRAM after the FEL \
+                   scratch area of the SoC, avoiding the memory that FEL and the command use. \
+                   Otherwise, the base address and the size of the region, such as \
+                   0x40000000:1MiB")
+            .long("scratch-region")
+            .value_name("auto|addr:size")
+            .takes_value(true)
+            .default_value("auto"))
         .subcommand(SubCommand::with_name("spl")
             .about("Loads and executes U-Boot SPL. If file additionally contains a main U-Boot \
                     binary, it will transfer it to memory and print the entry point address, in \
@@ -22,52 +167,443 @@ pub fn generate() -> App<'static, 'static> {
             .arg(Arg::with_name("exec")
                 .help("Execute U-Boot binary instead of printing the entry point address")
                 .short("x")
-                .long("exec")))
+                .long("exec"))
+            .arg(Arg::with_name("no_exec")
+                .help("Print the entry point address of the U-Boot binary without executing it. \
+                       This is the default")
+                .long("no-exec")
+                .conflicts_with("exec"))
+            .arg(Arg::with_name("entry")
+                .help("Address to execute instead of the entry point of the main U-Boot binary")
+                .long("entry")
+                .value_name("addr")
+                .requires("exec")
+                .takes_value(true))
+            .arg(Arg::with_name("allow_small")
+                .help("Allow executing files without a main U-Boot binary as standalone SPLs. Files \
+                       of up to 32 KiB, the size of the SPL area, have no main U-Boot binary")
+                .long("allow-small")
+                .requires("exec"))
+            .arg(Arg::with_name("check_dram")
+                .help("Check that DRAM works after executing the SPL, before writing the main \
+                       U-Boot binary")
+                .long("check-dram"))
+            .arg(Arg::with_name("verify")
+                .help("Read back the SPL and check that it matches the file before writing the \
+                       main U-Boot binary. The SPL is written and executed at once, so it's read \
+                       back once it returns to FEL mode")
+                .long("verify")))
         .subcommand(SubCommand::with_name("dump")
             .about("Dumps memory region in binary through stdout")
             .arg(Arg::with_name("addr")
                 .help("The memory address to dump")
                 .takes_value(true)
-                .required_unless("sid")
+                .required_unless_one(&["sid", "region"])
                 .conflicts_with("sid"))
             .arg(Arg::with_name("size")
                 .help("The size of the dumped memory chunk, in bytes. By default, a 32-bit word \
-                       (4 bytes)")
+                       (4 bytes). Accepts K, M, G, KiB, MiB and GiB suffixes")
                 .takes_value(true)
                 .conflicts_with("sid"))
+            .arg(Arg::with_name("region")
+                .help("Dump a known memory region of the SoC instead of the given address and \
+                       size. `sram` is the boot SRAM, where the SPL is loaded")
+                .long("region")
+                .possible_values(&["sram", "sram-a1", "sram-c"])
+                .takes_value(true)
+                .conflicts_with_all(&["addr", "size", "sid", "words", "endian"]))
+            .arg(Arg::with_name("words")
+                .help("Number of consecutive 32-bit words to print, each with its address, if no \
+                       size is given. With `--out`, the words are written to the file as raw \
+                       bytes, in the byte order given with `--endian`. By default, 1")
+                .long("words")
+                .value_name("N")
+                .takes_value(true)
+                .conflicts_with_all(&["size", "sid", "hex"]))
+            .arg(Arg::with_name("endian")
+                .help("Byte order of the dumped words, if no size is given. By default, `little`, \
+                       the native order of the SoC")
+                .long("endian")
+                .possible_values(&["little", "big"])
+                .takes_value(true)
+                .conflicts_with_all(&["size", "sid"]))
             .arg(Arg::with_name("hex")
-                .help("Dump memory in hexadecimal instead of binary")
+                .help("Dump memory in hexadecimal instead of binary. With `--out`, the \
+                       hexadecimal dump is written to the file")
                 .long("hex")
-                .conflicts_with_all(&["out", "sid"]))
+                .conflicts_with("sid"))
+            .arg(Arg::with_name("width")
+                .help("Number of bytes in each line of the hexadecimal dump. By default, 16")
+                .long("width")
+                .possible_values(&["8", "16", "32"])
+                .requires("hex")
+                .takes_value(true))
+            .arg(Arg::with_name("color")
+                .help("When to color the hexadecimal dump. With `auto`, only if it's printed \
+                       to a terminal. By default, `auto`")
+                .long("color")
+                .possible_values(&["auto", "always", "never"])
+                .takes_value(true))
             .arg(Arg::with_name("sid")
                 .help("Get the value of the SID registers")
                 .long("sid")
-                .conflicts_with_all(&["addr", "size", "hex", "out"]))
+                .conflicts_with_all(&["addr", "size", "hex"]))
+            .arg(Arg::with_name("sid_format")
+                .help("Output format of the SID registers: words separated by colons, the bytes \
+                       as a hexadecimal string, the raw bytes, or a JSON array of the words. By \
+                       default, `colon`, or `json` with `--json`")
+                .long("sid-format")
+                .possible_values(&["colon", "hex", "raw", "json"])
+                .requires("sid")
+                .takes_value(true))
             .arg(Arg::with_name("out")
-                .help("Output binary data to the specified file. Note: it will be overwritten if \
-                       it already exists")
+                .help("Output the dumped data to the specified file. Note: it will be overwritten \
+                       if it already exists, unless `--append` is given")
                 .short("o")
                 .long("out")
+                .takes_value(true))
+            .arg(Arg::with_name("format")
+                .help("Format of the dumped data. By default, raw binary")
+                .long("format")
+                .possible_values(&["binary", "intel-hex", "srec", "carray"])
                 .conflicts_with_all(&["hex", "sid"])
+                .takes_value(true))
+            .arg(Arg::with_name("array_name")
+                .help("Name of the C array of the `carray` format. By default, `data`")
+                .long("array-name")
+                .value_name("NAME")
+                .requires("format")
+                .takes_value(true))
+            .arg(Arg::with_name("append")
+                .help("Append the dumped data to the output file instead of overwriting it")
+                .long("append")
+                .requires("out"))
+            .arg(Arg::with_name("force")
+                .help("Write raw binary data to stdout even if it's a terminal")
+                .long("force")
+                .conflicts_with_all(&["hex", "out"]))
+            .arg(Arg::with_name("compress")
+                .help("Compress the output file. The extension of the format will be appended to \
+                       the file name if not present")
+                .long("compress")
+                .possible_values(&["gzip", "xz"])
+                .requires("out")
+                .takes_value(true))
+            .arg(Arg::with_name("read_retries")
+                .help("Number of times to retry reading a chunk of memory if it fails")
+                .long("read-retries")
+                .value_name("N")
+                .takes_value(true)
+                .conflicts_with("sid"))
+            .arg(Arg::with_name("partial_ok")
+                .help("Continue dumping if a chunk of memory cannot be read, leaving it zeroed")
+                .long("partial-ok")
+                .conflicts_with("sid"))
+            .arg(Arg::with_name("align_output")
+                .help("Pad the output file up to a multiple of the given number of bytes")
+                .long("align-output")
+                .value_name("bytes")
+                .requires("out")
+                .conflicts_with_all(&["sid", "hex"])
+                .takes_value(true))
+            .arg(Arg::with_name("count")
+                .help("Number of times to dump the memory. Each dump is written to the output \
+                       file with its index as extension, as in `out.0`, or to stdout after a \
+                       separator line. By default, 1")
+                .long("count")
+                .value_name("N")
+                .conflicts_with("sid")
+                .takes_value(true))
+            .arg(Arg::with_name("interval")
+                .help("Time between dumps, in milliseconds. By default, 0")
+                .long("interval")
+                .value_name("ms")
+                .requires("count")
+                .takes_value(true))
+            .arg(Arg::with_name("pad_byte")
+                .help("Byte to use to pad the output file. By default, 0x00")
+                .long("pad-byte")
+                .value_name("byte")
+                .requires("align_output")
                 .takes_value(true)))
         .subcommand(SubCommand::with_name("write")
             .about("Write data to device memory")
             .arg(Arg::with_name("write_data")
-                .help("Address to start writing and 32-bit word or input file to write")
+                .help("Address to start writing and 32-bit word or input file to write. Intel HEX \
+                       and S-record files can be given without address. A `-` file writes the raw \
+                       data read from stdin, and it can only be given once")
                 .takes_value(true)
                 .required(true)
                 .multiple(true)
-                .value_names(&["address", "word|file"])))
+                .value_names(&["address", "word|file"]))
+            .arg(Arg::with_name("format")
+                .help("Format of the input files. By default, Intel HEX for files with the `.hex` \
+                       extension, S-records for `.srec`, `.s19`, `.s28` and `.s37` files, and raw \
+                       binary for the rest. The given address is added to the addresses in Intel \
+                       HEX and S-record files")
+                .long("format")
+                .possible_values(&["binary", "intel-hex", "srec"])
+                .takes_value(true))
+            .arg(Arg::with_name("endian")
+                .help("Byte order of the words to write. By default, `little`, the native order of \
+                       the SoC")
+                .long("endian")
+                .possible_values(&["little", "big"])
+                .takes_value(true))
+            .arg(Arg::with_name("offset")
+                .help("Offset in the raw binary files of the first byte to write. By default, 0. \
+                       Accepts K, M, G, KiB, MiB and GiB suffixes")
+                .long("offset")
+                .value_name("bytes")
+                .takes_value(true))
+            .arg(Arg::with_name("length")
+                .help("Number of bytes of the raw binary files to write. By default, up to the end \
+                       of the file. Accepts K, M, G, KiB, MiB and GiB suffixes")
+                .long("length")
+                .value_name("bytes")
+                .takes_value(true))
+            .arg(Arg::with_name("byteswap")
+                .help("Swap the bytes of each group of 2 or 4 bytes of the Intel HEX and S-record \
+                       files, for images with big-endian values. The data of each segment must \
+                       be a multiple of the group size")
+                .long("byteswap")
+                .possible_values(&["2", "4"])
+                .takes_value(true))
+            .arg(Arg::with_name("repeat")
+                .help("Number of times to write each word, to consecutive addresses starting at \
+                       its address. By default, 1")
+                .long("repeat")
+                .value_name("N")
+                .takes_value(true))
+            .arg(Arg::with_name("merge_writes")
+                .help("Sort each run of consecutive word writes by address and coalesce contiguous \
+                       ones into a single transfer. The files and the rest of the writes keep \
+                       their order")
+                .long("merge-writes")
+                .conflicts_with("repeat"))
+            .arg(Arg::with_name("verify")
+                .help("Read back the written data and check that it matches")
+                .long("verify"))
+            .arg(Arg::with_name("no_verify")
+                .help("Do not read back the written data, even if the configuration file enables \
+                       it")
+                .long("no-verify")
+                .conflicts_with("verify"))
+            .arg(Arg::with_name("summary")
+                .help("Print a table with the address, the data and the result of each write \
+                       after all of them")
+                .long("summary")))
         .subcommand(SubCommand::with_name("exec")
             .about("Call function at the given address")
             .arg(Arg::with_name("addr")
                 .help("Memory address to call")
                 .takes_value(true)
-                .required(true)))
+                .required(true))
+            .arg(Arg::with_name("return_within")
+                .help("Time in which the called function must return to count as returned, in \
+                       milliseconds, up to 10000. It does not bound the wait: the call still \
+                       blocks until the function returns or the USB transfer fails after 10 s")
+                .long("return-within")
+                .value_name("ms")
+                .takes_value(true))
+            .arg(Arg::with_name("expect_no_return")
+                .help("Treat a function that does not return in time as a success, and one that \
+                       returns in time as a failure")
+                .long("expect-no-return")
+                .requires("return_within")
+                .conflicts_with("expect_return"))
+            .arg(Arg::with_name("expect_return")
+                .help("Treat a function that does not return in time as a failure")
+                .long("expect-return")
+                .requires("return_within"))
+            .arg(Arg::with_name("repeat")
+                .help("Number of times to call the function. With 0, it runs until Ctrl-C is \
+                       pressed")
+                .long("repeat")
+                .value_name("N")
+                .takes_value(true)
+                .default_value("1"))
+            .arg(Arg::with_name("arg0")
+                .help("Value of the `r0` register, the first argument of the function")
+                .long("arg0")
+                .value_name("VALUE")
+                .takes_value(true))
+            .arg(Arg::with_name("arg1")
+                .help("Value of the `r1` register, the second argument of the function")
+                .long("arg1")
+                .value_name("VALUE")
+                .takes_value(true))
+            .arg(Arg::with_name("arg2")
+                .help("Value of the `r2` register, the third argument of the function")
+                .long("arg2")
+                .value_name("VALUE")
+                .takes_value(true))
+            .arg(Arg::with_name("arg3")
+                .help("Value of the `r3` register, the fourth argument of the function")
+                .long("arg3")
+                .value_name("VALUE")
+                .takes_value(true))
+            .arg(Arg::with_name("wait_addr")
+                .help("Address of a 32-bit word to poll after the function returns, until it has \
+                       the value given with `--wait-value`")
+                .long("wait-addr")
+                .value_name("addr")
+                .requires("wait_value")
+                .takes_value(true))
+            .arg(Arg::with_name("wait_value")
+                .help("Value of the word at `--wait-addr` that marks the completion of the \
+                       function")
+                .long("wait-value")
+                .value_name("VALUE")
+                .requires("wait_addr")
+                .takes_value(true))
+            .arg(Arg::with_name("wait_timeout")
+                .help("Maximum time to wait for the word at `--wait-addr` to have the expected \
+                       value, in milliseconds. By default, it waits indefinitely")
+                .long("wait-timeout")
+                .value_name("ms")
+                .requires("wait_addr")
+                .takes_value(true)))
         .subcommand(SubCommand::with_name("reset64")
             .about("RMR request for AArch64 warm boot")
             .arg(Arg::with_name("addr").takes_value(true).required(true)))
-        .subcommand(SubCommand::with_name("version").about("Gets SoC version information"))
+        .subcommand(SubCommand::with_name("reset")
+            .about("Resets the SoC through its watchdog, or starts executing the given address in \
+                    AArch32 mode, with a warm RMR reset request if the SoC has an RVBAR register \
+                    or by jumping to it otherwise")
+            .arg(Arg::with_name("addr")
+                .help("The address to start executing in AArch32 mode. If not given, the SoC is \
+                       reset through its watchdog")
+                .takes_value(true)))
+        .subcommand(SubCommand::with_name("version")
+            .about("Gets SoC version information")
+            .arg(Arg::with_name("json")
+                .help("Print the version information in JSON format, with the `soc_id`, \
+                       `soc_name`, `protocol` and `scratchpad` fields. Unknown values are `null`")
+                .long("json")))
+        .subcommand(SubCommand::with_name("list")
+            .about("Lists the connected FEL devices")
+            .arg(Arg::with_name("json")
+                .help("Print the list of devices in JSON format")
+                .long("json")))
+        .subcommand(SubCommand::with_name("map")
+            .about("Probes the known memory regions of the SoC and prints which ones are \
+                    accessible"))
+        .subcommand(SubCommand::with_name("meminfo")
+            .about("Prints the SoC, its scratchpad address and, if the SPL initialized it and its \
+                    controller is known, the base address and size of DRAM"))
+        .subcommand(SubCommand::with_name("crc32")
+            .about("Computes the CRC-32 of a memory region")
+            .arg(Arg::with_name("addr")
+                .help("The start address of the memory region")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("size")
+                .help("The size of the memory region, in bytes. Accepts K, M, G, KiB, MiB and GiB \
+                       suffixes")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("expected")
+                .help("Fail if the computed CRC-32 is different from the given one")
+                .long("expected")
+                .value_name("crc")
+                .takes_value(true)))
+        .subcommand(SubCommand::with_name("hash")
+            .about("Computes the hash of a memory region")
+            .arg(Arg::with_name("addr")
+                .help("The start address of the memory region")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("size")
+                .help("The size of the memory region, in bytes. Accepts K, M, G, KiB, MiB and GiB \
+                       suffixes")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("algorithm")
+                .help("The hash algorithm to use. `crc16` is the CRC-16/CCITT of legacy \
+                       programmers, printed as 4 hexadecimal digits")
+                .long("algorithm")
+                .visible_alias("algo")
+                .possible_values(&["sha256", "sha1", "md5", "crc16"])
+                .default_value("sha256")))
+        .subcommand(SubCommand::with_name("compare")
+            .about("Compares two memory regions")
+            .arg(Arg::with_name("addr_a")
+                .help("The start address of the first memory region")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("addr_b")
+                .help("The start address of the second memory region")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("size")
+                .help("The size of the memory regions, in bytes. Accepts K, M, G, KiB, MiB and \
+                       GiB suffixes")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("diff_format")
+                .help("How the first differences are printed: `offsets` lists the offset and the \
+                       bytes of each differing byte, `unified` prints the differing lines of the \
+                       first region removed and those of the second one added, as in a unified \
+                       diff, and `hexdump` prints the differing lines of both regions side by side \
+                       in hexadecimal")
+                .long("diff-format")
+                .takes_value(true)
+                .possible_values(&["offsets", "unified", "hexdump"])
+                .default_value("offsets"))
+            .arg(Arg::with_name("hex")
+                .help("Same as `--diff-format hexdump`")
+                .long("hex")
+                .conflicts_with("diff_format")))
+        .subcommand(SubCommand::with_name("copy")
+            .about("Copies a memory region to another address")
+            .arg(Arg::with_name("src")
+                .help("The start address of the source memory region")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("dst")
+                .help("The start address of the destination memory region")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("size")
+                .help("The number of bytes to copy. Accepts K, M, G, KiB, MiB and GiB suffixes")
+                .takes_value(true)
+                .required(true)))
+        .subcommand(SubCommand::with_name("watch")
+            .about("Polls a 32-bit word in memory and prints it when it changes, until Ctrl-C is \
+                    pressed. With `--json`, each value is printed as a JSON object with the \
+                    milliseconds since the start, the address and the value")
+            .arg(Arg::with_name("addr")
+                .help("The memory address to watch")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("interval")
+                .help("Time between reads, in milliseconds")
+                .long("interval")
+                .value_name("ms")
+                .takes_value(true)
+                .default_value("1000"))
+            .arg(Arg::with_name("count")
+                .help("Number of reads before stopping. With 0, it runs until Ctrl-C is pressed")
+                .long("count")
+                .value_name("N")
+                .takes_value(true)
+                .default_value("0"))
+            .arg(Arg::with_name("all")
+                .help("Print the value in every read, even if it did not change")
+                .long("all"))
+            .arg(Arg::with_name("record")
+                .help("Append a timestamped record of each printed value to the given file, to \
+                       print them later with `replay`")
+                .long("record")
+                .value_name("FILE")
+                .takes_value(true)))
+        .subcommand(SubCommand::with_name("replay")
+            .about("Prints the values recorded by `watch --record`, in order")
+            .arg(Arg::with_name("file")
+                .help("The file with the records")
+                .takes_value(true)
+                .required(true)))
         .subcommand(SubCommand::with_name("clear")
             .about("Clear memory")
             .arg(Arg::with_name("addr")
@@ -75,21 +611,63 @@ pub fn generate() -> App<'static, 'static> {
                 .takes_value(true)
                 .required(true))
             .arg(Arg::with_name("num_bytes")
-                .help("Number of bytes to clear. By default, a 32-bit word (4 bytes)")
+                .help("Number of bytes to clear. By default, a 32-bit word (4 bytes). Accepts K, \
+                       M, G, KiB, MiB and GiB suffixes")
                 .takes_value(true)
-                .default_value("4")))
+                .default_value("4"))
+            .arg(Arg::with_name("verify")
+                .help("Read back the cleared memory and check that every byte matches")
+                .long("verify")))
         .subcommand(SubCommand::with_name("fill")
-            .about("Fill memory with the given byte")
+            .about("Fill memory with the given byte or repeating pattern")
             .arg(Arg::with_name("addr")
                 .help("Memory address to fill")
                 .takes_value(true)
                 .required(true))
             .arg(Arg::with_name("num_bytes")
-                .help("Number of bytes to fill. By default, a 32-bit word (4 bytes)")
+                .help("Number of bytes to fill. By default, a 32-bit word (4 bytes). Accepts K, M, \
+                       G, KiB, MiB and GiB suffixes")
                 .takes_value(true)
                 .default_value("4"))
             .arg(Arg::with_name("fill_byte")
                 .help("Byte to use to fill the memory")
                 .takes_value(true)
+                .required_unless("pattern"))
+            .arg(Arg::with_name("pattern")
+                .help("Pattern of bytes to repeat in the memory instead of a single byte, in \
+                       hexadecimal (such as `deadbeef`). The last repetition is truncated if the \
+                       number of bytes is not a multiple of its length")
+                .long("pattern")
+                .value_name("HEX")
+                .takes_value(true)
+                .conflicts_with("fill_byte"))
+            .arg(Arg::with_name("verify")
+                .help("Read back the filled memory and check that every byte matches")
+                .long("verify")))
+        .subcommand(SubCommand::with_name("memtest")
+            .about("Test a memory region by writing a pattern and reading it back. Note: the \
+                    contents of the region are overwritten")
+            .arg(Arg::with_name("addr")
+                .help("Start address of the region to test")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("size")
+                .help("Number of bytes to test, a multiple of 4. Accepts K, M, G, KiB, MiB and GiB \
+                       suffixes")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("pattern")
+                .help("Pattern to write: a walking one bit in each word, the address of each word \
+                       or pseudo-random words. By default, `address`")
+                .long("pattern")
+                .possible_values(&["walking", "address", "random"])
+                .takes_value(true)))
+        .subcommand(SubCommand::with_name("completions")
+            .about("Prints the completion script of the given shell")
+            .setting(AppSettings::Hidden)
+            .arg(Arg::with_name("shell")
+                .help("Shell to generate the completion script for")
+                .possible_values(&["bash", "zsh", "fish", "powershell"])
+                .takes_value(true)
                 .required(true)))
 }
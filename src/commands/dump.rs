@@ -0,0 +1,502 @@
+//! `dump` command, which reads the memory, the SID or a memory region of the device.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use aw_fel::FelHandle;
+use failure::{bail, format_err, Error, ResultExt};
+use flate2::write::GzEncoder;
+use log::debug;
+use xz2::write::XzEncoder;
+
+use super::{
+    le_bytes, resolve_address,
+    transfer::{read_memory, stream_memory},
+    Reporter, Session, SRAM_REGIONS,
+};
+use crate::{
+    carray,
+    config::{ColorMode, Command, Compression, DataFormat, MemoryRegion, SidFormat},
+    ihex,
+    output::{hex_dump, HexFormat, Output},
+    srec,
+};
+
+/// XZ compression preset level.
+const XZ_PRESET: u32 = 6;
+
+/// Executes the `dump` command.
+pub(super) fn execute(session: &Session, command: &Command) -> Result<(), Error> {
+    match *command {
+        Command::Dump {
+            count,
+            interval_ms,
+            ref out,
+            compress,
+            hex,
+            format,
+            size,
+            region,
+            ..
+        } if count > 1 => {
+            // Raw binary dumps in stdout are concatenated, since separators would corrupt them.
+            let raw = !hex && format == DataFormat::Binary && (size.is_some() || region.is_some());
+            let separated = out.is_none() && !raw;
+            for i in 0..count {
+                if i > 0 {
+                    thread::sleep(Duration::from_millis(interval_ms));
+                }
+                let mut single = command.clone();
+                if let Command::Dump {
+                    count: ref mut single_count,
+                    out: ref mut single_out,
+                    ..
+                } = single
+                {
+                    *single_count = 1;
+                    *single_out = out.as_ref().map(|out| numbered_path(out, i, compress));
+                }
+                if separated {
+                    session.report(Output::DumpHeader {
+                        number: i + 1,
+                        count,
+                    })?;
+                }
+                execute(session, &single)?;
+            }
+            Ok(())
+        }
+        Command::Dump {
+            address,
+            size,
+            words,
+            endian,
+            hex,
+            width,
+            color,
+            sid,
+            sid_format,
+            ref out,
+            format,
+            ref array_name,
+            append,
+            compress,
+            align_output,
+            pad_byte,
+            read_retries,
+            partial_ok,
+            region,
+            ..
+        } => {
+            let device = session.device;
+            let hex_fmt = session.hex_fmt;
+            let (address, size) = match region {
+                Some(region) => {
+                    let (address, size) = find_memory_region(device, region)?;
+                    (Some(address), Some(size))
+                }
+                None => match address {
+                    Some(address) => {
+                        let len = size.map_or(4 * u64::from(words), u64::from);
+                        (Some(resolve_address(device, address, len)?), size)
+                    }
+                    None => (None, size),
+                },
+            };
+            if sid {
+                let to_stdout = out.is_none();
+                let sid = match device.read_sid().context("unable to get SID from device")? {
+                    Some(sid) => sid,
+                    None => bail!("the device does not have SID registers"),
+                };
+                if sid_format == SidFormat::Raw {
+                    let mut bytes = Vec::with_capacity(16);
+                    for word in &sid {
+                        bytes.extend_from_slice(&le_bytes(*word));
+                    }
+                    let write_context = if to_stdout {
+                        "unable to write the SID to stdout"
+                    } else {
+                        "unable to write the SID to file"
+                    };
+                    let mut output = DumpOutput::create(
+                        &session.reporter,
+                        out.as_ref().map(PathBuf::as_path),
+                        compress,
+                        append,
+                    )?;
+                    output.write_all(&bytes).context(write_context)?;
+                    output.finish().context(write_context)?;
+                } else {
+                    session.report(Output::Sid {
+                        sid,
+                        format: sid_format,
+                    })?;
+                }
+            } else if size.is_some() {
+                let (address, size) = (address.unwrap(), size.unwrap());
+                let show_progress = session.options.show_progress;
+                let write_context = if out.is_some() {
+                    "unable to write dumped data to file"
+                } else {
+                    "unable to write dumped data to stdout"
+                };
+                if !hex && format == DataFormat::Binary {
+                    // Streamed, so that big dumps don't need a buffer of their size.
+                    let mut output = DumpOutput::create(
+                        &session.reporter,
+                        out.as_ref().map(PathBuf::as_path),
+                        compress,
+                        append,
+                    )?;
+                    stream_memory(
+                        session,
+                        address,
+                        size,
+                        read_retries,
+                        partial_ok,
+                        show_progress,
+                        |chunk| Ok(output.write_all(chunk).context(write_context)?),
+                    )?;
+                    if let Some(align) = align_output {
+                        let padded_size = aligned_size(size as usize, align);
+                        let padding = (padded_size - size as usize) as u64;
+                        let _ = io::copy(&mut io::repeat(pad_byte).take(padding), &mut output)
+                            .context(write_context)?;
+                        session.confirmation(&format!(
+                            "Dumped {} bytes, padded to {} bytes",
+                            size, padded_size
+                        ))?;
+                    }
+                    output.finish().context(write_context)?;
+                } else {
+                    let mut result = vec![0_u8; size as usize];
+                    read_memory(
+                        session,
+                        address,
+                        &mut result,
+                        read_retries,
+                        partial_ok,
+                        show_progress,
+                    )?;
+                    if hex {
+                        match *out {
+                            Some(ref out) => {
+                                let mut output = DumpOutput::create(
+                                    &session.reporter,
+                                    Some(out),
+                                    compress,
+                                    append,
+                                )?;
+                                let color = color == ColorMode::Always;
+                                hex_dump(&mut output, &result, address, width, color, hex_fmt)
+                                    .context(write_context)?;
+                                output.finish().context(write_context)?;
+                            }
+                            None => session
+                                .report(Output::HexDump {
+                                    address,
+                                    data: result,
+                                    width,
+                                    color,
+                                })
+                                .context(write_context)?,
+                        }
+                    } else {
+                        if let Some(align) = align_output {
+                            let padded_size = pad_to_alignment(&mut result, align, pad_byte);
+                            session.confirmation(&format!(
+                                "Dumped {} bytes, padded to {} bytes",
+                                size, padded_size
+                            ))?;
+                        }
+                        let mut records = Vec::new();
+                        match format {
+                            DataFormat::IntelHex => ihex::write(&mut records, address, &result)
+                                .context("unable to encode dumped data as Intel HEX")?,
+                            DataFormat::CArray => carray::write(&mut records, array_name, &result)
+                                .context("unable to encode dumped data as a C array")?,
+                            _ => srec::write(&mut records, address, &result)
+                                .context("unable to encode dumped data as S-records")?,
+                        }
+                        let mut output = DumpOutput::create(
+                            &session.reporter,
+                            out.as_ref().map(PathBuf::as_path),
+                            compress,
+                            append,
+                        )?;
+                        output.write_all(&records).context(write_context)?;
+                        output.finish().context(write_context)?;
+                    }
+                }
+            } else {
+                let addr = address.unwrap();
+                let mut val = vec![0_u32; words as usize];
+                debug!("read_words of {} words at {:#010x}", words, addr);
+                session
+                    .retry_usb(|| device.read_words(addr, &mut val))
+                    .context(format!("unable to read {:#010x} address", addr))?;
+                for word in &mut val {
+                    *word = endian.apply(*word);
+                }
+                if let Some(ref out_path) = *out {
+                    let mut data = Vec::with_capacity(val.len() * 4);
+                    for word in &val {
+                        data.extend_from_slice(&le_bytes(*word));
+                    }
+                    if let Some(align) = align_output {
+                        let padded_size = pad_to_alignment(&mut data, align, pad_byte);
+                        session.confirmation(&format!(
+                            "Dumped {} words, padded to {} bytes",
+                            words, padded_size
+                        ))?;
+                    }
+                    let mut output =
+                        DumpOutput::create(&session.reporter, Some(out_path), compress, append)?;
+                    output
+                        .write_all(&data)
+                        .context("unable to write dumped words to file")?;
+                    output
+                        .finish()
+                        .context("unable to write dumped words to file")?;
+                } else {
+                    session.report(Output::Words {
+                        address: addr,
+                        words: val,
+                    })?;
+                }
+            }
+            Ok(())
+        }
+        _ => unreachable!("not a dump command"),
+    }
+}
+
+/// Describes what the `dump` command would do.
+pub(super) fn describe(command: &Command, hex_fmt: HexFormat) -> Vec<String> {
+    let (address, size, region, words, sid, out, append, count, interval_ms) = match *command {
+        Command::Dump {
+            address,
+            size,
+            region,
+            words,
+            sid,
+            ref out,
+            append,
+            count,
+            interval_ms,
+            ..
+        } => (
+            address,
+            size,
+            region,
+            words,
+            sid,
+            out,
+            append,
+            count,
+            interval_ms,
+        ),
+        _ => unreachable!("not a dump command"),
+    };
+    let mut steps = Vec::new();
+    let action = if append { "append" } else { "write" };
+    match (address, size) {
+        _ if sid => steps.push(match *out {
+            Some(ref out) => format!(
+                "Would read the SID registers and {} them to '{}'",
+                action,
+                out.display()
+            ),
+            None => "Would read the SID registers".to_owned(),
+        }),
+        _ if region.is_some() => steps.push(match *out {
+            Some(ref out) => format!(
+                "Would read the `{}` region of the SoC and {} it to '{}'",
+                region.unwrap().name(),
+                action,
+                out.display()
+            ),
+            None => format!(
+                "Would read the `{}` region of the SoC",
+                region.unwrap().name()
+            ),
+        }),
+        (Some(address), Some(size)) => steps.push(match *out {
+            Some(ref out) => format!(
+                "Would read {} bytes at {} and {} them to '{}'",
+                size,
+                hex_fmt.address(address),
+                action,
+                out.display()
+            ),
+            None => format!("Would read {} bytes at {}", size, hex_fmt.address(address)),
+        }),
+        (Some(address), None) if out.is_some() => steps.push(format!(
+            "Would read {} words at {} and {} them to '{}'",
+            words,
+            hex_fmt.address(address),
+            action,
+            out.as_ref().unwrap().display()
+        )),
+        (Some(address), None) if words == 1 => steps.push(format!(
+            "Would read the word at {}",
+            hex_fmt.address(address)
+        )),
+        (Some(address), None) => steps.push(format!(
+            "Would read {} words at {}",
+            words,
+            hex_fmt.address(address)
+        )),
+        (None, _) => {}
+    }
+    if count > 1 {
+        steps.push(format!(
+            "Would repeat the dump {} times, {} ms apart{}",
+            count,
+            interval_ms,
+            if out.is_some() {
+                ", numbering the output files"
+            } else {
+                ""
+            }
+        ));
+    }
+    steps
+}
+
+/// Finds the base address and size of the given memory region of the device SoC.
+fn find_memory_region(device: &FelHandle, region: MemoryRegion) -> Result<(u32, u32), Error> {
+    let soc_id = device.get_version_info().get_id();
+    let regions = SRAM_REGIONS.iter().find(|&&(id, _, _)| id == soc_id);
+    let found = match (regions, region) {
+        (Some(&(_, sram_a1, _)), MemoryRegion::Sram)
+        | (Some(&(_, sram_a1, _)), MemoryRegion::SramA1) => Some(sram_a1),
+        (Some(&(_, _, sram_c)), MemoryRegion::SramC) => sram_c,
+        (None, _) => None,
+    };
+    found.ok_or_else(|| {
+        format_err!(
+            "the `{}` region of the SoC {:#06x} is not known, give the address and size to dump",
+            region.name(),
+            soc_id
+        )
+    })
+}
+
+/// Pads the given data with the given byte up to a multiple of the alignment.
+///
+/// Returns the padded size.
+fn pad_to_alignment(data: &mut Vec<u8>, align: u32, pad_byte: u8) -> usize {
+    let padded_size = aligned_size(data.len(), align);
+    data.resize(padded_size, pad_byte);
+    padded_size
+}
+
+/// Rounds the given size up to a multiple of the alignment.
+fn aligned_size(size: usize, align: u32) -> usize {
+    let align = align as usize;
+    (size + align - 1) / align * align
+}
+
+/// Gets the path of the output file of one of several dumps, with its index as extension, before
+/// the extension of the compression, if any.
+fn numbered_path(path: &Path, index: u32, compress: Option<Compression>) -> PathBuf {
+    let mut numbered = match compress {
+        Some(_) => path.with_extension("").into_os_string(),
+        None => path.as_os_str().to_owned(),
+    };
+    numbered.push(format!(".{}", index));
+    if let Some(compress) = compress {
+        numbered.push(format!(".{}", compress.extension()));
+    }
+    PathBuf::from(numbered)
+}
+
+/// Destination of the dumped data, compressing it if requested.
+enum DumpOutput<'a, 'r: 'a> {
+    /// Standard output, through the reporter of the command output.
+    Stdout(&'a Reporter<'r>),
+    /// Uncompressed output file.
+    File(BufWriter<File>),
+    /// Gzip compressed output file.
+    Gzip(GzEncoder<BufWriter<File>>),
+    /// XZ compressed output file.
+    Xz(XzEncoder<BufWriter<File>>),
+}
+
+impl<'a, 'r> DumpOutput<'a, 'r> {
+    /// Creates the output file, or opens it to append to it, or reports the data as output if
+    /// there is none.
+    ///
+    /// Compressed data is appended as a new gzip member or XZ stream, which decompressors
+    /// concatenate.
+    fn create(
+        reporter: &'a Reporter<'r>,
+        path: Option<&Path>,
+        compress: Option<Compression>,
+        append: bool,
+    ) -> Result<Self, Error> {
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(DumpOutput::Stdout(reporter)),
+        };
+        let file = if append {
+            OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(path)
+                .context("unable to open output file to append to it")?
+        } else {
+            File::create(path).context("unable to create output file")?
+        };
+        let file = BufWriter::new(file);
+        Ok(match compress {
+            None => DumpOutput::File(file),
+            Some(Compression::Gzip) => {
+                DumpOutput::Gzip(GzEncoder::new(file, flate2::Compression::default()))
+            }
+            Some(Compression::Xz) => DumpOutput::Xz(XzEncoder::new(file, XZ_PRESET)),
+        })
+    }
+
+    /// Finishes the compression, if any, and flushes the output.
+    fn finish(self) -> io::Result<()> {
+        match self {
+            DumpOutput::Stdout(_) => Ok(()),
+            DumpOutput::File(mut file) => file.flush(),
+            DumpOutput::Gzip(encoder) => encoder.finish()?.flush(),
+            DumpOutput::Xz(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+impl<'a, 'r> Write for DumpOutput<'a, 'r> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            DumpOutput::Stdout(reporter) => {
+                reporter
+                    .report(Output::Data(buf.to_vec()))
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e.compat()))?;
+                Ok(buf.len())
+            }
+            DumpOutput::File(ref mut file) => file.write(buf),
+            DumpOutput::Gzip(ref mut encoder) => encoder.write(buf),
+            DumpOutput::Xz(ref mut encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            DumpOutput::Stdout(_) => Ok(()),
+            DumpOutput::File(ref mut file) => file.flush(),
+            DumpOutput::Gzip(ref mut encoder) => encoder.flush(),
+            DumpOutput::Xz(ref mut encoder) => encoder.flush(),
+        }
+    }
+}
@@ -0,0 +1,350 @@
+//! `exec` command, which calls code in the memory of the device.
+
+use std::{
+    sync::atomic::Ordering,
+    thread,
+    time::{Duration, Instant},
+};
+
+use aw_fel::FelHandle;
+use failure::{bail, Error, ResultExt};
+use log::debug;
+
+use super::{resolve_address, resolve_entry_point, scratch, Session};
+use crate::{
+    config::{Command, ExecExpectation},
+    output::HexFormat,
+};
+
+/// ARM instructions of the trampoline that sets the arguments of `exec`.
+///
+/// Each one is an `ldr rN, [pc, #12]`, loading the word 20 bytes after itself, so the `r0` to `r3`
+/// values and the address of the code go right after them. Loading `pc` keeps the FEL return
+/// address in `lr`.
+const EXEC_TRAMPOLINE: [u32; 5] = [
+    0xE59F_000C,
+    0xE59F_100C,
+    0xE59F_200C,
+    0xE59F_300C,
+    0xE59F_F00C,
+];
+/// ARM `mov r0, r0` instruction, replacing the load of the registers without a value.
+const ARM_NOP: u32 = 0xE1A0_0000;
+/// Time between reads of the completion word with `exec --wait-addr`, in milliseconds.
+const EXEC_WAIT_POLL_INTERVAL_MS: u64 = 10;
+
+/// Executes the `exec` command.
+pub(super) fn execute(session: &Session, command: &Command) -> Result<(), Error> {
+    match *command {
+        Command::Execute {
+            address,
+            return_within_ms,
+            expect,
+            repeat,
+            args,
+            wait,
+            wait_timeout_ms,
+        } => {
+            let device = session.device;
+            let hex_fmt = session.hex_fmt;
+            let address = resolve_entry_point(device, address)?;
+            let wait = match wait {
+                Some((wait_addr, wait_value)) => {
+                    Some((resolve_address(device, wait_addr, 4)?, wait_value))
+                }
+                None => None,
+            };
+            let entry = if args.iter().any(Option::is_some) {
+                write_exec_trampoline(session, address, &args, wait.map(|(addr, _)| addr))?
+            } else {
+                address
+            };
+            // A previous device might have been interrupted.
+            let interrupted = &session.options.interrupted;
+            interrupted.store(false, Ordering::SeqCst);
+            let mut calls = 0;
+            while !interrupted.load(Ordering::SeqCst) {
+                calls += 1;
+                if repeat != 1 {
+                    session.status(&format!("Call {}", calls))?;
+                }
+                let returned = execute_once(session, address, entry, return_within_ms, expect)?;
+                if let (true, Some((wait_addr, wait_value))) = (returned, wait) {
+                    let waited = wait_for_word(session, wait_addr, wait_value, wait_timeout_ms)?;
+                    session.confirmation(&format!(
+                        "The word at {} was {} after waiting {}.{:03}s",
+                        hex_fmt.word(wait_addr),
+                        hex_fmt.word(wait_value),
+                        waited.as_secs(),
+                        waited.subsec_millis()
+                    ))?;
+                }
+                if !returned || calls == repeat {
+                    break;
+                }
+            }
+            if repeat != 1 {
+                session.confirmation(&format!(
+                    "Called the code at address {} {} times",
+                    hex_fmt.word(address),
+                    calls
+                ))?;
+            }
+            Ok(())
+        }
+        _ => unreachable!("not an exec command"),
+    }
+}
+
+/// Describes what the `exec` command would do.
+pub(super) fn describe(command: &Command, hex_fmt: HexFormat) -> Vec<String> {
+    let (address, repeat, args, wait) = match *command {
+        Command::Execute {
+            address,
+            repeat,
+            args,
+            wait,
+            ..
+        } => (address, repeat, args, wait),
+        _ => unreachable!("not an exec command"),
+    };
+    let mut steps = Vec::new();
+    let registers = args
+        .iter()
+        .enumerate()
+        .filter_map(|(i, arg)| arg.map(|value| format!("r{} = {}", i, hex_fmt.word(value))))
+        .collect::<Vec<_>>();
+    let with = if registers.is_empty() {
+        String::new()
+    } else {
+        format!(" with {}", registers.join(", "))
+    };
+    steps.push(match repeat {
+        1 => format!(
+            "Would call the code at address {}{}",
+            hex_fmt.address(address),
+            with
+        ),
+        0 => format!(
+            "Would call the code at address {}{} until interrupted",
+            hex_fmt.address(address),
+            with
+        ),
+        _ => format!(
+            "Would call the code at address {}{} {} times",
+            hex_fmt.address(address),
+            with,
+            repeat
+        ),
+    });
+    if let Some((wait_addr, wait_value)) = wait {
+        steps.push(format!(
+            "Would wait after {} until the word at {} is {}",
+            if repeat == 1 { "the call" } else { "each call" },
+            hex_fmt.address(wait_addr),
+            hex_fmt.word(wait_value)
+        ));
+    }
+    steps
+}
+
+/// Writes a trampoline to a scratch buffer of the device that loads the given arguments in the
+/// `r0` to `r3` registers and jumps to the code at the given address.
+///
+/// The buffer is allocated below the code, which might take the memory after its address, and away
+/// from the completion word of `--wait-addr`, if any. Registers without a value keep the one they
+/// had. Returns the address of the trampoline, to be called instead of the code.
+fn write_exec_trampoline(
+    session: &Session,
+    address: u32,
+    args: &[Option<u32>; 4],
+    wait_addr: Option<u32>,
+) -> Result<u32, Error> {
+    let mut words = Vec::with_capacity(2 * EXEC_TRAMPOLINE.len());
+    for (i, arg) in args.iter().enumerate() {
+        words.push(if arg.is_some() {
+            EXEC_TRAMPOLINE[i]
+        } else {
+            ARM_NOP
+        });
+    }
+    words.push(EXEC_TRAMPOLINE[4]);
+    words.extend(args.iter().map(|arg| arg.unwrap_or(0)));
+    words.push(address);
+    let mut avoid = Vec::with_capacity(2);
+    avoid.push(u64::from(address)..u64::from(u32::max_value()) + 1);
+    if let Some(wait_addr) = wait_addr {
+        avoid.push(u64::from(wait_addr)..u64::from(wait_addr) + 4);
+    }
+    let (trampoline, _) = scratch::allocate(session, 4 * words.len() as u32, &avoid)
+        .context("unable to allocate the argument trampoline")?;
+    debug!("argument trampoline at {:#010x}: {:x?}", trampoline, words);
+    session
+        .device
+        .write_words(trampoline, &words)
+        .context("unable to write the argument trampoline to the scratch buffer")?;
+    Ok(trampoline)
+}
+
+/// Outcome of a call to the code in the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExecOutcome {
+    /// The code returned in time.
+    Returned,
+    /// The code did not return in time, but it might have returned later.
+    Late,
+    /// The device stopped answering FEL requests, as expected.
+    StoppedResponding,
+}
+
+/// Calls the code at the given address once, through the given entry point, checking the expected
+/// outcome.
+///
+/// Returns whether the code returned in time, which is required to keep using the device.
+fn execute_once(
+    session: &Session,
+    address: u32,
+    entry: u32,
+    return_within_ms: Option<u64>,
+    expect: Option<ExecExpectation>,
+) -> Result<bool, Error> {
+    let hex_fmt = session.hex_fmt;
+    debug!("fel_execute at {:#010x}", entry);
+    let returned = match return_within_ms {
+        Some(within_ms) => execute_timed(session.device, entry, within_ms),
+        None => session.device.fel_execute(entry).map(|()| true),
+    };
+    match exec_outcome(address, return_within_ms, returned, expect)? {
+        ExecOutcome::Returned => Ok(true),
+        ExecOutcome::Late => {
+            session.confirmation(&format!(
+                "The code at address {} did not return within {} ms",
+                hex_fmt.word(address),
+                return_within_ms.unwrap()
+            ))?;
+            Ok(false)
+        }
+        ExecOutcome::StoppedResponding => {
+            session.confirmation(&format!(
+                "The device stopped responding after calling the code at address {}",
+                hex_fmt.word(address)
+            ))?;
+            Ok(false)
+        }
+    }
+}
+
+/// Checks the result of a call to the code at the given address against the expected outcome.
+///
+/// The result is whether the code returned within the given time, or the error of the call.
+fn exec_outcome(
+    address: u32,
+    return_within_ms: Option<u64>,
+    returned: Result<bool, Error>,
+    expect: Option<ExecExpectation>,
+) -> Result<ExecOutcome, Error> {
+    match (returned, expect) {
+        (Ok(true), Some(ExecExpectation::NoReturn)) => bail!(
+            "the code at address {:#010x} returned, but it was expected not to",
+            address
+        ),
+        (Ok(false), Some(ExecExpectation::Return)) => bail!(
+            "the code at address {:#010x} did not return within {} ms",
+            address,
+            return_within_ms.unwrap()
+        ),
+        (Ok(false), _) => Ok(ExecOutcome::Late),
+        // The device stops answering FEL requests once the payload takes over.
+        (Err(_), Some(ExecExpectation::NoReturn)) => Ok(ExecOutcome::StoppedResponding),
+        (Err(e), _) => Err(e
+            .context(format!(
+                "unable to execute code at address {:#010x}",
+                address
+            ))
+            .into()),
+        (Ok(true), _) => Ok(ExecOutcome::Returned),
+    }
+}
+
+/// Calls the code at the given address, checking if it returns within the given milliseconds.
+///
+/// Returns whether the code returned in time. The time does not bound the wait: the USB transfer
+/// can't be abandoned, since the handle is borrowed from the FEL context, so this blocks until the
+/// code returns or `aw-fel` gives up on the transfer, after 10 seconds.
+fn execute_timed(device: &FelHandle, address: u32, within_ms: u64) -> Result<bool, Error> {
+    let start = Instant::now();
+    device.fel_execute(address)?;
+    Ok(start.elapsed() <= Duration::from_millis(within_ms))
+}
+
+/// Polls the word at the given address until it has the expected value, for at most the given
+/// timeout in milliseconds, if any.
+///
+/// Returns how long it waited.
+fn wait_for_word(
+    session: &Session,
+    address: u32,
+    value: u32,
+    timeout_ms: Option<u64>,
+) -> Result<Duration, Error> {
+    let start = Instant::now();
+    let timeout = timeout_ms.map(Duration::from_millis);
+    loop {
+        let mut val = [0_u32];
+        debug!("read_words of 1 word at {:#010x}", address);
+        session
+            .retry_usb(|| session.device.read_words(address, &mut val))
+            .context(format!("unable to read {:#010x} address", address))?;
+        if val[0] == value {
+            return Ok(start.elapsed());
+        }
+        if timeout.map_or(false, |timeout| start.elapsed() >= timeout) {
+            bail!(
+                "the word at {:#010x} was not {:#010x} within {} ms, the last value was {:#010x}",
+                address,
+                value,
+                timeout_ms.unwrap(),
+                val[0]
+            );
+        }
+        thread::sleep(Duration::from_millis(EXEC_WAIT_POLL_INTERVAL_MS));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use failure::format_err;
+
+    use super::{exec_outcome, ExecOutcome};
+    use crate::config::ExecExpectation;
+
+    #[test]
+    fn exec_outcomes() {
+        let outcome = |returned, expect| {
+            let returned = match returned {
+                Some(returned) => Ok(returned),
+                None => Err(format_err!("the USB transfer timed out")),
+            };
+            exec_outcome(0x2000, Some(500), returned, expect).ok()
+        };
+        let (returns, no_return) = (
+            Some(ExecExpectation::Return),
+            Some(ExecExpectation::NoReturn),
+        );
+
+        assert_eq!(outcome(Some(true), None), Some(ExecOutcome::Returned));
+        assert_eq!(outcome(Some(true), returns), Some(ExecOutcome::Returned));
+        assert_eq!(outcome(Some(true), no_return), None);
+
+        assert_eq!(outcome(Some(false), None), Some(ExecOutcome::Late));
+        assert_eq!(outcome(Some(false), returns), None);
+        assert_eq!(outcome(Some(false), no_return), Some(ExecOutcome::Late));
+
+        assert_eq!(outcome(None, None), None);
+        assert_eq!(outcome(None, returns), None);
+        assert_eq!(
+            outcome(None, no_return),
+            Some(ExecOutcome::StoppedResponding)
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! `version`, `map` and `meminfo` commands, which get information of the SoC of the device.
+
+use aw_fel::FelHandle;
+use failure::{Error, ResultExt};
+use log::debug;
+
+use super::{Session, DRAM_BASE};
+use crate::{
+    config::Command,
+    output::{DramInfo, Output, VersionInfo},
+};
+
+/// Clock control unit base address, common to all supported SoCs.
+const CCU_BASE: u32 = 0x01C2_0000;
+/// GPIO controller base address, common to all supported SoCs.
+const PIO_BASE: u32 = 0x01C2_0800;
+/// DRAM controllers of the known SoC IDs.
+const DRAM_CONTROLLERS: &[(u32, DramController)] = &[
+    (0x1623, DramController::Sun4i),
+    (0x1625, DramController::Sun4i),
+    (0x1651, DramController::Sun4i),
+    (0x1680, DramController::Sun8i),
+    (0x1689, DramController::Sun8i),
+    (0x1718, DramController::Sun8i),
+];
+/// DRAM configuration register (`DCR`) of the A10, A13 and A20 DRAM controller.
+const SUN4I_DRAMC_DCR: u32 = 0x01C0_1004;
+/// Configuration register (`MCTL_COM_CR`) of the H3, H5 and A64 DRAM controller.
+const SUN8I_MCTL_COM_CR: u32 = 0x01C6_2000;
+
+/// Executes the `version`, `map` or `meminfo` command.
+pub(super) fn execute(session: &Session, command: &Command) -> Result<(), Error> {
+    let device = session.device;
+    match *command {
+        Command::Version { .. } => {
+            session.report(Output::Version(VersionInfo::from_device(device)))
+        }
+        Command::MemInfo => {
+            let version = VersionInfo::from_device(device);
+            let dram = dram_info(session, version.get_soc_id())?;
+            session.report(Output::MemInfo { version, dram })
+        }
+        Command::Map => {
+            session.report(Output::MapHeader)?;
+            for (name, base) in memory_map_candidates(device) {
+                let mut probe = [0_u8; 4];
+                let accessible = device.fel_read(base, &mut probe).is_ok();
+                session.report(Output::MapRegion {
+                    name,
+                    base,
+                    accessible,
+                })?;
+            }
+            Ok(())
+        }
+        _ => unreachable!("not an information command"),
+    }
+}
+
+/// Describes what the `version`, `map` or `meminfo` command would do.
+pub(super) fn describe(command: &Command) -> Vec<String> {
+    vec![match *command {
+        Command::Version { .. } => "Would get the SoC version information",
+        Command::Map => "Would probe the known memory regions of the SoC",
+        Command::MemInfo => "Would read the memory information of the SoC",
+        _ => unreachable!("not an information command"),
+    }
+    .to_owned()]
+}
+
+/// Gets the memory regions of the device SoC to probe, with their base addresses.
+///
+/// *DRAM* goes last, since accessing it before it has been initialized can stall the device.
+fn memory_map_candidates(device: &FelHandle) -> Vec<(&'static str, u32)> {
+    let info = device.get_soc_info();
+    let mut candidates = vec![
+        ("SRAM (SPL)", info.get_spl_addr()),
+        ("FEL scratch", info.get_scratch_addr()),
+        ("Thunk", info.get_thunk_addr()),
+    ];
+    if let Some(mmu_tt_addr) = info.get_mmu_tt_addr() {
+        candidates.push(("MMU TT", mmu_tt_addr));
+    }
+    candidates.push(("CCU", CCU_BASE));
+    candidates.push(("PIO", PIO_BASE));
+    if let Some(sid_addr) = info.get_sid_addr() {
+        candidates.push(("SID", sid_addr));
+    }
+    if let Some(rvbar_reg) = info.get_rvbar_reg() {
+        candidates.push(("RVBAR", rvbar_reg));
+    }
+    candidates.push(("DRAM", DRAM_BASE));
+    candidates
+}
+
+/// DRAM controller of an SoC, with the layout of its configuration register.
+#[derive(Debug, Clone, Copy)]
+enum DramController {
+    /// Controller of the A10, A13 and A20.
+    Sun4i,
+    /// Controller of the H3, H5 and A64.
+    Sun8i,
+}
+
+/// Gets the DRAM information of the SoC with the given ID from the configuration of its DRAM
+/// controller.
+///
+/// The controller is only configured once the SPL initializes DRAM, so an empty configuration
+/// register means that DRAM is not initialized.
+fn dram_info(session: &Session, soc_id: u32) -> Result<DramInfo, Error> {
+    let controller = match DRAM_CONTROLLERS.iter().find(|&&(id, _)| id == soc_id) {
+        Some(&(_, controller)) => controller,
+        None => return Ok(DramInfo::Unknown),
+    };
+    let register = match controller {
+        DramController::Sun4i => SUN4I_DRAMC_DCR,
+        DramController::Sun8i => SUN8I_MCTL_COM_CR,
+    };
+    let mut value = [0_u32];
+    debug!("read_words of 1 word at {:#010x}", register);
+    session
+        .retry_usb(|| session.device.read_words(register, &mut value))
+        .context(format!(
+            "unable to read the DRAM controller register at {:#010x}",
+            register
+        ))?;
+    let value = value[0];
+    let size = match controller {
+        // Chip density, I/O width of each chip, bus width and number of ranks.
+        DramController::Sun4i => {
+            let chip_bytes = (256_u64 << (value >> 3 & 0x7)) << 17;
+            let io_width = u64::from(value >> 1 & 0x3) * 8;
+            let bus_width = (u64::from(value >> 6 & 0x7) + 1) * 8;
+            let ranks = u64::from(value >> 10 & 0x3) + 1;
+            bus_width
+                .checked_div(io_width)
+                .map_or(0, |chips| chip_bytes * chips * ranks)
+        }
+        // Row bits, page size, number of banks and number of ranks.
+        DramController::Sun8i => {
+            let rows = 1_u64 << ((value >> 4 & 0xF) + 1);
+            let page_size = 1_u64 << ((value >> 8 & 0xF) + 3);
+            let banks = if value & 0x4 == 0 { 4 } else { 8 };
+            let ranks = if value & 0x1 == 0 { 1 } else { 2 };
+            rows * page_size * banks * ranks
+        }
+    };
+    if value == 0 || size == 0 {
+        Ok(DramInfo::NotInitialized)
+    } else {
+        Ok(DramInfo::Initialized {
+            base: DRAM_BASE,
+            size,
+        })
+    }
+}
@@ -0,0 +1,628 @@
+//! Commands that check, copy, watch, fill and test the memory of the device.
+
+use std::{
+    fs::OpenOptions,
+    sync::atomic::Ordering,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use failure::{bail, Error, ResultExt};
+use log::{debug, info};
+use sha2::Sha256;
+
+use super::{
+    le_bytes, le_word, resolve_address,
+    transfer::{
+        copy_memory, hash_memory, read_memory, read_memory_chunks, stream_memory, Progress,
+    },
+    Session,
+};
+use crate::{
+    config::{Command, DiffFormat, HashAlgorithm, MemTestPattern},
+    output::{HexFormat, Output},
+    record, CliError, HEX_DUMP_LINE,
+};
+
+/// Maximum number of differing lines reported by `compare`.
+const COMPARE_DIFF_LINES: usize = 16;
+/// Polynomial of the CRC-16/CCITT.
+const CRC16_POLY: u16 = 0x1021;
+/// Initial value of the CRC-16/CCITT.
+const CRC16_INIT: u16 = 0xFFFF;
+
+/// Executes one of the memory commands.
+pub(super) fn execute(session: &Session, command: &Command) -> Result<(), Error> {
+    let device = session.device;
+    let hex_fmt = session.hex_fmt;
+    let show_progress = session.options.show_progress;
+    match *command {
+        Command::Crc32 {
+            address,
+            size,
+            expected,
+        } => {
+            let mut hasher = crc32fast::Hasher::new();
+            read_memory_chunks(session, address, size, |chunk| hasher.update(chunk))?;
+            let crc = hasher.finalize();
+            session.report(Output::Crc32(crc))?;
+            if let Some(expected) = expected {
+                if crc != expected {
+                    bail!(
+                        "the CRC-32 of the memory region is {:#010x}, but {:#010x} was expected",
+                        crc,
+                        expected
+                    );
+                }
+            }
+        }
+        Command::Hash {
+            address,
+            size,
+            algorithm,
+        } => {
+            let digest = match algorithm {
+                HashAlgorithm::Sha256 => hash_memory::<Sha256>(session, address, size)?,
+                HashAlgorithm::Sha1 => hash_memory::<sha1::Sha1>(session, address, size)?,
+                HashAlgorithm::Md5 => hash_memory::<md5::Md5>(session, address, size)?,
+                HashAlgorithm::Crc16 => {
+                    let mut crc = CRC16_INIT;
+                    read_memory_chunks(session, address, size, |chunk| {
+                        crc = crc16_update(crc, chunk)
+                    })?;
+                    vec![(crc >> 8) as u8, crc as u8]
+                }
+            };
+            session.report(Output::Digest {
+                algorithm,
+                address,
+                size,
+                digest,
+            })?;
+        }
+        Command::Compare {
+            addr_a,
+            addr_b,
+            size,
+            format,
+        } => {
+            if let Some((offset, byte_a, byte_b)) =
+                compare_memory(session, addr_a, addr_b, size, format)?
+            {
+                bail!(
+                    "the regions differ at offset {:#x}: {:#04x} at {:#010x}, {:#04x} at {:#010x}",
+                    offset,
+                    byte_a,
+                    addr_a + offset as u32,
+                    byte_b,
+                    addr_b + offset as u32
+                );
+            }
+            session.confirmation("Regions identical")?;
+        }
+        Command::Copy { src, dst, size } => {
+            copy_memory(session, src, dst, size)?;
+            session.confirmation(&format!(
+                "Copied {} bytes from {} to {}",
+                size,
+                hex_fmt.word(src),
+                hex_fmt.word(dst)
+            ))?;
+        }
+        Command::Watch {
+            address,
+            interval_ms,
+            count,
+            all,
+            ref record,
+        } => {
+            let mut record = match *record {
+                Some(ref path) => Some(
+                    OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(path)
+                        .context(format!(
+                            "could not open the record file '{}'",
+                            path.display()
+                        ))?,
+                ),
+                None => None,
+            };
+            // A previous device might have been interrupted.
+            let interrupted = &session.options.interrupted;
+            interrupted.store(false, Ordering::SeqCst);
+            let start = Instant::now();
+            let mut previous = None;
+            let mut reads = 0;
+            while !interrupted.load(Ordering::SeqCst) {
+                let mut val = [0_u32];
+                session
+                    .retry_usb(|| device.read_words(address, &mut val))
+                    .context(format!("unable to read {:#010x} address", address))?;
+                if all || previous != Some(val[0]) {
+                    let elapsed = start.elapsed();
+                    if let Some(ref mut file) = record {
+                        record::write(file, elapsed, address, val[0])
+                            .context("could not write to the record file")?;
+                    }
+                    session.report(Output::Watch {
+                        elapsed,
+                        address,
+                        value: val[0],
+                    })?;
+                }
+                previous = Some(val[0]);
+                reads += 1;
+                if reads == count {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(interval_ms));
+            }
+        }
+        Command::Clear {
+            address,
+            num_bytes,
+            verify,
+        } => {
+            let address = resolve_address(device, address, u64::from(num_bytes))?;
+            debug!("fel_fill of {:#x} bytes at {:#010x}", num_bytes, address);
+            session
+                .retry_usb(|| device.fel_fill(address, num_bytes, 0x00))
+                .context({
+                    format!(
+                        "unable to clear {} bytes at address {:#010x}",
+                        num_bytes, address
+                    )
+                })?;
+            session.confirmation(&format!(
+                "Cleared {} bytes at address {}",
+                num_bytes,
+                hex_fmt.word(address)
+            ))?;
+            if verify {
+                verify_fill(session, address, num_bytes, &[0x00], show_progress)?;
+                session.confirmation(&format!(
+                    "Verified {} bytes == {}",
+                    num_bytes,
+                    hex_fmt.byte(0x00)
+                ))?;
+            }
+        }
+        Command::Fill {
+            address,
+            num_bytes,
+            fill_byte,
+            verify,
+        } => {
+            let address = resolve_address(device, address, u64::from(num_bytes))?;
+            debug!("fel_fill of {:#x} bytes at {:#010x}", num_bytes, address);
+            session
+                .retry_usb(|| device.fel_fill(address, num_bytes, fill_byte))
+                .context({
+                    format!(
+                        "unable to fill {} bytes at address {:#010x} with byte {:#04x}",
+                        num_bytes, address, fill_byte
+                    )
+                })?;
+            session.confirmation(&format!(
+                "Filled {} bytes at address {} with byte {}",
+                num_bytes,
+                hex_fmt.word(address),
+                hex_fmt.byte(fill_byte)
+            ))?;
+            if verify {
+                verify_fill(session, address, num_bytes, &[fill_byte], show_progress)?;
+                session.confirmation(&format!(
+                    "Verified {} bytes == {}",
+                    num_bytes,
+                    hex_fmt.byte(fill_byte)
+                ))?;
+            }
+        }
+        Command::FillPattern {
+            address,
+            num_bytes,
+            ref pattern,
+            verify,
+        } => {
+            let address = resolve_address(device, address, u64::from(num_bytes))?;
+            fill_pattern(session, address, num_bytes, pattern, show_progress)?;
+            session.confirmation(&format!(
+                "Filled {} bytes at address {} with pattern {}",
+                num_bytes,
+                hex_fmt.word(address),
+                hex_fmt.bytes(pattern)
+            ))?;
+            if verify {
+                verify_fill(session, address, num_bytes, pattern, show_progress)?;
+                session.confirmation(&format!(
+                    "Verified {} bytes == pattern {}",
+                    num_bytes,
+                    hex_fmt.bytes(pattern)
+                ))?;
+            }
+        }
+        Command::MemTest {
+            address,
+            size,
+            pattern,
+        } => {
+            let address = resolve_address(device, address, u64::from(size))?;
+            memory_test(session, address, size, pattern, show_progress)?;
+            session.confirmation(&format!(
+                "{} bytes OK at address {}",
+                size,
+                hex_fmt.word(address)
+            ))?;
+        }
+        _ => unreachable!("not a memory command"),
+    }
+
+    Ok(())
+}
+
+/// Describes what the memory command would do.
+pub(super) fn describe(command: &Command, hex_fmt: HexFormat) -> Vec<String> {
+    vec![match *command {
+        Command::Crc32 { address, size, .. } => format!(
+            "Would compute the CRC-32 of {} bytes at {}",
+            size,
+            hex_fmt.word(address)
+        ),
+        Command::Hash {
+            address,
+            size,
+            algorithm,
+        } => format!(
+            "Would compute the {} hash of {} bytes at {}",
+            algorithm.name(),
+            size,
+            hex_fmt.word(address)
+        ),
+        Command::Compare {
+            addr_a,
+            addr_b,
+            size,
+            format,
+        } => format!(
+            "Would compare {} bytes at {} and {}, printing the differences as `{}`",
+            size,
+            hex_fmt.word(addr_a),
+            hex_fmt.word(addr_b),
+            format.name()
+        ),
+        Command::Copy { src, dst, size } => format!(
+            "Would copy {} bytes from {} to {}",
+            size,
+            hex_fmt.word(src),
+            hex_fmt.word(dst)
+        ),
+        Command::Watch {
+            address,
+            interval_ms,
+            ref record,
+            ..
+        } => format!(
+            "Would read the word at {} every {} ms{}",
+            hex_fmt.word(address),
+            interval_ms,
+            match *record {
+                Some(ref path) => format!(", recording it in '{}'", path.display()),
+                None => String::new(),
+            }
+        ),
+        Command::Clear {
+            address,
+            num_bytes,
+            verify,
+        } => format!(
+            "Would clear {} bytes at {}{}",
+            num_bytes,
+            hex_fmt.address(address),
+            if verify { " and read them back" } else { "" }
+        ),
+        Command::Fill {
+            address,
+            num_bytes,
+            fill_byte,
+            verify,
+        } => format!(
+            "Would fill {} bytes at {} with byte {}{}",
+            num_bytes,
+            hex_fmt.address(address),
+            hex_fmt.byte(fill_byte),
+            if verify { " and read them back" } else { "" }
+        ),
+        Command::FillPattern {
+            address,
+            num_bytes,
+            ref pattern,
+            verify,
+        } => format!(
+            "Would fill {} bytes at {} with pattern {}{}",
+            num_bytes,
+            hex_fmt.address(address),
+            hex_fmt.bytes(pattern),
+            if verify { " and read them back" } else { "" }
+        ),
+        Command::MemTest {
+            address,
+            size,
+            pattern,
+        } => format!(
+            "Would test {} bytes at {} with the `{}` pattern",
+            size,
+            hex_fmt.address(address),
+            pattern.name()
+        ),
+        _ => unreachable!("not a memory command"),
+    }]
+}
+
+/// Updates the given CRC-16/CCITT with the given data.
+fn crc16_update(mut crc: u16, data: &[u8]) -> u16 {
+    for byte in data {
+        crc ^= u16::from(*byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 == 0 {
+                crc << 1
+            } else {
+                (crc << 1) ^ CRC16_POLY
+            };
+        }
+    }
+    crc
+}
+
+/// Compares the memory regions at the given addresses in chunks, reporting the first
+/// `COMPARE_DIFF_LINES` differing lines in the given format.
+///
+/// Returns the offset of the first difference and the byte in each region, if any.
+fn compare_memory(
+    session: &Session,
+    addr_a: u32,
+    addr_b: u32,
+    size: u32,
+    format: DiffFormat,
+) -> Result<Option<(usize, u8, u8)>, Error> {
+    // Whole lines are read in each chunk, so that none of them is split.
+    let chunk_size = (session.options.chunk_size / HEX_DUMP_LINE).max(1) * HEX_DUMP_LINE;
+    let size = size as usize;
+    let mut chunk_a = vec![0_u8; chunk_size.min(size)];
+    let mut chunk_b = vec![0_u8; chunk_size.min(size)];
+    let mut first_diff = None;
+    let mut lines = 0;
+    for offset in (0..size).step_by(chunk_size) {
+        let len = chunk_size.min(size - offset);
+        let (chunk_a, chunk_b) = (&mut chunk_a[..len], &mut chunk_b[..len]);
+        read_memory(session, addr_a + offset as u32, chunk_a, 0, false, false)?;
+        read_memory(session, addr_b + offset as u32, chunk_b, 0, false, false)?;
+        if first_diff.is_none() {
+            first_diff = chunk_a
+                .iter()
+                .zip(chunk_b.iter())
+                .position(|(a, b)| a != b)
+                .map(|pos| (offset + pos, chunk_a[pos], chunk_b[pos]));
+        }
+        let diff_lines = chunk_a
+            .chunks(HEX_DUMP_LINE)
+            .zip(chunk_b.chunks(HEX_DUMP_LINE))
+            .enumerate()
+            .filter(|&(_, (line_a, line_b))| line_a != line_b);
+        for (i, (line_a, line_b)) in diff_lines {
+            if lines == COMPARE_DIFF_LINES {
+                break;
+            }
+            lines += 1;
+            let line_offset = (offset + i * HEX_DUMP_LINE) as u32;
+            session.report(Output::Difference {
+                offset: line_offset,
+                addr_a: addr_a + line_offset,
+                line_a: line_a.to_vec(),
+                addr_b: addr_b + line_offset,
+                line_b: line_b.to_vec(),
+                width: HEX_DUMP_LINE,
+                format,
+            })?;
+        }
+        if lines == COMPARE_DIFF_LINES {
+            break;
+        }
+    }
+    Ok(first_diff)
+}
+
+/// Fills the memory at the given address by repeating the given pattern of bytes.
+///
+/// The last repetition is truncated if the number of bytes is not a multiple of the pattern length.
+fn fill_pattern(
+    session: &Session,
+    address: u32,
+    num_bytes: u32,
+    pattern: &[u8],
+    show_progress: bool,
+) -> Result<(), Error> {
+    let chunk_size = session.options.chunk_size;
+    let total = num_bytes as usize;
+    let progress = Progress::new(total, show_progress);
+    let mut chunk = Vec::with_capacity(chunk_size.min(total));
+    let mut written = 0;
+    while written < total {
+        progress.update(&session.reporter, written)?;
+        let len = chunk_size.min(total - written);
+        chunk.clear();
+        chunk.extend((written..written + len).map(|i| pattern[i % pattern.len()]));
+        let chunk_address = address + written as u32;
+        debug!("fel_write of {:#x} bytes at {:#010x}", len, chunk_address);
+        session
+            .retry_usb(|| session.device.fel_write(chunk_address, &chunk))
+            .context(format!(
+                "unable to fill {} bytes at address {:#010x}",
+                len, chunk_address
+            ))?;
+        written += len;
+    }
+    progress.finish(&session.reporter)
+}
+
+/// Checks that the memory at the given address repeats the given pattern of bytes.
+///
+/// The memory is read back in chunks, so that big regions don't need a buffer of their size, and it
+/// fails at the first byte that doesn't match.
+fn verify_fill(
+    session: &Session,
+    address: u32,
+    num_bytes: u32,
+    pattern: &[u8],
+    show_progress: bool,
+) -> Result<(), Error> {
+    let mut offset = 0;
+    stream_memory(
+        session,
+        address,
+        num_bytes,
+        0,
+        false,
+        show_progress,
+        |chunk| {
+            let mismatch = chunk
+                .iter()
+                .enumerate()
+                .find(|&(i, byte)| *byte != pattern[(offset + i) % pattern.len()]);
+            if let Some((i, byte)) = mismatch {
+                return Err(CliError::new(format!(
+                    "verification failed at address {:#010x} (offset {:#x}): expected {:#04x}, \
+                     found {:#04x}",
+                    address + (offset + i) as u32,
+                    offset + i,
+                    pattern[(offset + i) % pattern.len()],
+                    byte
+                ))
+                .into());
+            }
+            offset += chunk.len();
+            Ok(())
+        },
+    )
+}
+
+/// Tests the memory at the given address by writing the given pattern and reading it back.
+///
+/// The whole region is written before reading it back, so that writes aliasing other addresses are
+/// detected, and it's done in chunks so that big regions don't need a buffer of their size. Fails
+/// with the first word that does not match.
+fn memory_test(
+    session: &Session,
+    address: u32,
+    size: u32,
+    pattern: MemTestPattern,
+    show_progress: bool,
+) -> Result<(), Error> {
+    let hex_fmt = session.hex_fmt;
+    let chunk_size = session.options.chunk_size;
+    let seed = match pattern {
+        MemTestPattern::Random => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let seed = now.as_secs() as u32 ^ now.subsec_nanos();
+            info!("Random memory test pattern with seed {:#010x}", seed);
+            seed
+        }
+        _ => 0,
+    };
+    let total = size as usize;
+    let progress = Progress::new(2 * total, show_progress);
+    let mut chunk = Vec::with_capacity(chunk_size.min(total));
+    for offset in (0..total).step_by(chunk_size) {
+        progress.update(&session.reporter, offset)?;
+        let chunk_address = address + offset as u32;
+        chunk.clear();
+        for i in 0..chunk_size.min(total - offset) / 4 {
+            let word_address = chunk_address + 4 * i as u32;
+            chunk.extend_from_slice(&le_bytes(memory_test_word(
+                pattern,
+                seed,
+                word_address - address,
+                word_address,
+            )));
+        }
+        debug!(
+            "fel_write of {:#x} bytes at {:#010x}",
+            chunk.len(),
+            chunk_address
+        );
+        session
+            .retry_usb(|| session.device.fel_write(chunk_address, &chunk))
+            .context(format!(
+                "unable to write {} bytes of the memory test at address {:#010x}",
+                chunk.len(),
+                chunk_address
+            ))?;
+    }
+    let mut readback = vec![0_u8; chunk_size.min(total)];
+    for offset in (0..total).step_by(chunk_size) {
+        progress.update(&session.reporter, total + offset)?;
+        let chunk_address = address + offset as u32;
+        let readback = &mut readback[..chunk_size.min(total - offset)];
+        debug!(
+            "fel_read of {:#x} bytes at {:#010x}",
+            readback.len(),
+            chunk_address
+        );
+        let len = readback.len();
+        session
+            .retry_usb(|| session.device.fel_read(chunk_address, readback))
+            .context(format!(
+                "unable to read back {} bytes of the memory test at address {:#010x}",
+                len, chunk_address
+            ))?;
+        for (i, bytes) in readback.chunks(4).enumerate() {
+            let word_address = chunk_address + 4 * i as u32;
+            let expected = memory_test_word(pattern, seed, word_address - address, word_address);
+            let found = le_word(bytes);
+            if found != expected {
+                progress.finish(&session.reporter)?;
+                return Err(CliError::new(format!(
+                    "memory test failed at address {}: expected {}, found {}",
+                    hex_fmt.word(word_address),
+                    hex_fmt.word(expected),
+                    hex_fmt.word(found)
+                ))
+                .into());
+            }
+        }
+    }
+    progress.finish(&session.reporter)
+}
+
+/// Gets the word of the memory test pattern for the word at the given offset and address.
+fn memory_test_word(pattern: MemTestPattern, seed: u32, offset: u32, address: u32) -> u32 {
+    match pattern {
+        MemTestPattern::Walking => 1 << (offset / 4 % 32),
+        MemTestPattern::Address => address,
+        MemTestPattern::Random => {
+            // Hash of the offset, so that the words can be generated again to check them.
+            let mut x = offset ^ seed;
+            x = (x ^ (x >> 16)).wrapping_mul(0x7FEB_352D);
+            x = (x ^ (x >> 15)).wrapping_mul(0x846C_A68B);
+            x ^ (x >> 16)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc16_update, CRC16_INIT};
+
+    #[test]
+    fn crc16() {
+        // Check value of the CRC-16/CCITT-FALSE.
+        assert_eq!(crc16_update(CRC16_INIT, b"123456789"), 0x29B1);
+        // Chunked data gives the same CRC.
+        let crc = crc16_update(CRC16_INIT, b"1234");
+        assert_eq!(crc16_update(crc, b"56789"), 0x29B1);
+        assert_eq!(crc16_update(CRC16_INIT, b""), CRC16_INIT);
+    }
+}
@@ -0,0 +1,422 @@
+//! Commands run in a FEL device.
+
+use std::{cell::RefCell, path::Path, time::Instant};
+
+use aw_fel::FelHandle;
+use failure::{bail, format_err, Error, ResultExt};
+use log::info;
+
+use crate::{
+    config::{Address, Command, NamedAddress},
+    output::{HexFormat, Output},
+    CliError, Options, TRANSFER_END,
+};
+
+mod dump;
+mod exec;
+mod info;
+mod memory;
+mod reset;
+mod scratch;
+mod spl;
+mod transfer;
+mod write;
+
+/// *DRAM* base address.
+const DRAM_BASE: u32 = 0x4000_0000;
+/// Base address and size of an SRAM region.
+type SramRegion = (u32, u32);
+/// SRAM A1 and C regions of the known SoC IDs, as base addresses and sizes.
+const SRAM_REGIONS: &[(u32, SramRegion, Option<SramRegion>)] = &[
+    (0x1623, (0x0000_0000, 0x4000), None),
+    (0x1625, (0x0000_0000, 0x4000), None),
+    (0x1633, (0x0000_0000, 0x8000), None),
+    (0x1650, (0x0000_0000, 0x8000), None),
+    (0x1651, (0x0000_0000, 0x4000), None),
+    (0x1667, (0x0000_0000, 0x8000), None),
+    (0x1680, (0x0000_0000, 0x1_0000), Some((0x0001_0000, 0xB000))),
+    (0x1689, (0x0001_0000, 0x8000), Some((0x0001_8000, 0x2_8000))),
+    (0x1701, (0x0000_0000, 0x4000), None),
+    (0x1718, (0x0001_0000, 0x8000), None),
+    (0x1728, (0x0002_0000, 0x8000), Some((0x0002_8000, 0x1_E000))),
+];
+
+/// Reporter of the output of a command, as it's produced.
+struct Reporter<'a> {
+    /// Function that handles each output.
+    report: RefCell<&'a mut dyn FnMut(Output) -> Result<(), Error>>,
+}
+
+impl<'a> Reporter<'a> {
+    /// Creates a reporter that passes the output to the given function.
+    fn new(report: &'a mut dyn FnMut(Output) -> Result<(), Error>) -> Self {
+        Self {
+            report: RefCell::new(report),
+        }
+    }
+
+    /// Reports the given output.
+    fn report(&self, output: Output) -> Result<(), Error> {
+        let mut report = self.report.borrow_mut();
+        let report: &mut dyn FnMut(Output) -> Result<(), Error> = &mut **report;
+        report(output)
+    }
+
+    /// Reports a problem that did not stop the command.
+    fn warning(&self, message: &str) -> Result<(), Error> {
+        self.report(Output::Warning(message.to_owned()))
+    }
+}
+
+/// Device in which a command runs, with the options of the execution and the reporter of its
+/// output.
+struct Session<'a> {
+    /// FEL device.
+    device: &'a FelHandle<'a>,
+    /// Options of the execution.
+    options: &'a Options,
+    /// Reporter of the output.
+    reporter: Reporter<'a>,
+    /// Hexadecimal formatting of the messages.
+    hex_fmt: HexFormat,
+}
+
+impl<'a> Session<'a> {
+    /// Reports the given output.
+    fn report(&self, output: Output) -> Result<(), Error> {
+        self.reporter.report(output)
+    }
+
+    /// Reports the status of a long running command.
+    fn status(&self, message: &str) -> Result<(), Error> {
+        self.report(Output::Status(message.to_owned()))
+    }
+
+    /// Reports that the command, or one of its steps, succeeded.
+    fn confirmation(&self, message: &str) -> Result<(), Error> {
+        self.report(Output::Confirmation(message.to_owned()))
+    }
+
+    /// Runs the given USB operation, retrying it up to the number of times given in the options if
+    /// it fails because of a USB error.
+    fn retry_usb<T, F>(&self, operation: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Result<T, Error>,
+    {
+        transfer::retry_usb(self.options.usb_retries, operation)
+    }
+}
+
+/// Executes the given command in the given device, with the steps of the execution enabled in the
+/// options.
+pub(crate) fn execute(
+    device: &FelHandle,
+    command: &Command,
+    options: &Options,
+    report: &mut dyn FnMut(Output) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let session = Session {
+        device,
+        options,
+        reporter: Reporter::new(report),
+        hex_fmt: HexFormat::new(options.hex_uppercase),
+    };
+    if options.prewarm {
+        prewarm(&session)?;
+    }
+
+    // The SID is read before running the command, since the device might not respond afterwards.
+    let sid = if options.show_sid {
+        Some(device.read_sid().context("unable to get SID from device")?)
+    } else {
+        None
+    };
+
+    info!(
+        "Running the {} command in a {} device",
+        command.name(),
+        device.get_soc_info().get_name()
+    );
+    if let Err(e) = execute_command(&session, command) {
+        if options.dump_registers_on_error {
+            match *command {
+                // The error of the command is reported even if the registers can't be.
+                Command::Uboot { .. } | Command::Write { .. } | Command::Execute { .. } => {
+                    let _ = report_diagnostic_registers(&session);
+                }
+                _ => {}
+            }
+        }
+        return Err(e);
+    }
+
+    if options.require_clean_exit {
+        probe_device(device)
+            .context("the device does not answer FEL requests anymore after running the command")?;
+    }
+
+    if let Some(sid) = sid {
+        session.report(Output::DeviceSid(sid))?;
+    }
+    Ok(())
+}
+
+/// Describes what the given command would do, without touching any device.
+pub(crate) fn describe(
+    command: &Command,
+    options: &Options,
+    report: &mut dyn FnMut(Output) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let reporter = Reporter::new(report);
+    let hex_fmt = HexFormat::new(options.hex_uppercase);
+    let steps = match *command {
+        Command::Uboot { .. } => spl::describe(command, hex_fmt)?,
+        Command::Dump { .. } => dump::describe(command, hex_fmt),
+        Command::Write { .. } => write::describe(&reporter, command, options, hex_fmt)?,
+        Command::Execute { .. } => exec::describe(command, hex_fmt),
+        Command::Reset { .. } | Command::Reset64 { .. } => reset::describe(command, hex_fmt),
+        Command::Version { .. } | Command::Map | Command::MemInfo => info::describe(command),
+        Command::List { .. } => vec!["Would list the connected FEL devices".to_owned()],
+        Command::Completions { .. } => {
+            unreachable!("completion scripts are printed without running the command")
+        }
+        Command::Replay { .. } => unreachable!("records are printed without running the command"),
+        Command::Crc32 { .. }
+        | Command::Hash { .. }
+        | Command::Compare { .. }
+        | Command::Copy { .. }
+        | Command::Watch { .. }
+        | Command::Clear { .. }
+        | Command::Fill { .. }
+        | Command::FillPattern { .. }
+        | Command::MemTest { .. } => memory::describe(command, hex_fmt),
+    };
+    for step in steps {
+        reporter.report(Output::DryRun(step))?;
+    }
+    Ok(())
+}
+
+/// Checks the input files of the given command without touching any device.
+pub(crate) fn validate(
+    command: &Command,
+    options: &Options,
+    report: &mut dyn FnMut(Output) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let reporter = Reporter::new(report);
+    match *command {
+        Command::Write { .. } => write::validate(&reporter, command, options),
+        // The rest of the files are checked when parsing the command.
+        _ => Ok(()),
+    }
+}
+
+/// Executes the given command in the device of the session.
+fn execute_command(session: &Session, command: &Command) -> Result<(), Error> {
+    match *command {
+        Command::Uboot { .. } => spl::execute(session, command),
+        Command::Dump { .. } => dump::execute(session, command),
+        Command::Write { .. } => write::execute(session, command),
+        Command::Execute { .. } => exec::execute(session, command),
+        Command::Reset { .. } | Command::Reset64 { .. } => reset::execute(session, command),
+        Command::Version { .. } | Command::Map | Command::MemInfo => {
+            info::execute(session, command)
+        }
+        Command::List { .. } => unreachable!("devices are listed before selecting any of them"),
+        Command::Completions { .. } => {
+            unreachable!("completion scripts are printed before selecting any device")
+        }
+        Command::Replay { .. } => unreachable!("records are printed before selecting any device"),
+        Command::Crc32 { .. }
+        | Command::Hash { .. }
+        | Command::Compare { .. }
+        | Command::Copy { .. }
+        | Command::Watch { .. }
+        | Command::Clear { .. }
+        | Command::Fill { .. }
+        | Command::FillPattern { .. }
+        | Command::MemTest { .. } => memory::execute(session, command),
+    }
+}
+
+/// Primes the USB link of the device with a throwaway read, reporting its latency.
+///
+/// The first transfer after enumeration is usually slower than the rest.
+fn prewarm(session: &Session) -> Result<(), Error> {
+    let start = Instant::now();
+    probe_device(session.device).context("unable to prewarm the USB link")?;
+    session.report(Output::Prewarm(start.elapsed()))
+}
+
+/// Checks that the device answers FEL requests with a throwaway read of its scratch area.
+fn probe_device(device: &FelHandle) -> Result<(), Error> {
+    let mut val = [0_u32];
+    device.read_words(device.get_soc_info().get_scratch_addr(), &mut val)
+}
+
+/// Reports the diagnostic registers of the device, given in the options or the default ones of
+/// its SoC.
+fn report_diagnostic_registers(session: &Session) -> Result<(), Error> {
+    let registers = if session.options.diagnostic_registers.is_empty() {
+        reset::default_diagnostic_registers(session.device)
+    } else {
+        session
+            .options
+            .diagnostic_registers
+            .iter()
+            .map(|addr| ("", *addr))
+            .collect()
+    };
+    let registers = registers
+        .into_iter()
+        .map(|(name, addr)| {
+            let mut val = [0_u32];
+            let value = session
+                .device
+                .read_words(addr, &mut val)
+                .ok()
+                .map(|()| val[0]);
+            (name, addr, value)
+        })
+        .collect();
+    session.report(Output::Registers(registers))
+}
+
+/// Resolves the given address of memory transferred to or from the device with the named
+/// addresses of the device SoC.
+///
+/// Fails if the name is unknown for the SoC, if the address goes past the end of the memory address
+/// space, or if the given number of bytes starting at the address, even none, cannot be
+/// transferred.
+fn resolve_address(device: &FelHandle, address: Address, len: u64) -> Result<u32, Error> {
+    let resolved = resolve_entry_point(device, address)?;
+    check_transfer_end(address, resolved, len)?;
+    Ok(resolved)
+}
+
+/// Resolves the given address of code executed in the device, without transferring any memory,
+/// with the named addresses of the device SoC.
+///
+/// Fails if the name is unknown for the SoC, or if the address goes past the end of the memory
+/// address space.
+fn resolve_entry_point(device: &FelHandle, address: Address) -> Result<u32, Error> {
+    let info = device.get_soc_info();
+    let base = match address.get_base() {
+        Some(NamedAddress::Scratch) => Some(info.get_scratch_addr()),
+        Some(NamedAddress::Spl) => Some(info.get_spl_addr()),
+        Some(NamedAddress::Thunk) => Some(info.get_thunk_addr()),
+        Some(NamedAddress::Sid) => info.get_sid_addr(),
+        Some(NamedAddress::Dram) => Some(DRAM_BASE),
+        None => Some(0),
+    };
+    let base = base.ok_or_else(|| {
+        format_err!(
+            "the `@{}` address is not known for the SoC {:#06x}",
+            address.get_base().unwrap().name(),
+            info.get_soc_id()
+        )
+    })?;
+    let resolved = u64::from(base) + u64::from(address.get_offset());
+    if resolved > u64::from(u32::max_value()) {
+        bail!(
+            "the address {} ({:#010x}) goes past the end of the memory address space",
+            HexFormat::new(false).address(address),
+            resolved
+        );
+    }
+    Ok(resolved as u32)
+}
+
+/// Checks that the given number of bytes at the given resolved address can be transferred.
+///
+/// The address itself must be below `TRANSFER_END` even if no bytes are transferred, since aw-fel
+/// also panics on empty transfers there.
+fn check_transfer_end(address: Address, resolved: u32, len: u64) -> Result<(), Error> {
+    if u64::from(resolved) + len > u64::from(TRANSFER_END) || resolved >= TRANSFER_END {
+        bail!(
+            "{} bytes at address {} ({:#010x}) go past {:#010x}, the end of the memory that can \
+             be transferred",
+            len,
+            HexFormat::new(false).address(address),
+            resolved,
+            TRANSFER_END
+        );
+    }
+    Ok(())
+}
+
+/// Gets the size of the given file, in bytes.
+fn file_size(path: &Path) -> Result<u64, Error> {
+    Ok(path
+        .metadata()
+        .context(format!(
+            "could not read the metadata of file '{}'",
+            path.display()
+        ))?
+        .len())
+}
+
+/// Gets the offset and the number of bytes to write from the given file, from the given offset and
+/// up to the given length or the end of the file.
+pub(crate) fn file_range(
+    path: &Path,
+    offset: u32,
+    length: Option<u32>,
+) -> Result<(u64, u64), Error> {
+    let size = file_size(path)?;
+    let offset = u64::from(offset);
+    let description = match length {
+        _ if offset > size => format!(
+            "the offset {:#x} is past the end of the file '{}', which has {} bytes",
+            offset,
+            path.display(),
+            size
+        ),
+        Some(length) if offset + u64::from(length) > size => format!(
+            "cannot write {} bytes at offset {:#x} of the file '{}', which has {} bytes",
+            length,
+            offset,
+            path.display(),
+            size
+        ),
+        Some(length) => return Ok((offset, u64::from(length))),
+        None => return Ok((offset, size - offset)),
+    };
+    Err(CliError::new(description).into())
+}
+
+/// Gets the little-endian bytes of a word.
+fn le_bytes(word: u32) -> [u8; 4] {
+    [
+        word as u8,
+        (word >> 8) as u8,
+        (word >> 16) as u8,
+        (word >> 24) as u8,
+    ]
+}
+
+/// Gets the word from its little-endian bytes.
+fn le_word(bytes: &[u8]) -> u32 {
+    u32::from(bytes[0])
+        | u32::from(bytes[1]) << 8
+        | u32::from(bytes[2]) << 16
+        | u32::from(bytes[3]) << 24
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_transfer_end;
+    use crate::config::Address;
+
+    #[test]
+    fn transfer_end() {
+        let check = |address, len| check_transfer_end(Address::absolute(address), address, len);
+
+        assert!(check(0x1000, 0).is_ok());
+        assert!(check(0xFFFF_FFF0, 0xE).is_ok());
+        assert!(check(0xFFFF_FFF0, 0xF).is_err());
+        assert!(check(0xFFFF_FFFD, 0).is_ok());
+        assert!(check(0xFFFF_FFFE, 0).is_err());
+        assert!(check(0xFFFF_FFFF, 0).is_err());
+    }
+}
@@ -0,0 +1,134 @@
+//! `reset` and `reset64` commands, which reset the SoC of the device.
+
+use std::slice;
+
+use aw_fel::FelHandle;
+use failure::{Error, ResultExt};
+use log::debug;
+
+use super::{scratch, Session};
+use crate::{config::Command, output::HexFormat};
+
+/// ARM `ldr pc, [pc, #-4]` instruction, jumping to the address in the word after it.
+const ARM_LDR_PC_NEXT_WORD: u32 = 0xE51F_F004;
+/// Watchdog base address of the A10 family SoCs.
+const SUN4I_WDT_BASE: u32 = 0x01C2_0C90;
+/// Watchdog base address of the A31 family SoCs.
+const SUN6I_WDT_BASE: u32 = 0x01C2_0CA0;
+/// Watchdog base address of the A80 SoC.
+const SUN9I_WDT_BASE: u32 = 0x0600_0CA0;
+
+/// Executes the `reset` or `reset64` command.
+pub(super) fn execute(session: &Session, command: &Command) -> Result<(), Error> {
+    let device = session.device;
+    match *command {
+        Command::Reset {
+            address: Some(address),
+        } => {
+            if device.get_soc_info().get_rvbar_reg().is_some() {
+                device
+                    .rmr_request(address, false)
+                    .context("could not send the warm RMR reset request")?;
+                session.confirmation("Warm RMR reset request sent")
+            } else {
+                jump_to(session, address)?;
+                session.confirmation(&format!(
+                    "Jumped to {} in AArch32 mode",
+                    session.hex_fmt.word(address)
+                ))
+            }
+        }
+        Command::Reset { address: None } => {
+            watchdog_reset(device).context("could not send the watchdog reset request")?;
+            session.confirmation("Watchdog reset request sent")
+        }
+        Command::Reset64 { address } => {
+            device
+                .rmr_request(address, true)
+                .context("could not send the warm RMR reset request")?;
+            session.confirmation("Warm RMR reset request sent")
+        }
+        _ => unreachable!("not a reset command"),
+    }
+}
+
+/// Describes what the `reset` or `reset64` command would do.
+pub(super) fn describe(command: &Command, hex_fmt: HexFormat) -> Vec<String> {
+    vec![match *command {
+        Command::Reset {
+            address: Some(address),
+        } => format!(
+            "Would send a warm RMR reset request to boot in AArch32 mode at {}, or jump to it if \
+             the SoC has no RVBAR register",
+            hex_fmt.word(address)
+        ),
+        Command::Reset { address: None } => "Would reset the SoC through its watchdog".to_owned(),
+        Command::Reset64 { address } => format!(
+            "Would send a warm RMR reset request to boot in AArch64 mode at {}",
+            hex_fmt.word(address)
+        ),
+        _ => unreachable!("not a reset command"),
+    }]
+}
+
+/// Jumps to the code at the given address in AArch32 mode, through a thunk in a scratch buffer.
+///
+/// RMR requests need the RVBAR register, which the 32-bit SoCs don't have. The code is not
+/// expected to return to FEL, so the device might stop answering the request.
+fn jump_to(session: &Session, address: u32) -> Result<(), Error> {
+    let words = [ARM_LDR_PC_NEXT_WORD, address];
+    // The code might take the memory after its address.
+    let code = u64::from(address)..u64::from(u32::max_value()) + 1;
+    let (thunk, _) = scratch::allocate(session, 4 * words.len() as u32, slice::from_ref(&code))
+        .context("unable to allocate the jump thunk")?;
+    debug!("jump thunk at {:#010x}: {:x?}", thunk, words);
+    session
+        .device
+        .write_words(thunk, &words)
+        .context("unable to write the jump thunk to the scratch buffer")?;
+    debug!("fel_execute at {:#010x}", thunk);
+    if let Err(e) = session.device.fel_execute(thunk) {
+        debug!("the device stopped responding after the jump: {}", e);
+    }
+    Ok(())
+}
+
+/// Resets the SoC of the device by making its watchdog expire as soon as possible.
+fn watchdog_reset(device: &FelHandle) -> Result<(), Error> {
+    match device.get_soc_info().get_soc_id() {
+        // A10, A10s/A13/R8, A20 and R40 watchdog: enable it with system reset in its mode register.
+        0x1623 | 0x1625 | 0x1651 | 0x1701 => device.write_words(SUN4I_WDT_BASE + 0x04, &[0b11]),
+        // A80 watchdog: select system reset in its configuration register and enable it.
+        0x1639 => device.write_words(SUN9I_WDT_BASE + 0x14, &[0b1, 0b1]),
+        // A31 and newer watchdog.
+        _ => device.write_words(SUN6I_WDT_BASE + 0x14, &[0b1, 0b1]),
+    }
+}
+
+/// Gets the default diagnostic registers for the SoC of the device, with their names.
+pub(super) fn default_diagnostic_registers(device: &FelHandle) -> Vec<(&'static str, u32)> {
+    let info = device.get_soc_info();
+    let mut registers = match info.get_soc_id() {
+        // A10, A10s/A13/R8, A20 and R40 watchdog.
+        0x1623 | 0x1625 | 0x1651 | 0x1701 => vec![
+            ("WDT_CTRL", SUN4I_WDT_BASE),
+            ("WDT_MODE", SUN4I_WDT_BASE + 0x04),
+        ],
+        // A80 watchdog.
+        0x1639 => vec![
+            ("WDT_CTRL", SUN9I_WDT_BASE + 0x10),
+            ("WDT_CFG", SUN9I_WDT_BASE + 0x14),
+            ("WDT_MODE", SUN9I_WDT_BASE + 0x18),
+        ],
+        // A31 and newer watchdog.
+        _ => vec![
+            ("WDT_CTRL", SUN6I_WDT_BASE + 0x10),
+            ("WDT_CFG", SUN6I_WDT_BASE + 0x14),
+            ("WDT_MODE", SUN6I_WDT_BASE + 0x18),
+        ],
+    };
+    if let Some(rvbar_reg) = info.get_rvbar_reg() {
+        registers.push(("RVBAR", rvbar_reg));
+    }
+    registers
+}
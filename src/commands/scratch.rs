@@ -0,0 +1,124 @@
+//! Allocation of the scratch buffers that commands need in the memory of the device.
+
+use std::ops::Range;
+
+use failure::{bail, format_err, Error};
+use log::debug;
+
+use super::{Session, SRAM_REGIONS};
+
+/// Alignment of the scratch buffers, in bytes.
+const SCRATCH_ALIGN: u64 = 4;
+
+/// Allocates a scratch buffer of at least the given length in the memory of the device, outside
+/// the given address ranges, which the command uses for something else.
+///
+/// The buffer is taken from the scratch region of the options if given. Otherwise, it's taken from
+/// the SRAM A1 of the SoC, starting at its FEL scratch area and avoiding the swap buffers and the
+/// thunk that FEL uses. Returns the address of the buffer and the maximum size it can have.
+pub(super) fn allocate(
+    session: &Session,
+    len: u32,
+    avoid: &[Range<u64>],
+) -> Result<(u32, u32), Error> {
+    let mut reserved = avoid.to_vec();
+    let region = match session.options.scratch_region {
+        Some((address, size)) => u64::from(address)..u64::from(address) + u64::from(size),
+        None => {
+            let info = session.device.get_soc_info();
+            let soc_id = session.device.get_version_info().get_id();
+            let start = u64::from(info.get_scratch_addr());
+            let end = match SRAM_REGIONS.iter().find(|&&(id, _, _)| id == soc_id) {
+                Some(&(_, (base, size), _)) => u64::from(base) + u64::from(size),
+                // The thunk goes after the scratch area, at the end of the usable SRAM.
+                None => u64::from(info.get_thunk_addr()),
+            };
+            if end <= start {
+                bail!(
+                    "the SRAM of the SoC {:#06x} is not known, give a `--scratch-region`",
+                    soc_id
+                );
+            }
+            for swap_buffers in info.get_swap_buffers() {
+                let buf1 = u64::from(swap_buffers.get_buf1());
+                reserved.push(buf1..buf1 + u64::from(swap_buffers.get_size()));
+            }
+            let thunk = u64::from(info.get_thunk_addr());
+            reserved.push(thunk..thunk + u64::from(info.get_thunk_size()));
+            start..end
+        }
+    };
+    let found = find_free(region.clone(), &mut reserved, u64::from(len)).ok_or_else(|| {
+        format_err!(
+            "no {} bytes are free for a scratch buffer from {:#010x} to {:#010x}",
+            len,
+            region.start,
+            region.end
+        )
+    })?;
+    debug!(
+        "scratch buffer of {} bytes at {:#010x}, up to {:#010x}",
+        len, found.start, found.end
+    );
+    Ok((found.start as u32, (found.end - found.start) as u32))
+}
+
+/// Finds the first aligned range of at least the given length in the region that doesn't overlap
+/// any of the reserved ranges.
+///
+/// The found range extends up to the next reserved range or the end of the region.
+fn find_free(region: Range<u64>, reserved: &mut [Range<u64>], len: u64) -> Option<Range<u64>> {
+    reserved.sort_by_key(|range| range.start);
+    let align = |address: u64| (address + SCRATCH_ALIGN - 1) / SCRATCH_ALIGN * SCRATCH_ALIGN;
+    let mut start = align(region.start);
+    for range in reserved.iter().filter(|range| range.start < range.end) {
+        if range.end <= start {
+            continue;
+        }
+        if range.start >= start + len {
+            break;
+        }
+        start = align(range.end);
+    }
+    if start + len > region.end {
+        return None;
+    }
+    let end = reserved
+        .iter()
+        .filter(|range| range.start < range.end && range.start >= start)
+        .map(|range| range.start)
+        .min()
+        .map_or(region.end, |next| next.min(region.end));
+    Some(start..end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_free;
+
+    #[test]
+    fn free_ranges() {
+        let free = |reserved: &[(u64, u64)], len| {
+            let mut reserved = reserved
+                .iter()
+                .map(|&(start, end)| start..end)
+                .collect::<Vec<_>>();
+            find_free(0x1000..0x8000, &mut reserved, len)
+        };
+
+        assert_eq!(free(&[], 0x100), Some(0x1000..0x8000));
+        assert_eq!(free(&[(0x1000, 0x1802)], 0x100), Some(0x1804..0x8000));
+        assert_eq!(
+            free(&[(0x7A00, 0x7C00), (0x1800, 0x2000)], 0x100),
+            Some(0x1000..0x1800)
+        );
+        assert_eq!(
+            free(&[(0x1000, 0x1100), (0x1180, 0x2000)], 0x100),
+            Some(0x2000..0x8000)
+        );
+        assert_eq!(free(&[(0x1100, 0x1100)], 0x200), Some(0x1000..0x8000));
+        assert_eq!(free(&[(0x0, 0x1_0000_0000)], 4), None);
+        assert_eq!(free(&[(0x1000, 0x7F00)], 0x200), None);
+        assert_eq!(free(&[], 0x8000), None);
+    }
+}
@@ -0,0 +1,257 @@
+//! `spl` command, which writes and executes the SPL of a U-Boot file, and then its U-Boot image.
+
+use aw_fel::SPL_LEN_LIMIT;
+use failure::{bail, format_err, Error, ResultExt};
+use log::debug;
+
+use super::{file_size, le_word, resolve_entry_point, transfer::read_file, Session, DRAM_BASE};
+use crate::{config::Command, output::HexFormat, output::Output, CliError};
+
+/// Pattern used to check that *DRAM* works.
+const DRAM_TEST_PATTERN: [u32; 4] = [0x1234_5678, 0xA5A5_A5A5, 0x5A5A_5A5A, 0xFEDC_BA98];
+/// Signature left in the SPL header when it returns to FEL mode.
+const SPL_FEL_SIGNATURE: &[u8] = b"eGON.FEL";
+
+/// Executes the `spl` command.
+pub(super) fn execute(session: &Session, command: &Command) -> Result<(), Error> {
+    let (file, start_uboot, entry, allow_small, check_dram, verify) = match *command {
+        Command::Uboot {
+            ref file,
+            start_uboot,
+            entry,
+            allow_small,
+            check_dram,
+            verify,
+        } => (file, start_uboot, entry, allow_small, check_dram, verify),
+        _ => unreachable!("not an spl command"),
+    };
+    let device = session.device;
+    let hex_fmt = session.hex_fmt;
+
+    // Load file.
+    let contents = read_file(&session.reporter, file, session.options.file_read_retries)
+        .context("could not load U-Boot file")?;
+
+    let uboot_image = split_uboot_file(&contents).1;
+    if start_uboot && !allow_small && uboot_image.is_none() {
+        bail!("the provided file does not contain a valid U-Boot image to be executed");
+    }
+
+    // Write and execute the SPL from the buffer.
+    session.status("Writing and executing SPL...")?;
+    debug!(
+        "Writing and executing SPL from a file of {} bytes",
+        contents.len()
+    );
+    device
+        .write_and_execute_spl(&contents)
+        .context("there was an error trying to write SPL to memory or executing it")?;
+
+    if verify {
+        session.status("Verifying SPL...")?;
+        verify_spl(session, &contents)?;
+    }
+
+    if check_dram {
+        session.status("Checking DRAM...")?;
+        let mut readback = vec![0_u32; DRAM_TEST_PATTERN.len()];
+        device
+            .write_words(DRAM_BASE, &DRAM_TEST_PATTERN)
+            .and_then(|_| device.read_words(DRAM_BASE, &mut readback))
+            .context("SPL did not initialize DRAM: unable to access it")?;
+        if readback != DRAM_TEST_PATTERN {
+            bail!(
+                "SPL did not initialize DRAM: the test pattern written at {:#010x} could not be \
+                 read back",
+                DRAM_BASE
+            );
+        }
+    }
+
+    if let Some(uboot_image) = uboot_image {
+        session.status(&format!(
+            "Writing U-Boot image ({} bytes)...",
+            uboot_image.len()
+        ))?;
+        debug!("Writing U-Boot image of {} bytes", uboot_image.len());
+        let (entry_point, _) = device
+            .write_uboot_image(uboot_image)
+            .context("could not write U-Boot image to device after writing the SPL")?;
+        if start_uboot {
+            let address = match entry {
+                Some(entry) => {
+                    let address = resolve_entry_point(device, entry)?;
+                    session.status(&format!(
+                        "Executing U-Boot at {}, instead of its entry point {}...",
+                        hex_fmt.word(address),
+                        hex_fmt.word(entry_point)
+                    ))?;
+                    address
+                }
+                None => {
+                    session.status(&format!(
+                        "Executing U-Boot at {}...",
+                        hex_fmt.word(entry_point)
+                    ))?;
+                    entry_point
+                }
+            };
+            debug!("fel_execute at {:#010x}", address);
+            device
+                .fel_execute(address)
+                .context("could not execute U-Boot")?;
+        } else {
+            session.report(Output::EntryPoint(entry_point))?;
+        }
+    }
+    Ok(())
+}
+
+/// Describes what the `spl` command would do.
+pub(super) fn describe(command: &Command, hex_fmt: HexFormat) -> Result<Vec<String>, Error> {
+    let (file, start_uboot, entry, check_dram, verify) = match *command {
+        Command::Uboot {
+            ref file,
+            start_uboot,
+            entry,
+            check_dram,
+            verify,
+            ..
+        } => (file, start_uboot, entry, check_dram, verify),
+        _ => unreachable!("not an spl command"),
+    };
+    let mut steps = Vec::new();
+    let size = file_size(file)?;
+    steps.push(format!(
+        "Would write and execute the SPL in '{}' ({} bytes)",
+        file.display(),
+        size.min(u64::from(SPL_LEN_LIMIT))
+    ));
+    if verify {
+        steps.push("Would read back the SPL and check it".to_owned());
+    }
+    if check_dram {
+        steps.push(format!("Would check DRAM at {}", hex_fmt.word(DRAM_BASE)));
+    }
+    if size > u64::from(SPL_LEN_LIMIT) {
+        steps.push(format!(
+            "Would write the U-Boot image in it ({} bytes)",
+            size - u64::from(SPL_LEN_LIMIT)
+        ));
+        match entry {
+            Some(entry) if start_uboot => steps.push(format!(
+                "Would execute U-Boot at {}, instead of its entry point",
+                hex_fmt.address(entry)
+            )),
+            _ if start_uboot => steps.push("Would execute U-Boot".to_owned()),
+            _ => {}
+        }
+    }
+    Ok(steps)
+}
+
+/// Splits the contents of a U-Boot file in its SPL area and its main U-Boot image, if any.
+///
+/// The SPL area takes the first `SPL_LEN_LIMIT` bytes, and the U-Boot image the rest of the file.
+/// Files of `SPL_LEN_LIMIT` bytes or less only have an SPL, even if they are exactly at the limit,
+/// since their U-Boot image would be empty, so they can only be executed with `--allow-small`.
+fn split_uboot_file(contents: &[u8]) -> (&[u8], Option<&[u8]>) {
+    if contents.len() > SPL_LEN_LIMIT as usize {
+        let (spl, uboot_image) = contents.split_at(SPL_LEN_LIMIT as usize);
+        (spl, Some(uboot_image))
+    } else {
+        (contents, None)
+    }
+}
+
+/// Checks that the SPL in the memory of the device matches the one in the given file contents.
+///
+/// The SPL is read back from the same places where it was written, so the parts that go in the
+/// swap buffers of the SoC are read from their second buffer. It must have returned to FEL mode,
+/// leaving the `eGON.FEL` signature in its header instead of the one of the file.
+fn verify_spl(session: &Session, contents: &[u8]) -> Result<(), Error> {
+    let spl_len = contents.get(16..20).map_or(0, le_word) as usize;
+    let mut expected = contents
+        .get(..spl_len)
+        .filter(|spl| spl.len() >= 4 + SPL_FEL_SIGNATURE.len())
+        .ok_or_else(|| format_err!("bad length in the SPL header"))?
+        .to_vec();
+    expected[4..4 + SPL_FEL_SIGNATURE.len()].copy_from_slice(SPL_FEL_SIGNATURE);
+
+    let soc_info = session.device.get_soc_info();
+    let mut segments = Vec::new();
+    let mut current = soc_info.get_spl_addr();
+    let mut offset = 0;
+    for swap_buffers in soc_info.get_swap_buffers() {
+        if offset < spl_len && current < swap_buffers.get_buf1() {
+            let len = ((swap_buffers.get_buf1() - current) as usize).min(spl_len - offset);
+            segments.push((current, offset, len));
+            current += len as u32;
+            offset += len;
+        }
+        if offset < spl_len && current == swap_buffers.get_buf1() {
+            let len = (swap_buffers.get_size() as usize).min(spl_len - offset);
+            segments.push((swap_buffers.get_buf2(), offset, len));
+            current += len as u32;
+            offset += len;
+        }
+    }
+    if offset < spl_len {
+        segments.push((current, offset, spl_len - offset));
+    }
+
+    for (address, offset, len) in segments {
+        let mut readback = vec![0_u8; len];
+        debug!("fel_read of {:#x} bytes at {:#010x}", len, address);
+        session
+            .retry_usb(|| session.device.fel_read(address, &mut readback))
+            .context(format!(
+                "could not read back {} bytes of the SPL at memory address {:#010x}",
+                len, address
+            ))?;
+        let chunk = &expected[offset..offset + len];
+        if let Some(pos) = chunk.iter().zip(readback.iter()).position(|(e, r)| e != r) {
+            return Err(CliError::new(format!(
+                "SPL verification failed at offset {:#x} (address {:#010x}): expected {:#04x}, \
+                 found {:#04x}",
+                offset + pos,
+                address + pos as u32,
+                chunk[pos],
+                readback[pos]
+            ))
+            .into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use aw_fel::SPL_LEN_LIMIT;
+
+    use super::split_uboot_file;
+
+    #[test]
+    fn uboot_file_split() {
+        let limit = SPL_LEN_LIMIT as usize;
+
+        let below = vec![0_u8; limit - 1];
+        let (spl, uboot_image) = split_uboot_file(&below);
+        assert_eq!(spl.len(), limit - 1);
+        assert!(uboot_image.is_none());
+
+        let at_limit = vec![0_u8; limit];
+        let (spl, uboot_image) = split_uboot_file(&at_limit);
+        assert_eq!(spl.len(), limit);
+        assert!(uboot_image.is_none());
+
+        let above = vec![0_u8; limit + 1];
+        let (spl, uboot_image) = split_uboot_file(&above);
+        assert_eq!(spl.len(), limit);
+        assert_eq!(uboot_image.map(<[u8]>::len), Some(1));
+
+        let (spl, uboot_image) = split_uboot_file(&[]);
+        assert!(spl.is_empty());
+        assert!(uboot_image.is_none());
+    }
+}
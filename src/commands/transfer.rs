@@ -0,0 +1,653 @@
+//! Memory transfers and file reads, retried on failure and reporting their progress.
+
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+use failure::{bail, Error, ResultExt};
+use log::{debug, info};
+use sha2::Digest;
+
+use super::{Reporter, Session};
+use crate::{output::Output, CliError, Options, TRANSFER_END};
+
+/// Minimum size of a memory transfer to log its throughput.
+const STATS_THRESHOLD: usize = 0x10_0000;
+/// Minimum size of a memory transfer to report its progress.
+const PROGRESS_THRESHOLD: usize = 0x1_0000;
+/// Time to wait before retrying a failed file read, in milliseconds.
+const FILE_READ_RETRY_DELAY_MS: u64 = 500;
+/// Time to wait before the first retry of a failed USB operation, in milliseconds, growing with
+/// each attempt.
+const USB_RETRY_DELAY_MS: u64 = 100;
+
+/// Reads the memory at the given address in chunks, retrying each chunk up to `retries` times.
+///
+/// If `partial_ok` is set, the chunks that cannot be read are left zeroed instead of failing. If
+/// `show_progress` is set, the progress of big reads is reported.
+pub(super) fn read_memory(
+    session: &Session,
+    address: u32,
+    buf: &mut [u8],
+    retries: u32,
+    partial_ok: bool,
+    show_progress: bool,
+) -> Result<(), Error> {
+    let mut offset = 0;
+    stream_memory(
+        session,
+        address,
+        buf.len() as u32,
+        retries,
+        partial_ok,
+        show_progress,
+        |chunk| {
+            buf[offset..offset + chunk.len()].copy_from_slice(chunk);
+            offset += chunk.len();
+            Ok(())
+        },
+    )
+}
+
+/// Reads the memory region at the given address in chunks, passing each of them to the callback
+/// as soon as it's read.
+///
+/// Each chunk is retried up to `retries` times. If `partial_ok` is set, the chunks that cannot be
+/// read are passed zeroed instead of failing. If `show_progress` is set, the progress of big reads
+/// is reported.
+pub(super) fn stream_memory<F>(
+    session: &Session,
+    address: u32,
+    size: u32,
+    retries: u32,
+    partial_ok: bool,
+    show_progress: bool,
+    f: F,
+) -> Result<(), Error>
+where
+    F: FnMut(&[u8]) -> Result<(), Error>,
+{
+    stream_chunks(
+        &session.reporter,
+        session.options,
+        (address, size),
+        (retries, partial_ok),
+        show_progress,
+        |chunk_address, chunk| session.retry_usb(|| session.device.fel_read(chunk_address, chunk)),
+        f,
+    )
+}
+
+/// Reads the memory region at the given address in chunks of the size given in the options with
+/// the `read` function, passing each of them to the callback as soon as it's read.
+///
+/// Each chunk is retried up to `retries` times. If `partial_ok` is set, the chunks that cannot be
+/// read are passed zeroed instead of failing.
+fn stream_chunks<R, F>(
+    reporter: &Reporter,
+    options: &Options,
+    (address, size): (u32, u32),
+    (retries, partial_ok): (u32, bool),
+    show_progress: bool,
+    mut read: R,
+    mut f: F,
+) -> Result<(), Error>
+where
+    R: FnMut(u32, &mut [u8]) -> Result<(), Error>,
+    F: FnMut(&[u8]) -> Result<(), Error>,
+{
+    let chunk_size = options.chunk_size;
+    let size = size as usize;
+    let progress = Progress::new(size, show_progress);
+    let mut stats = TransferStats::new();
+    let mut buf = vec![0_u8; chunk_size.min(size)];
+    let mut unreadable = 0;
+    for offset in (0..size).step_by(chunk_size) {
+        progress.update(reporter, offset)?;
+        let chunk = &mut buf[..chunk_size.min(size - offset)];
+        let chunk_address = address + offset as u32;
+        let mut attempt = 0;
+        debug!(
+            "fel_read of {:#x} bytes at {:#010x}",
+            chunk.len(),
+            chunk_address
+        );
+        let len = chunk.len();
+        while let Err(e) = stats.measure(len, || read(chunk_address, chunk)) {
+            if attempt == retries {
+                if !partial_ok {
+                    return Err(e
+                        .context(format!(
+                            "could not read {:#010x} bytes at memory address {:#010x}",
+                            chunk.len(),
+                            chunk_address
+                        ))
+                        .into());
+                }
+                for byte in chunk.iter_mut() {
+                    *byte = 0;
+                }
+                unreadable += 1;
+                reporter.warning(&format!(
+                    "could not read {:#010x} bytes at memory address {:#010x}, leaving them zeroed",
+                    chunk.len(),
+                    chunk_address
+                ))?;
+                break;
+            }
+            attempt += 1;
+            reporter.warning(&format!(
+                "{}, retrying the chunk at memory address {:#010x} ({}/{})",
+                e, chunk_address, attempt, retries
+            ))?;
+        }
+        f(chunk)?;
+    }
+    progress.finish(reporter)?;
+    stats.report(reporter, options, "Read")?;
+    if unreadable > 0 {
+        reporter.warning(&format!(
+            "{} of {} chunks could not be read",
+            unreadable,
+            (size + chunk_size - 1) / chunk_size
+        ))?;
+    }
+    Ok(())
+}
+
+/// Reads the memory region at the given address in chunks, passing each of them to the callback.
+pub(super) fn read_memory_chunks<F>(
+    session: &Session,
+    address: u32,
+    size: u32,
+    mut f: F,
+) -> Result<(), Error>
+where
+    F: FnMut(&[u8]),
+{
+    let chunk_size = session.options.chunk_size;
+    let mut stats = TransferStats::new();
+    let mut buf = vec![0_u8; chunk_size.min(size as usize)];
+    let mut offset = 0;
+    while offset < size {
+        let chunk = &mut buf[..chunk_size.min((size - offset) as usize)];
+        debug!(
+            "fel_read of {:#x} bytes at {:#010x}",
+            chunk.len(),
+            address + offset
+        );
+        let len = chunk.len();
+        stats
+            .measure(len, || {
+                session.retry_usb(|| session.device.fel_read(address + offset, chunk))
+            })
+            .context(format!(
+                "could not read {:#010x} bytes at memory address {:#010x}",
+                len,
+                address + offset
+            ))?;
+        f(chunk);
+        offset += chunk.len() as u32;
+    }
+    stats.report(&session.reporter, session.options, "Read")
+}
+
+/// Computes the digest of the memory region at the given address with the given hash function.
+pub(super) fn hash_memory<D: Digest>(
+    session: &Session,
+    address: u32,
+    size: u32,
+) -> Result<Vec<u8>, Error> {
+    let mut hasher = D::new();
+    read_memory_chunks(session, address, size, |chunk| hasher.input(chunk))?;
+    Ok(hasher.result().to_vec())
+}
+
+/// Copies the memory region at `src` to `dst` in chunks.
+///
+/// If the destination overlaps the end of the source, the chunks are copied back to front, so
+/// that no source byte is overwritten before being read.
+pub(super) fn copy_memory(session: &Session, src: u32, dst: u32, size: u32) -> Result<(), Error> {
+    let chunk_size = session.options.chunk_size;
+    let device = session.device;
+    let mut buf = vec![0_u8; chunk_size.min(size as usize)];
+    let mut offsets = (0..size).step_by(chunk_size).collect::<Vec<_>>();
+    if dst > src && dst - src < size {
+        offsets.reverse();
+    }
+    for offset in offsets {
+        let chunk = &mut buf[..chunk_size.min((size - offset) as usize)];
+        debug!(
+            "Copying {:#x} bytes from {:#010x} to {:#010x}",
+            chunk.len(),
+            src + offset,
+            dst + offset
+        );
+        session
+            .retry_usb(|| device.fel_read(src + offset, chunk))
+            .context(format!(
+                "could not read {:#010x} bytes at memory address {:#010x}",
+                chunk.len(),
+                src + offset
+            ))?;
+        session
+            .retry_usb(|| device.fel_write(dst + offset, chunk))
+            .context(format!(
+                "could not write {:#010x} bytes to memory address {:#010x}",
+                chunk.len(),
+                dst + offset
+            ))?;
+    }
+    Ok(())
+}
+
+/// Checks that the memory at the given address contains the expected data.
+///
+/// The memory is read back in chunks, so that big writes don't need a second buffer of their size.
+pub(super) fn verify_memory(session: &Session, address: u32, expected: &[u8]) -> Result<(), Error> {
+    let chunk_size = session.options.chunk_size;
+    let mut readback = vec![0_u8; chunk_size.min(expected.len())];
+    for (i, chunk) in expected.chunks(chunk_size).enumerate() {
+        let chunk_address = address + (i * chunk_size) as u32;
+        let readback = &mut readback[..chunk.len()];
+        debug!(
+            "fel_read of {:#x} bytes at {:#010x}",
+            chunk.len(),
+            chunk_address
+        );
+        session
+            .retry_usb(|| session.device.fel_read(chunk_address, readback))
+            .context(format!(
+                "could not read back {:#010x} bytes at memory address {:#010x}",
+                chunk.len(),
+                chunk_address
+            ))?;
+        if let Some(pos) = chunk.iter().zip(readback.iter()).position(|(e, r)| e != r) {
+            return Err(CliError::new(format!(
+                "verification failed at address {:#010x} (offset {:#x}): expected {:#04x}, found \
+                 {:#04x}",
+                chunk_address + pos as u32,
+                i * chunk_size + pos,
+                chunk[pos],
+                readback[pos]
+            ))
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Reports the successful verification of the given bytes at an address.
+pub(super) fn report_verified(session: &Session, len: usize, address: u32) -> Result<(), Error> {
+    session.confirmation(&format!(
+        "Verified {} bytes at {}",
+        len,
+        session.hex_fmt.word(address)
+    ))
+}
+
+/// Streams the given range of the file, with its offset and length, in chunks to the memory of the
+/// device, starting at the given address.
+///
+/// Each chunk is read back after writing it if `verify` is set. The progress of big files is
+/// reported if enabled in the options.
+pub(super) fn write_file(
+    session: &Session,
+    address: u32,
+    path: &Path,
+    (offset, len): (u64, u64),
+    verify: bool,
+) -> Result<(), Error> {
+    let mut file =
+        retry_file_operation(&session.reporter, session.options.file_read_retries, || {
+            File::open(path)
+                .context(format!("could not open the file '{}'", path.display()))
+                .map_err(Error::from)
+        })?;
+    let _ = file.seek(SeekFrom::Start(offset)).context(format!(
+        "could not seek to offset {:#x} of the file '{}'",
+        offset,
+        path.display()
+    ))?;
+    let mut reader = BufReader::new(file).take(len);
+    let source = format!("file '{}'", path.display());
+    let _ = write_stream(session, address, &mut reader, Some(len), &source, verify)?;
+    Ok(())
+}
+
+/// Streams all the data of the given reader in chunks to the memory of the device, starting at
+/// the given address, and returns the number of bytes written.
+///
+/// Fails if the data goes past the end of the memory that can be transferred. The progress is only
+/// reported if the total length of the data is known.
+pub(super) fn write_stream<R: Read>(
+    session: &Session,
+    address: u32,
+    reader: &mut R,
+    total: Option<u64>,
+    source: &str,
+    verify: bool,
+) -> Result<u64, Error> {
+    let chunk_size = session.options.chunk_size;
+    let total = total.map_or(0, |total| total as usize);
+    let mut chunk = Vec::with_capacity(chunk_size);
+    let progress = Progress::new(total, session.options.show_progress);
+    let mut stats = TransferStats::new();
+    let mut written = 0;
+    loop {
+        progress.update(&session.reporter, written.min(total))?;
+        chunk.clear();
+        let len = reader
+            .take(chunk_size as u64)
+            .read_to_end(&mut chunk)
+            .context(format!("could not read data from {}", source))?;
+        if len == 0 {
+            break;
+        }
+        if u64::from(address) + (written + len) as u64 > u64::from(TRANSFER_END) {
+            bail!(
+                "the data from {} does not fit in memory from address {:#010x}, it goes past \
+                 {:#010x}, the end of the memory that can be written",
+                source,
+                address,
+                TRANSFER_END
+            );
+        }
+        let chunk_address = address + written as u32;
+        debug!(
+            "fel_write of {:#x} bytes at {:#010x}",
+            chunk.len(),
+            chunk_address
+        );
+        stats
+            .measure(len, || {
+                session.retry_usb(|| session.device.fel_write(chunk_address, &chunk))
+            })
+            .context(format!(
+                "could not write data from {} to device memory",
+                source
+            ))?;
+        if verify {
+            verify_memory(session, chunk_address, &chunk)?;
+        }
+        written += len;
+    }
+    progress.finish(&session.reporter)?;
+    stats.report(&session.reporter, session.options, "Wrote")?;
+    if verify {
+        report_verified(session, written, address)?;
+    }
+    Ok(written as u64)
+}
+
+/// Reads the whole contents of the given file, retrying up to `retries` times on failure.
+pub(super) fn read_file(reporter: &Reporter, path: &Path, retries: u32) -> Result<Vec<u8>, Error> {
+    retry_file_operation(reporter, retries, || try_read_file(path))
+}
+
+/// Runs the given file operation, retrying it up to `retries` times on failure.
+fn retry_file_operation<T, F>(
+    reporter: &Reporter,
+    retries: u32,
+    mut operation: F,
+) -> Result<T, Error>
+where
+    F: FnMut() -> Result<T, Error>,
+{
+    let mut attempt = 0;
+    loop {
+        match operation() {
+            Ok(result) => return Ok(result),
+            Err(e) => {
+                if attempt == retries {
+                    return Err(e);
+                }
+                attempt += 1;
+                reporter.warning(&format!("{}, retrying ({}/{})", e, attempt, retries))?;
+                thread::sleep(Duration::from_millis(FILE_READ_RETRY_DELAY_MS));
+            }
+        }
+    }
+}
+
+/// Opens and reads the whole contents of the given file.
+fn try_read_file(path: &Path) -> Result<Vec<u8>, Error> {
+    let file = File::open(path).context(format!("could not open the file '{}'", path.display()))?;
+    let mut contents = Vec::new();
+    let _ = BufReader::new(file)
+        .read_to_end(&mut contents)
+        .context(format!(
+            "could not read data from file '{}'",
+            path.display()
+        ))?;
+    Ok(contents)
+}
+
+/// Runs the given USB operation, retrying it up to the given number of times if it fails because
+/// of a USB error, waiting longer after each attempt.
+///
+/// Other errors, such as unexpected responses of the device, are not retried.
+pub(super) fn retry_usb<T, F>(retries: u32, mut operation: F) -> Result<T, Error>
+where
+    F: FnMut() -> Result<T, Error>,
+{
+    let mut attempt = 0;
+    loop {
+        match operation() {
+            Err(ref e) if attempt < retries && is_usb_error(e) => {
+                attempt += 1;
+                info!(
+                    "{}, retrying the USB operation ({}/{})",
+                    e, attempt, retries
+                );
+                thread::sleep(Duration::from_millis(
+                    USB_RETRY_DELAY_MS * u64::from(attempt),
+                ));
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Checks whether the given error was caused by a USB error, even if it was given a context.
+fn is_usb_error(e: &Error) -> bool {
+    e.iter_chain()
+        .any(|cause| cause.downcast_ref::<libusb::Error>().is_some())
+}
+
+/// Progress of a memory transfer, reported as it advances.
+pub(super) struct Progress {
+    /// Total number of bytes to transfer.
+    total: usize,
+    /// Start of the transfer.
+    start: Instant,
+    /// Report the progress, only if the transfer is big enough.
+    enabled: bool,
+}
+
+impl Progress {
+    /// Starts tracking the progress of a transfer of the given number of bytes.
+    pub(super) fn new(total: usize, show: bool) -> Self {
+        Self {
+            total,
+            start: Instant::now(),
+            enabled: show && total > PROGRESS_THRESHOLD,
+        }
+    }
+
+    /// Reports the number of bytes transferred so far.
+    pub(super) fn update(&self, reporter: &Reporter, done: usize) -> Result<(), Error> {
+        if !self.enabled {
+            return Ok(());
+        }
+        reporter.report(Output::Progress {
+            done,
+            total: self.total,
+            elapsed: self.start.elapsed(),
+        })
+    }
+
+    /// Reports the completed transfer and the end of the progress.
+    pub(super) fn finish(&self, reporter: &Reporter) -> Result<(), Error> {
+        if !self.enabled {
+            return Ok(());
+        }
+        self.update(reporter, self.total)?;
+        reporter.report(Output::ProgressEnd)
+    }
+}
+
+/// Time spent transferring memory to or from the device, excluding the processing of the data.
+pub(super) struct TransferStats {
+    /// Number of bytes transferred.
+    bytes: usize,
+    /// Time spent in the transfers.
+    elapsed: Duration,
+}
+
+impl TransferStats {
+    /// Starts measuring the transfers.
+    pub(super) fn new() -> Self {
+        Self {
+            bytes: 0,
+            elapsed: Duration::from_secs(0),
+        }
+    }
+
+    /// Runs the given transfer of the given number of bytes, adding it to the measures.
+    pub(super) fn measure<T, F>(&mut self, bytes: usize, transfer: F) -> T
+    where
+        F: FnOnce() -> T,
+    {
+        let start = Instant::now();
+        let result = transfer();
+        self.elapsed += start.elapsed();
+        self.bytes += bytes;
+        result
+    }
+
+    /// Reports the number of bytes transferred, the time it took and the throughput, as in
+    /// `Read 4.00 MiB in 1.83s (2.19 MiB/s)`.
+    ///
+    /// It's reported if enabled in the options, and logged if the transfer was big enough.
+    pub(super) fn report(
+        &self,
+        reporter: &Reporter,
+        options: &Options,
+        action: &str,
+    ) -> Result<(), Error> {
+        let show = options.show_stats;
+        if !show && self.bytes <= STATS_THRESHOLD {
+            return Ok(());
+        }
+        let secs = self.elapsed.as_secs() as f64 + f64::from(self.elapsed.subsec_nanos()) / 1e9;
+        let mib = self.bytes as f64 / f64::from(1 << 20);
+        let throughput = if secs > 0.0 {
+            format!("{:.2} MiB/s", mib / secs)
+        } else {
+            "unknown throughput".to_owned()
+        };
+        let message = format!("{} {:.2} MiB in {:.2}s ({})", action, mib, secs, throughput);
+        if show {
+            reporter.report(Output::Stats(message))
+        } else {
+            info!("{}", message);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use failure::{format_err, Error, Fail};
+
+    use super::{retry_usb, stream_chunks, Reporter};
+    use crate::{output::Output, Options};
+
+    #[test]
+    fn usb_retries() {
+        // The USB errors of aw-fel are wrapped in a context, so the whole chain is checked.
+        let mut attempts = 0;
+        let result = retry_usb(1, || {
+            attempts += 1;
+            if attempts == 1 {
+                Err(libusb::Error::Io
+                    .context("could not read from the device")
+                    .into())
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(result.unwrap(), 2);
+
+        let mut attempts = 0;
+        let result: Result<(), Error> = retry_usb(1, || {
+            attempts += 1;
+            Err(format_err!("unexpected response"))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn chunked_reads() {
+        // The chunk size doesn't divide the region, so the last chunk is shorter.
+        let base = 0x4000_0000;
+        let memory = (0..100_u32).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        let read = |address: u32, chunk: &mut [u8]| -> Result<(), Error> {
+            let start = (address - base) as usize;
+            chunk.copy_from_slice(&memory[start..start + chunk.len()]);
+            Ok(())
+        };
+        let mut report = |_: Output| -> Result<(), Error> { Ok(()) };
+        let reporter = Reporter::new(&mut report);
+
+        let mut single = Vec::new();
+        let options = Options {
+            chunk_size: memory.len(),
+            ..Options::default()
+        };
+        stream_chunks(
+            &reporter,
+            &options,
+            (base, 100),
+            (0, false),
+            false,
+            read,
+            |chunk| {
+                single.extend_from_slice(chunk);
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        let mut chunked = Vec::new();
+        let mut chunks = 0;
+        let options = Options {
+            chunk_size: 16,
+            ..Options::default()
+        };
+        stream_chunks(
+            &reporter,
+            &options,
+            (base, 100),
+            (0, false),
+            false,
+            read,
+            |chunk| {
+                chunked.extend_from_slice(chunk);
+                chunks += 1;
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(single, memory);
+        assert_eq!(chunked, single);
+        assert_eq!(chunks, 7);
+    }
+}
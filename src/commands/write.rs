@@ -0,0 +1,548 @@
+//! `write` command, which writes words and files to the memory of the device.
+
+use std::{collections::BTreeMap, io};
+
+use failure::{bail, Error, ResultExt};
+use log::debug;
+
+use super::{
+    file_range, file_size, le_bytes, resolve_address,
+    transfer::{
+        read_file, report_verified, verify_memory, write_file, write_stream, TransferStats,
+    },
+    Reporter, Session,
+};
+use crate::{
+    config::{Command, Endianness, WriteData},
+    ihex,
+    output::{HexFormat, Output, WriteStatus},
+    srec, CliError, Options, TRANSFER_END,
+};
+
+/// Executes the `write` command.
+pub(super) fn execute(session: &Session, command: &Command) -> Result<(), Error> {
+    match *command {
+        Command::Write {
+            ref addresses,
+            ref data,
+            file_offset,
+            file_length,
+            endian,
+            repeat,
+            byte_swap,
+            merge_writes,
+            verify,
+            summary,
+        } => {
+            let device = session.device;
+            let hex_fmt = session.hex_fmt;
+
+            let mut resolved = Vec::with_capacity(addresses.len());
+            for (addr, data) in addresses.iter().zip(data) {
+                // Intel HEX and S-record segments are checked when relocating them.
+                let len = match *data {
+                    WriteData::Word(_) => 4 * u64::from(repeat),
+                    WriteData::File(ref path) => file_range(path, file_offset, file_length)?.1,
+                    // The length of stdin is checked when writing it.
+                    WriteData::Stdin | WriteData::IntelHex(_) | WriteData::Srec(_) => 0,
+                };
+                resolved.push(resolve_address(device, *addr, len)?);
+            }
+            let addresses = &resolved;
+            // Only runs of consecutive word writes are merged, so that the rest of the writes keep
+            // their order with respect to them. Returns the number of transfers.
+            let write_merged = |addresses: &[u32], data: &[WriteData]| -> Result<usize, Error> {
+                let words = addresses
+                    .iter()
+                    .zip(data)
+                    .filter_map(|(addr, data)| match *data {
+                        WriteData::Word(w) => Some((*addr, endian.apply(w))),
+                        WriteData::File(_)
+                        | WriteData::Stdin
+                        | WriteData::IntelHex(_)
+                        | WriteData::Srec(_) => None,
+                    })
+                    .collect::<BTreeMap<_, _>>();
+                let transfers = merge_word_writes(&words);
+                for (addr, bytes) in &transfers {
+                    debug!("fel_write of {:#x} bytes at {:#010x}", bytes.len(), addr);
+                    session
+                        .retry_usb(|| device.fel_write(*addr, bytes))
+                        .context({
+                            format!(
+                                "could not write {} merged bytes to address {:#010x}",
+                                bytes.len(),
+                                addr
+                            )
+                        })?;
+                    if verify {
+                        verify_memory(session, *addr, bytes)?;
+                        report_verified(session, bytes.len(), *addr)?;
+                    }
+                }
+                Ok(transfers.len())
+            };
+            let write_entry = |addr: &u32, data: &WriteData| -> Result<(), Error> {
+                match *data {
+                    WriteData::Word(w) if repeat > 1 => {
+                        let words = vec![endian.apply(w); repeat as usize];
+                        debug!("write_words of {} words at {:#010x}", repeat, addr);
+                        session
+                            .retry_usb(|| device.write_words(*addr, &words))
+                            .context({
+                                format!(
+                                    "could not write word {:#010x} {} times from address {:#010x}",
+                                    w, repeat, addr
+                                )
+                            })?;
+                        session.confirmation(&format!(
+                            "Wrote word {} {} times from address {}",
+                            hex_fmt.word(w),
+                            repeat,
+                            hex_fmt.word(*addr)
+                        ))?;
+                        if verify {
+                            let mut expected = Vec::with_capacity(words.len() * 4);
+                            for word in &words {
+                                expected.extend_from_slice(&le_bytes(*word));
+                            }
+                            verify_memory(session, *addr, &expected)?;
+                            report_verified(session, expected.len(), *addr)?;
+                        }
+                    }
+                    WriteData::Word(w) => {
+                        let native = endian.apply(w);
+                        debug!("write_words of 1 word at {:#010x}", addr);
+                        session
+                            .retry_usb(|| device.write_words(*addr, &[native]))
+                            .context({
+                                format!(
+                                    "could not write word {:#010x} to address {:#010x}",
+                                    w, addr
+                                )
+                            })?;
+                        session.confirmation(&format!(
+                            "Wrote word {} to address {}",
+                            hex_fmt.word(w),
+                            hex_fmt.word(*addr)
+                        ))?;
+                        if verify {
+                            verify_memory(session, *addr, &le_bytes(native))?;
+                            report_verified(session, 4, *addr)?;
+                        }
+                    }
+                    WriteData::File(ref path) => {
+                        let range = file_range(path, file_offset, file_length)?;
+                        write_file(session, *addr, path, range, verify)?;
+
+                        let message = if file_offset != 0 || file_length.is_some() {
+                            format!(
+                                "Wrote bytes {:#x} to {:#x} of file '{}' to address {}",
+                                range.0,
+                                range.0 + range.1,
+                                path.display(),
+                                hex_fmt.word(*addr)
+                            )
+                        } else {
+                            format!(
+                                "Wrote contents of file '{}' to address {}",
+                                path.display(),
+                                hex_fmt.word(*addr)
+                            )
+                        };
+                        session.confirmation(&message)?;
+                    }
+                    WriteData::Stdin => {
+                        let stdin = io::stdin();
+                        let written =
+                            write_stream(session, *addr, &mut stdin.lock(), None, "stdin", verify)?;
+                        session.confirmation(&format!(
+                            "Wrote {} bytes from stdin to address {}",
+                            written,
+                            hex_fmt.word(*addr)
+                        ))?;
+                    }
+                    WriteData::IntelHex(ref path) | WriteData::Srec(ref path) => {
+                        let segments = read_addressed_file(
+                            &session.reporter,
+                            data,
+                            session.options.file_read_retries,
+                            byte_swap,
+                        )?;
+                        let mut stats = TransferStats::new();
+                        for (offset, data) in segments {
+                            let segment_addr = relocate_segment(*addr, offset, data.len())?;
+                            debug!(
+                                "fel_write of {:#x} bytes at {:#010x}",
+                                data.len(),
+                                segment_addr
+                            );
+                            stats
+                                .measure(data.len(), || {
+                                    session.retry_usb(|| device.fel_write(segment_addr, &data))
+                                })
+                                .context("could not write file data to device memory")?;
+
+                            session.confirmation(&format!(
+                                "Wrote {} bytes of file '{}' to address {}",
+                                data.len(),
+                                path.display(),
+                                hex_fmt.word(segment_addr)
+                            ))?;
+                            if verify {
+                                verify_memory(session, segment_addr, &data)?;
+                                report_verified(session, data.len(), segment_addr)?;
+                            }
+                        }
+                        stats.report(&session.reporter, session.options, "Wrote")?;
+                    }
+                }
+                Ok(())
+            };
+            // Writes stop at the first failure, and the rest are skipped.
+            let mut written = 0;
+            let mut error = None;
+            let (mut merged_words, mut merged_transfers) = (0, 0);
+            while written < data.len() {
+                let words = data[written..]
+                    .iter()
+                    .take_while(|data| match **data {
+                        WriteData::Word(_) => merge_writes,
+                        _ => false,
+                    })
+                    .count();
+                let result = if words > 0 {
+                    let end = written + words;
+                    write_merged(&addresses[written..end], &data[written..end]).map(|transfers| {
+                        merged_words += words;
+                        merged_transfers += transfers;
+                        words
+                    })
+                } else {
+                    write_entry(&addresses[written], &data[written]).map(|()| 1)
+                };
+                match result {
+                    Ok(entries) => written += entries,
+                    Err(e) => {
+                        error = Some(e);
+                        break;
+                    }
+                }
+            }
+            if merged_words > 0 && error.is_none() {
+                session.confirmation(&format!(
+                    "Merged {} word writes into {} transfers",
+                    merged_words, merged_transfers
+                ))?;
+            }
+            if summary {
+                let rows = addresses
+                    .iter()
+                    .zip(data)
+                    .enumerate()
+                    .map(|(i, (addr, data))| {
+                        let status = if i < written {
+                            WriteStatus::Ok
+                        } else if i == written && error.is_some() {
+                            WriteStatus::Failed
+                        } else {
+                            WriteStatus::Skipped
+                        };
+                        (
+                            *addr,
+                            write_data_description(
+                                data,
+                                (file_offset, file_length),
+                                repeat,
+                                hex_fmt,
+                            ),
+                            status,
+                        )
+                    })
+                    .collect();
+                session.report(Output::WriteSummary(rows))?;
+            }
+            match error {
+                Some(e) => Err(e),
+                None => Ok(()),
+            }
+        }
+        _ => unreachable!("not a write command"),
+    }
+}
+
+/// Describes what the `write` command would do.
+///
+/// The Intel HEX and S-record files are read, to describe each of their segments.
+pub(super) fn describe(
+    reporter: &Reporter,
+    command: &Command,
+    options: &Options,
+    hex_fmt: HexFormat,
+) -> Result<Vec<String>, Error> {
+    let (addresses, data, file_offset, file_length, endian, repeat, byte_swap) = match *command {
+        Command::Write {
+            ref addresses,
+            ref data,
+            file_offset,
+            file_length,
+            endian,
+            repeat,
+            byte_swap,
+            ..
+        } => (
+            addresses,
+            data,
+            file_offset,
+            file_length,
+            endian,
+            repeat,
+            byte_swap,
+        ),
+        _ => unreachable!("not a write command"),
+    };
+    let mut steps = Vec::new();
+    for (addr, data) in addresses.iter().zip(data) {
+        match *data {
+            WriteData::Word(w) if repeat > 1 => steps.push(format!(
+                "Would write word {} {} times from address {}{}",
+                hex_fmt.word(w),
+                repeat,
+                hex_fmt.address(*addr),
+                if endian == Endianness::Big {
+                    " in big-endian"
+                } else {
+                    ""
+                }
+            )),
+            WriteData::Word(w) if endian == Endianness::Big => steps.push(format!(
+                "Would write word {} to address {} in big-endian",
+                hex_fmt.word(w),
+                hex_fmt.address(*addr)
+            )),
+            WriteData::Word(w) => steps.push(format!(
+                "Would write word {} to address {}",
+                hex_fmt.word(w),
+                hex_fmt.address(*addr)
+            )),
+            WriteData::File(ref path) if file_offset != 0 || file_length.is_some() => {
+                let (offset, len) = file_range(path, file_offset, file_length)?;
+                steps.push(format!(
+                    "Would write bytes {:#x} to {:#x} of file '{}' ({} bytes) to address {}",
+                    offset,
+                    offset + len,
+                    path.display(),
+                    len,
+                    hex_fmt.address(*addr)
+                ))
+            }
+            WriteData::Stdin => steps.push(format!(
+                "Would write the data read from stdin to address {}",
+                hex_fmt.address(*addr)
+            )),
+            WriteData::File(ref path) => steps.push(format!(
+                "Would write file '{}' ({} bytes) to address {}",
+                path.display(),
+                file_size(path)?,
+                hex_fmt.address(*addr)
+            )),
+            WriteData::IntelHex(ref path) | WriteData::Srec(ref path) => {
+                for (offset, segment) in
+                    read_addressed_file(reporter, data, options.file_read_retries, byte_swap)?
+                {
+                    steps.push(format!(
+                        "Would write {} bytes of file '{}' to address {}",
+                        segment.len(),
+                        path.display(),
+                        hex_fmt.address(addr.with_offset(relocate_segment(
+                            addr.get_offset(),
+                            offset,
+                            segment.len()
+                        )?))
+                    ));
+                }
+            }
+        }
+    }
+    Ok(steps)
+}
+
+/// Checks the Intel HEX and S-record files of the `write` command without touching any device,
+/// reading them and relocating their segments.
+///
+/// All the problems found in the files are reported, not just the first.
+pub(super) fn validate(
+    reporter: &Reporter,
+    command: &Command,
+    options: &Options,
+) -> Result<(), Error> {
+    let (addresses, data, byte_swap) = match *command {
+        Command::Write {
+            ref addresses,
+            ref data,
+            byte_swap,
+            ..
+        } => (addresses, data, byte_swap),
+        _ => unreachable!("not a write command"),
+    };
+    let mut problems = Vec::new();
+    for (addr, data) in addresses.iter().zip(data) {
+        let segments = match *data {
+            WriteData::IntelHex(_) | WriteData::Srec(_) => {
+                read_addressed_file(reporter, data, options.file_read_retries, byte_swap)
+            }
+            WriteData::Word(_) | WriteData::File(_) | WriteData::Stdin => continue,
+        };
+        match segments {
+            // Named addresses are checked again once resolved.
+            Ok(segments) => {
+                problems.extend(segments.iter().filter_map(|&(offset, ref segment)| {
+                    relocate_segment(addr.get_offset(), offset, segment.len()).err()
+                }))
+            }
+            Err(e) => problems.push(e),
+        }
+    }
+    CliError::check_problems(problems, "the write list")
+}
+
+/// Coalesces runs of contiguous word writes into single transfers of little-endian bytes.
+///
+/// Returns the start address and the packed bytes of each transfer.
+fn merge_word_writes(words: &BTreeMap<u32, u32>) -> Vec<(u32, Vec<u8>)> {
+    let mut transfers: Vec<(u32, Vec<u8>)> = Vec::new();
+    for (addr, word) in words {
+        match transfers.last_mut() {
+            Some((start, bytes)) if *start + bytes.len() as u32 == *addr => {
+                bytes.extend_from_slice(&le_bytes(*word))
+            }
+            _ => transfers.push((*addr, le_bytes(*word).to_vec())),
+        }
+    }
+    transfers
+}
+
+/// Reads the segments of data of an Intel HEX or S-record file to write, with their addresses.
+///
+/// The bytes of each group of `byte_swap` bytes of the segments are swapped, if given.
+fn read_addressed_file(
+    reporter: &Reporter,
+    data: &WriteData,
+    retries: u32,
+    byte_swap: Option<usize>,
+) -> Result<Vec<(u32, Vec<u8>)>, Error> {
+    let (path, format_name) = match *data {
+        WriteData::IntelHex(ref path) => (path, "Intel HEX"),
+        WriteData::Srec(ref path) => (path, "S-record"),
+        WriteData::Word(_) | WriteData::File(_) | WriteData::Stdin => {
+            bail!("the data to write has no addresses")
+        }
+    };
+    let contents = read_file(reporter, path, retries)?;
+    let text = String::from_utf8(contents)
+        .context(format!("the file '{}' is not a text file", path.display()))?;
+    let segments = match *data {
+        WriteData::IntelHex(_) => ihex::parse(&text),
+        _ => srec::parse(&text),
+    };
+    let mut segments = segments.context(format!(
+        "the file '{}' is not a valid {} file",
+        path.display(),
+        format_name
+    ))?;
+    if let Some(width) = byte_swap {
+        swap_segment_bytes(&mut segments, width).context(format!(
+            "unable to swap the bytes of the file '{}'",
+            path.display()
+        ))?;
+    }
+    Ok(segments)
+}
+
+/// Swaps the bytes of each group of the given number of bytes of the segments.
+///
+/// Fails if the length of any of the segments is not a multiple of the group size.
+fn swap_segment_bytes(segments: &mut [(u32, Vec<u8>)], width: usize) -> Result<(), Error> {
+    for &mut (address, ref mut segment) in segments {
+        if segment.len() % width != 0 {
+            bail!(
+                "the segment at {:#010x} has {} bytes, which is not a multiple of {}",
+                address,
+                segment.len(),
+                width
+            );
+        }
+        for group in segment.chunks_mut(width) {
+            group.reverse();
+        }
+    }
+    Ok(())
+}
+
+/// Describes the given data to write, with the size of the raw binary files.
+fn write_data_description(
+    data: &WriteData,
+    (file_offset, file_length): (u32, Option<u32>),
+    repeat: u32,
+    hex_fmt: HexFormat,
+) -> String {
+    match *data {
+        WriteData::Word(w) if repeat > 1 => format!("{} ({} times)", hex_fmt.word(w), repeat),
+        WriteData::Word(w) => hex_fmt.word(w),
+        WriteData::File(ref path) => match file_range(path, file_offset, file_length) {
+            Ok((_, len)) => format!("file '{}' ({} bytes)", path.display(), len),
+            Err(_) => format!("file '{}'", path.display()),
+        },
+        WriteData::Stdin => "stdin".to_owned(),
+        WriteData::IntelHex(ref path) => format!("Intel HEX file '{}'", path.display()),
+        WriteData::Srec(ref path) => format!("S-record file '{}'", path.display()),
+    }
+}
+
+/// Gets the address of a segment of a file, relative to the given base address.
+///
+/// Fails if the segment does not fit in the memory that can be transferred once relocated.
+fn relocate_segment(base: u32, offset: u32, len: usize) -> Result<u32, Error> {
+    match base.checked_add(offset) {
+        Some(addr) if u64::from(addr) + len as u64 <= u64::from(TRANSFER_END) => Ok(addr),
+        _ => bail!(
+            "cannot write {} bytes at offset {:#010x} from address {:#010x}, it would write past \
+             {:#010x}, the end of the memory that can be written",
+            len,
+            offset,
+            base,
+            TRANSFER_END
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{relocate_segment, swap_segment_bytes};
+
+    #[test]
+    fn segment_byte_swap() {
+        let mut segments = vec![(0x1000, vec![1, 2, 3, 4, 5, 6, 7, 8])];
+        swap_segment_bytes(&mut segments, 4).unwrap();
+        assert_eq!(segments[0].1, [4, 3, 2, 1, 8, 7, 6, 5]);
+        swap_segment_bytes(&mut segments, 2).unwrap();
+        assert_eq!(segments[0].1, [3, 4, 1, 2, 7, 8, 5, 6]);
+
+        let mut odd = vec![(0x1000, vec![1, 2, 3, 4]), (0x2000, vec![1, 2, 3])];
+        assert!(swap_segment_bytes(&mut odd, 2).is_err());
+    }
+
+    #[test]
+    fn segment_relocation() {
+        assert_eq!(
+            relocate_segment(0x4000_0000, 0x100, 0x10).unwrap(),
+            0x4000_0100
+        );
+        assert_eq!(
+            relocate_segment(0xffff_0000, 0xfff0, 0xe).unwrap(),
+            0xffff_fff0
+        );
+        assert!(relocate_segment(0xffff_0000, 0xfff0, 0xf).is_err());
+        assert!(relocate_segment(0xffff_0000, 0x1_0000, 0).is_err());
+    }
+}
@@ -1,72 +1,778 @@
-use std::path::PathBuf;
+//! Configuration of the command to run, parsed from the CLI.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::{u32, u8};
 
-use clap::ArgMatches;
-use failure::{Error, ResultExt};
+use clap::{ArgMatches, Shell};
+use failure::{bail, format_err, Error, ResultExt};
+use serde::Deserialize;
+
+use super::{CliError, DeviceSelector, Options, HEX_DUMP_LINE, TRANSFER_END};
+use crate::carray;
+use crate::commands::file_range;
+use crate::ihex::decode_hex;
 
-use super::CliError;
+/// Configuration file read from the current directory if none is given in the CLI.
+const DEFAULT_CONFIG_FILE: &str = "fel.toml";
+/// Environment variable with the default FEL devices.
+const DEVICE_ENV_VAR: &str = "FEL_DEVICE";
+/// Name of the C array of the `carray` dump format if none is given.
+const DEFAULT_ARRAY_NAME: &str = "data";
+/// File name that reads the data to write from stdin.
+const STDIN_FILE: &str = "-";
+/// Timeout of the USB transfers of `aw-fel`, in milliseconds.
+const USB_TIMEOUT_MS: u64 = 10_000;
 
 /// Data to write.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum WriteData {
     /// 32-bit word.
     Word(u32),
     /// Input file.
     File(Box<PathBuf>),
+    /// Raw data read from stdin.
+    Stdin,
+    /// Input Intel HEX file, with addresses relative to the given one.
+    IntelHex(Box<PathBuf>),
+    /// Input Motorola S-record file, with addresses relative to the given one.
+    Srec(Box<PathBuf>),
+}
+
+/// Compression format of an output file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Gzip compression.
+    Gzip,
+    /// XZ compression.
+    Xz,
+}
+
+impl Compression {
+    /// Gets the file extension of the compression format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Xz => "xz",
+        }
+    }
+}
+
+/// Known memory region of the SoC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryRegion {
+    /// Boot SRAM, where the boot ROM loads the SPL.
+    Sram,
+    /// SRAM A1.
+    SramA1,
+    /// SRAM C.
+    SramC,
+}
+
+impl MemoryRegion {
+    /// Gets the name of the region, as given in the CLI.
+    pub fn name(self) -> &'static str {
+        match self {
+            MemoryRegion::Sram => "sram",
+            MemoryRegion::SramA1 => "sram-a1",
+            MemoryRegion::SramC => "sram-c",
+        }
+    }
+}
+
+/// Pattern written to the memory by the memory test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemTestPattern {
+    /// A single bit set in each word, moving one position in each word.
+    Walking,
+    /// The address of each word.
+    Address,
+    /// Pseudo-random words.
+    Random,
+}
+
+impl MemTestPattern {
+    /// Gets the name of the pattern, as given in the CLI.
+    pub fn name(self) -> &'static str {
+        match self {
+            MemTestPattern::Walking => "walking",
+            MemTestPattern::Address => "address",
+            MemTestPattern::Random => "random",
+        }
+    }
+}
+
+/// Address of the SoC known by its name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NamedAddress {
+    /// FEL scratch area.
+    Scratch,
+    /// SRAM where the SPL is loaded.
+    Spl,
+    /// Area used by the FEL thunks.
+    Thunk,
+    /// SID registers.
+    Sid,
+    /// *DRAM* base address.
+    Dram,
+}
+
+impl NamedAddress {
+    /// Gets the name of the address, as given in the CLI after the `@`.
+    pub fn name(self) -> &'static str {
+        match self {
+            NamedAddress::Scratch => "scratch",
+            NamedAddress::Spl => "spl",
+            NamedAddress::Thunk => "thunk",
+            NamedAddress::Sid => "sid",
+            NamedAddress::Dram => "dram",
+        }
+    }
+
+    /// Gets the named address with the given name.
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "scratch" => Some(NamedAddress::Scratch),
+            "spl" => Some(NamedAddress::Spl),
+            "thunk" => Some(NamedAddress::Thunk),
+            "sid" => Some(NamedAddress::Sid),
+            "dram" => Some(NamedAddress::Dram),
+            _ => None,
+        }
+    }
+}
+
+/// Memory address, given as an integer or as an offset from a named address of the SoC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Address {
+    /// Named address the offset is relative to, if any.
+    base: Option<NamedAddress>,
+    /// Offset from the named address, or the address itself.
+    offset: u32,
+}
+
+impl Address {
+    /// Creates an address given as an integer.
+    pub fn absolute(address: u32) -> Self {
+        Self {
+            base: None,
+            offset: address,
+        }
+    }
+
+    /// Gets the named address the offset is relative to, if any.
+    pub fn get_base(&self) -> Option<NamedAddress> {
+        self.base
+    }
+
+    /// Gets the offset from the named address, or the address itself if there is none.
+    pub fn get_offset(&self) -> u32 {
+        self.offset
+    }
+
+    /// Gets the address with the same base and the given offset.
+    pub fn with_offset(self, offset: u32) -> Self {
+        Self { offset, ..self }
+    }
+}
+
+/// When to use colors in the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorMode {
+    /// Only if the output is a terminal.
+    Auto,
+    /// Always.
+    Always,
+    /// Never.
+    Never,
+}
+
+/// Format of memory data in a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataFormat {
+    /// Raw binary.
+    Binary,
+    /// Intel HEX records.
+    IntelHex,
+    /// Motorola S-records.
+    Srec,
+    /// C source with a byte array, only for dumps.
+    CArray,
+}
+
+/// Output format of the SID registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SidFormat {
+    /// Hexadecimal words separated by colons.
+    Colon,
+    /// Hexadecimal string of the bytes, in memory order.
+    Hex,
+    /// Raw bytes, in memory order.
+    Raw,
+    /// JSON array of the words.
+    Json,
+}
+
+/// Byte order of 32-bit words.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    /// Little-endian, the native order of the SoC.
+    Little,
+    /// Big-endian.
+    Big,
+}
+
+impl Endianness {
+    /// Converts the given word between this byte order and the native one of the SoC.
+    pub fn apply(self, word: u32) -> u32 {
+        match self {
+            Endianness::Little => word,
+            Endianness::Big => word.swap_bytes(),
+        }
+    }
+
+    /// Gets the byte order given in the CLI, little-endian by default.
+    fn from_cli(cli: &ArgMatches) -> Self {
+        match cli.value_of("endian") {
+            Some("big") => Endianness::Big,
+            _ => Endianness::Little,
+        }
+    }
+}
+
+/// Expected outcome of a function call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecExpectation {
+    /// The function returns within the given time.
+    Return,
+    /// The function does not return, since it takes over the device.
+    NoReturn,
+}
+
+/// Hash algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    /// SHA-256.
+    Sha256,
+    /// SHA-1.
+    Sha1,
+    /// MD5.
+    Md5,
+    /// CRC-16/CCITT, used by legacy programmers.
+    Crc16,
+}
+
+impl HashAlgorithm {
+    /// Gets the name of the hash algorithm, as used in the CLI.
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Md5 => "md5",
+            HashAlgorithm::Crc16 => "crc16",
+        }
+    }
+}
+
+/// Rendering of the differences found by `compare`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffFormat {
+    /// The offset and the bytes of each differing byte.
+    Offsets,
+    /// The differing lines of the first region removed and those of the second one added, as in a
+    /// unified diff.
+    Unified,
+    /// The differing lines of both regions side by side in hexadecimal.
+    Hexdump,
+}
+
+impl DiffFormat {
+    /// Gets the name of the format, as given in the CLI.
+    pub fn name(self) -> &'static str {
+        match self {
+            DiffFormat::Offsets => "offsets",
+            DiffFormat::Unified => "unified",
+            DiffFormat::Hexdump => "hexdump",
+        }
+    }
 }
 
 /// CLI command.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Command {
     /// U-Boot file.
-    Uboot { file: PathBuf, start_uboot: bool },
+    Uboot {
+        /// File with the SPL and, optionally, the U-Boot image.
+        file: PathBuf,
+        /// Whether to execute U-Boot after writing it.
+        start_uboot: bool,
+        /// Address to execute instead of the entry point of the U-Boot image.
+        entry: Option<Address>,
+        /// Whether to accept files smaller than the SPL.
+        allow_small: bool,
+        /// Whether to check that *DRAM* works after running the SPL.
+        check_dram: bool,
+        /// Whether to read back the SPL and check that it matches the file.
+        verify: bool,
+    },
     /// Dump memory address.
     Dump {
-        address: Option<u32>,
+        /// Address to dump, if not dumping the SID.
+        address: Option<Address>,
+        /// Number of bytes to dump, if not dumping words.
         size: Option<u32>,
+        /// Known region of the SoC to dump, instead of the address and size.
+        region: Option<MemoryRegion>,
+        /// Number of words to dump if no size is given.
+        words: u32,
+        /// Byte order of the dumped words.
+        endian: Endianness,
+        /// Whether to dump in hexadecimal.
         hex: bool,
+        /// Number of bytes in each line of the hexadecimal dump.
+        width: usize,
+        /// When to color the hexadecimal dump.
+        color: ColorMode,
+        /// Whether to dump the SID registers.
         sid: bool,
+        /// Output format of the SID registers.
+        sid_format: SidFormat,
+        /// Output file, instead of `stdout`.
         out: Option<PathBuf>,
+        /// Format of the dumped data.
+        format: DataFormat,
+        /// Name of the C array of the dumped data.
+        array_name: String,
+        /// Whether to append the dumped data to the output file instead of overwriting it.
+        append: bool,
+        /// Whether to write binary data to the terminal.
+        force: bool,
+        /// Compression of the output file.
+        compress: Option<Compression>,
+        /// Alignment to pad the output file to.
+        align_output: Option<u32>,
+        /// Byte used to pad the output file.
+        pad_byte: u8,
+        /// Number of times a failed chunk read is retried.
+        read_retries: u32,
+        /// Whether to leave unreadable chunks zeroed instead of failing.
+        partial_ok: bool,
+        /// Number of times to dump the memory.
+        count: u32,
+        /// Time between dumps, in milliseconds.
+        interval_ms: u64,
     },
     /// Write data to memory addresses.
     Write {
-        addresses: Vec<u32>,
+        /// Addresses to write the data to.
+        addresses: Vec<Address>,
+        /// Data to write to each of the addresses.
         data: Vec<WriteData>,
+        /// Offset in the raw binary files of the first byte to write.
+        file_offset: u32,
+        /// Number of bytes of the raw binary files to write, if not up to their end.
+        file_length: Option<u32>,
+        /// Byte order of the words to write.
+        endian: Endianness,
+        /// Number of times each word is written, to consecutive addresses.
+        repeat: u32,
+        /// Size of the groups of bytes to swap in the Intel HEX and S-record files, if any.
+        byte_swap: Option<usize>,
+        /// Whether to coalesce contiguous word writes.
+        merge_writes: bool,
+        /// Whether to read back and check the written data.
+        verify: bool,
+        /// Whether to print a table with the result of each write at the end.
+        summary: bool,
     },
     /// Call function at address.
-    Execute { address: u32 },
+    Execute {
+        /// Address of the function.
+        address: Address,
+        /// Time in which the function must return to count as returned, in milliseconds.
+        return_within_ms: Option<u64>,
+        /// Expected outcome of the call.
+        expect: Option<ExecExpectation>,
+        /// Number of calls, or 0 to call it until interrupted.
+        repeat: u32,
+        /// Values of the `r0` to `r3` registers, the arguments of the function.
+        args: [Option<u32>; 4],
+        /// Address and value of the word to poll after each call, until it has that value.
+        wait: Option<(Address, u32)>,
+        /// Maximum time to poll the word, in milliseconds.
+        wait_timeout_ms: Option<u64>,
+    },
+    /// Watchdog reset, or RMR request for AArch32 warm boot if an address is given.
+    Reset {
+        /// Entry point of the warm boot.
+        address: Option<u32>,
+    },
     /// RMR request for AArch64 warm boot.
-    Reset64 { address: u32 },
+    Reset64 {
+        /// Entry point of the warm boot.
+        address: u32,
+    },
     /// Get SoC version information.
-    Version,
+    Version {
+        /// Whether to print the version information as JSON.
+        json: bool,
+    },
+    /// Probe the known memory regions of the SoC.
+    Map,
+    /// Get the memory information of the SoC.
+    MemInfo,
+    /// List the connected FEL devices.
+    List {
+        /// Whether to print the list as JSON.
+        json: bool,
+    },
+    /// Compute the CRC-32 of a memory region.
+    Crc32 {
+        /// Start address of the region.
+        address: u32,
+        /// Size of the region, in bytes.
+        size: u32,
+        /// Expected CRC-32, to check the computed one against.
+        expected: Option<u32>,
+    },
+    /// Compute the hash of a memory region.
+    Hash {
+        /// Start address of the region.
+        address: u32,
+        /// Size of the region, in bytes.
+        size: u32,
+        /// Hash algorithm to use.
+        algorithm: HashAlgorithm,
+    },
+    /// Compare two memory regions.
+    Compare {
+        /// Start address of the first region.
+        addr_a: u32,
+        /// Start address of the second region.
+        addr_b: u32,
+        /// Size of the regions, in bytes.
+        size: u32,
+        /// Rendering of the differences.
+        format: DiffFormat,
+    },
+    /// Copy a memory region to another address.
+    Copy {
+        /// Source address.
+        src: u32,
+        /// Destination address.
+        dst: u32,
+        /// Size of the region, in bytes.
+        size: u32,
+    },
+    /// Poll a memory address and print its changes.
+    Watch {
+        /// Address to poll.
+        address: u32,
+        /// Time between reads, in milliseconds.
+        interval_ms: u64,
+        /// Number of reads, or 0 to poll until interrupted.
+        count: u32,
+        /// Whether to print every read, even if the value did not change.
+        all: bool,
+        /// File to append the record of each printed value to.
+        record: Option<PathBuf>,
+    },
+    /// Print the values recorded by `watch`.
+    Replay {
+        /// File with the records.
+        file: PathBuf,
+    },
     /// Clear the memory.
-    Clear { address: u32, num_bytes: u32 },
+    Clear {
+        /// Start address.
+        address: Address,
+        /// Number of bytes to clear.
+        num_bytes: u32,
+        /// Whether to read back the memory after clearing it.
+        verify: bool,
+    },
     /// Fill the memory.
     Fill {
-        address: u32,
+        /// Start address.
+        address: Address,
+        /// Number of bytes to fill.
         num_bytes: u32,
+        /// Byte to fill the memory with.
         fill_byte: u8,
+        /// Whether to read back the memory after filling it.
+        verify: bool,
+    },
+    /// Fill the memory with a repeating pattern of bytes.
+    FillPattern {
+        /// Start address.
+        address: Address,
+        /// Number of bytes to fill.
+        num_bytes: u32,
+        /// Pattern of bytes to repeat.
+        pattern: Vec<u8>,
+        /// Whether to read back the memory after filling it.
+        verify: bool,
+    },
+    /// Test a memory region by writing a pattern and reading it back.
+    MemTest {
+        /// Start address.
+        address: Address,
+        /// Number of bytes to test.
+        size: u32,
+        /// Pattern to write.
+        pattern: MemTestPattern,
     },
+    /// Print the completion script of a shell.
+    Completions {
+        /// Shell to generate the script for.
+        shell: Shell,
+    },
+}
+
+impl Command {
+    /// Gets the name of the subcommand of the command.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Command::Uboot { .. } => "spl",
+            Command::Dump { .. } => "dump",
+            Command::Write { .. } => "write",
+            Command::Execute { .. } => "exec",
+            Command::Reset { .. } => "reset",
+            Command::Reset64 { .. } => "reset64",
+            Command::Version { .. } => "version",
+            Command::Map => "map",
+            Command::MemInfo => "meminfo",
+            Command::List { .. } => "list",
+            Command::Completions { .. } => "completions",
+            Command::Crc32 { .. } => "crc32",
+            Command::Hash { .. } => "hash",
+            Command::Compare { .. } => "compare",
+            Command::Copy { .. } => "copy",
+            Command::Watch { .. } => "watch",
+            Command::Replay { .. } => "replay",
+            Command::Clear { .. } => "clear",
+            Command::Fill { .. } | Command::FillPattern { .. } => "fill",
+            Command::MemTest { .. } => "memtest",
+        }
+    }
+
+    /// Checks if the command runs until it's interrupted with Ctrl-C, or can be stopped with it.
+    pub fn is_interruptible(&self) -> bool {
+        match *self {
+            Command::Watch { .. } => true,
+            Command::Execute { repeat, .. } => repeat != 1,
+            _ => false,
+        }
+    }
+}
+
+/// Default options read from the configuration file.
+///
+/// Options given in the CLI take precedence over the ones in the file, which in turn take
+/// precedence over the built-in defaults.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+struct FileConfig {
+    /// Default FEL devices, in the same format as the `--device` option.
+    device: Option<String>,
+    /// Default number of bytes in each line of hexadecimal dumps.
+    dump_width: Option<usize>,
+    /// Default coloring of hexadecimal dumps.
+    color: Option<ColorMode>,
+    /// Whether to read back and check the written data by default.
+    verify_on_write: Option<bool>,
+}
+
+impl FileConfig {
+    /// Reads the configuration file given in the CLI, or the default one if it exists.
+    ///
+    /// If no file is given in the CLI and the default one does not exist, no option is set.
+    fn from_cli(cli: &ArgMatches) -> Result<Self, Error> {
+        let path = match cli.value_of("config") {
+            Some(path_str) => PathBuf::from(path_str),
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => PathBuf::from(DEFAULT_CONFIG_FILE),
+            None => return Ok(Self::default()),
+        };
+        let text = fs::read_to_string(&path).context(format!(
+            "could not read the configuration file '{}'",
+            path.display()
+        ))?;
+        let file_config: Self = toml::from_str(&text).context(CliError {
+            description: format!("the configuration file '{}' is not valid", path.display()),
+        })?;
+        if let Some(width) = file_config.dump_width {
+            if ![8, 16, 32].contains(&width) {
+                return Err(CliError {
+                    description: format!(
+                        "the dump width in the configuration file '{}' must be 8, 16 or 32, \
+                         given {}",
+                        path.display(),
+                        width
+                    ),
+                }
+                .into());
+            }
+        }
+        Ok(file_config)
+    }
 }
 
 /// Configuration structure.
+#[derive(Debug)]
 pub struct Config {
-    device: Option<(u8, u8)>,
+    devices: Vec<(u8, u8)>,
+    preferred_device: Option<DeviceSelector>,
+    serial: Option<String>,
+    all_devices: bool,
     command: Option<Command>,
+    validate_only: bool,
+    dry_run: bool,
+    no_device: bool,
+    wait: bool,
+    wait_timeout_secs: Option<u64>,
+    show_sid: bool,
+    stats: bool,
+    prewarm: bool,
+    require_clean_exit: bool,
+    hex_uppercase: bool,
+    json: bool,
+    quiet: bool,
+    assume_yes: bool,
+    verbosity: u64,
+    file_read_retries: u32,
+    usb_retries: u32,
+    chunk_size: usize,
+    scratch_region: Option<(u32, u32)>,
+    keep_going: bool,
+    exit_zero_on_no_device: bool,
+    dump_registers_on_error: bool,
+    diagnostic_registers: Vec<u32>,
+    summary_json: Option<PathBuf>,
 }
 
 impl Config {
     /// Generate the config structure from the CLI.
+    ///
+    /// All the problems found in the options and the command are reported, not just the first.
     pub fn from_cli(cli: &ArgMatches) -> Result<Self, Error> {
+        let mut problems = Vec::new();
+        if cli.is_present("strict_hex") {
+            let _ = check(&mut problems, Self::check_radix_prefixes(cli));
+        }
+        // The devices and the command default to the ones of the file, so they need it.
+        let file_config = FileConfig::from_cli(cli)?;
+        let all_devices = cli.is_present("all_devices");
+        if all_devices {
+            match cli.subcommand_name() {
+                Some("spl") | Some("write") | Some("fill") | None => {}
+                Some(_) => {
+                    problems.push(
+                        CliError {
+                            description: "`--all-devices` is only supported by the spl, write \
+                                          and fill commands"
+                                .to_owned(),
+                        }
+                        .into(),
+                    );
+                }
+            }
+        }
+        let devices = check(&mut problems, Self::get_devices_from_cli(cli, &file_config));
+        let preferred_device = check(&mut problems, Self::get_preferred_device_from_cli(cli));
+        let command = check(&mut problems, Self::get_command_from_cli(cli, &file_config));
+        let wait_timeout_secs = check(
+            &mut problems,
+            match cli.value_of("wait") {
+                Some(secs_str) => secs_str
+                    .parse::<u64>()
+                    .map(Some)
+                    .context(CliError {
+                        description: format!(
+                            "the time to wait for a device must be an integer between 0 and {} \
+                         seconds, given '{}'",
+                            u64::max_value(),
+                            secs_str
+                        ),
+                    })
+                    .map_err(Error::from),
+                None => Ok(None),
+            },
+        );
+        let file_read_retries = check(&mut problems, Self::get_file_read_retries_from_cli(cli));
+        let usb_retries = check(
+            &mut problems,
+            cli.value_of("retries")
+                .unwrap()
+                .parse::<u32>()
+                .context(CliError {
+                    description: format!(
+                        "the number of USB retries must be an integer between 0 and {}",
+                        u32::max_value()
+                    ),
+                })
+                .map_err(Error::from),
+        );
+        let chunk_size = check(&mut problems, Self::get_chunk_size_from_cli(cli));
+        let scratch_region = check(&mut problems, Self::get_scratch_region_from_cli(cli));
+        let diagnostic_registers =
+            check(&mut problems, Self::get_diagnostic_registers_from_cli(cli));
+        CliError::check_problems(problems, "the command line")?;
+        // All the values are set once there are no problems.
         Ok(Self {
-            device: Self::get_device_from_cli(&cli)?,
-            command: Self::get_command_from_cli(&cli)?,
+            devices: devices.unwrap_or_default(),
+            preferred_device: preferred_device.unwrap_or_default(),
+            serial: cli.value_of("serial").map(str::to_owned),
+            all_devices,
+            command: command.unwrap_or_default(),
+            validate_only: cli.is_present("validate_only"),
+            dry_run: cli.is_present("dry_run"),
+            no_device: cli.is_present("no_device"),
+            wait: cli.is_present("wait"),
+            wait_timeout_secs: wait_timeout_secs.unwrap_or_default(),
+            show_sid: cli.is_present("show_sid"),
+            stats: cli.is_present("stats"),
+            prewarm: cli.is_present("prewarm"),
+            require_clean_exit: cli.is_present("require_clean_exit"),
+            hex_uppercase: cli.is_present("hex_uppercase"),
+            json: cli.is_present("json"),
+            quiet: cli.is_present("quiet"),
+            assume_yes: cli.is_present("yes"),
+            verbosity: cli.occurrences_of("verbose"),
+            file_read_retries: file_read_retries.unwrap_or_default(),
+            usb_retries: usb_retries.unwrap_or_default(),
+            chunk_size: chunk_size.unwrap_or_default(),
+            scratch_region: scratch_region.unwrap_or_default(),
+            keep_going: cli.is_present("keep_going"),
+            exit_zero_on_no_device: cli.is_present("exit_zero_on_no_device"),
+            dump_registers_on_error: cli.is_present("dump_registers_on_error"),
+            diagnostic_registers: diagnostic_registers.unwrap_or_default(),
+            summary_json: cli.value_of("summary_json").map(PathBuf::from),
         })
     }
 
-    /// Gets the USB bus and address of the FEL device if provided in the CLI.
-    pub fn get_device(&self) -> Option<(u8, u8)> {
-        self.device
+    /// Gets the USB bus and address of the FEL devices provided in the CLI.
+    ///
+    /// If it's empty, the first available device should be used.
+    pub fn get_devices(&self) -> &[(u8, u8)] {
+        &self.devices
+    }
+
+    /// Gets the preferred FEL device, by USB bus and address or by serial number, if provided in
+    /// the CLI.
+    pub fn get_preferred_device(&self) -> Option<&DeviceSelector> {
+        self.preferred_device.as_ref()
+    }
+
+    /// Checks if the command should run concurrently in all the connected FEL devices.
+    pub fn all_devices(&self) -> bool {
+        self.all_devices
+    }
+
+    /// Gets the USB serial number of the FEL device if provided in the CLI.
+    pub fn get_serial(&self) -> Option<&str> {
+        self.serial.as_ref().map(AsRef::as_ref)
     }
 
     /// Gets the command used in the CLI.
@@ -74,311 +780,2447 @@ impl Config {
         self.command.as_ref()
     }
 
-    /// Gets the device information from the CLI.
-    fn get_device_from_cli(cli: &ArgMatches) -> Result<Option<(u8, u8)>, Error> {
-        Ok(match cli.value_of("device") {
-            Some(device_str) => {
-                let mut split = device_str.split(':');
-                let bus = split.next();
-                let addr = split.next();
-                if let (Some(bus), Some(addr), None) = (bus, addr, split.next()) {
-                    Some((
-                        bus.parse::<u8>().context(CliError {
-                            description: format!(
-                                "bus number must be an integer between 0 and {}",
-                                u8::max_value()
-                            ),
-                        })?,
-                        addr.parse::<u8>().context(CliError {
-                            description: format!(
-                                "device address must be an integer between 0 and {}",
-                                u8::max_value()
-                            ),
-                        })?,
-                    ))
-                } else {
-                    return Err(CliError {
-                        description: "Device must be in `bus:addr` format".to_owned(),
-                    }
-                    .into());
-                }
-            }
-            None => None,
-        })
+    /// Checks if the command should only be validated, without executing it.
+    pub fn is_validate_only(&self) -> bool {
+        self.validate_only
     }
 
-    /// Gets the command used in te CLI.
-    fn get_command_from_cli(cli: &ArgMatches) -> Result<Option<Command>, Error> {
-        if let Some(spl) = cli.subcommand_matches("spl") {
-            let file = PathBuf::from(spl.value_of("file").unwrap());
-            if file.exists() {
-                Ok(Some(Command::Uboot {
-                    file,
-                    start_uboot: spl.is_present("exec"),
-                }))
-            } else {
-                Err(CliError {
-                    description: format!("the file '{}' does not exist", file.display()),
-                }
-                .into())
-            }
-        } else if let Some(dump) = cli.subcommand_matches("dump") {
-            if dump.is_present("sid") {
-                Ok(Some(Command::Dump {
-                    address: None,
-                    size: None,
-                    hex: false,
-                    sid: true,
-                    out: None,
-                }))
-            } else {
-                let addr_str = dump.value_of("addr").unwrap();
-                let addr = if addr_str.starts_with("0x") {
-                    u32::from_str_radix(addr_str.trim_left_matches("0x"), 16)
-                } else {
-                    u32::from_str_radix(addr_str, 10)
+    /// Checks if the command should only print what it would do, without touching the device.
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Checks if the dry run should not require a FEL device to be connected.
+    pub fn no_device(&self) -> bool {
+        self.no_device
+    }
+
+    /// Checks if the command should wait until a FEL device is connected.
+    pub fn wait_for_device(&self) -> bool {
+        self.wait
+    }
+
+    /// Gets the maximum number of seconds to wait for a FEL device, if any.
+    pub fn get_wait_timeout_secs(&self) -> Option<u64> {
+        self.wait_timeout_secs
+    }
+
+    /// Checks if the SID of the device should be printed after the command output.
+    pub fn show_sid(&self) -> bool {
+        self.show_sid
+    }
+
+    /// Checks if the time and throughput of memory transfers should be printed.
+    pub fn show_stats(&self) -> bool {
+        self.stats
+    }
+
+    /// Checks if the USB link should be primed before running the command.
+    pub fn prewarm(&self) -> bool {
+        self.prewarm
+    }
+
+    /// Checks if the device should be checked to still be in FEL mode after running the command.
+    pub fn require_clean_exit(&self) -> bool {
+        self.require_clean_exit
+    }
+
+    /// Checks if hexadecimal output should use uppercase digits.
+    pub fn is_hex_uppercase(&self) -> bool {
+        self.hex_uppercase
+    }
+
+    /// Checks if the results of the command should be printed in JSON format.
+    pub fn is_json(&self) -> bool {
+        self.json
+    }
+
+    /// Checks if the success, status and progress messages of the command should not be printed.
+    pub fn is_quiet(&self) -> bool {
+        self.quiet
+    }
+
+    /// Checks if destructive operations were already confirmed in the CLI.
+    pub fn assume_yes(&self) -> bool {
+        self.assume_yes
+    }
+
+    /// Gets the verbosity level of the logs, from 0 (only warnings) to 3 (everything).
+    pub fn get_verbosity(&self) -> u64 {
+        self.verbosity
+    }
+
+    /// Gets the number of times a failed input file read should be retried.
+    pub fn get_file_read_retries(&self) -> u32 {
+        self.file_read_retries
+    }
+
+    /// Gets the number of times a memory operation failed because of a USB error should be retried.
+    pub fn get_usb_retries(&self) -> u32 {
+        self.usb_retries
+    }
+
+    /// Gets the size of the chunks in which memory is read from and written to the device.
+    pub fn get_chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Gets the base address and size of the region where scratch buffers are allocated, if it's
+    /// not chosen automatically.
+    pub fn get_scratch_region(&self) -> Option<(u32, u32)> {
+        self.scratch_region
+    }
+
+    /// Checks if the command should continue with the next device if it fails in one of them.
+    pub fn keep_going(&self) -> bool {
+        self.keep_going
+    }
+
+    /// Checks if not finding any FEL device should be considered a success.
+    pub fn exit_zero_on_no_device(&self) -> bool {
+        self.exit_zero_on_no_device
+    }
+
+    /// Checks if the diagnostic registers should be printed when a mutating command fails.
+    pub fn dump_registers_on_error(&self) -> bool {
+        self.dump_registers_on_error
+    }
+
+    /// Gets the addresses of the diagnostic registers provided in the CLI.
+    ///
+    /// If it's empty, the default registers of the SoC should be used.
+    pub fn get_diagnostic_registers(&self) -> &[u32] {
+        &self.diagnostic_registers
+    }
+
+    /// Gets the path of the JSON summary file if provided in the CLI.
+    pub fn get_summary_json(&self) -> Option<&Path> {
+        self.summary_json.as_ref().map(AsRef::as_ref)
+    }
+
+    /// Gets the options of the execution of the command in each device.
+    pub fn get_options(&self) -> Result<Options, Error> {
+        Ok(Options::default()
+            .with_usb_retries(self.usb_retries)
+            .with_chunk_size(self.chunk_size)?
+            .with_scratch_region(self.scratch_region)
+            .with_file_read_retries(self.file_read_retries)
+            .with_progress(!self.quiet)
+            .with_stats(self.stats)
+            .with_hex_uppercase(self.hex_uppercase)
+            .with_prewarm(self.prewarm)
+            .with_sid(self.show_sid)
+            .with_clean_exit(self.require_clean_exit)
+            .with_registers_on_error(
+                self.dump_registers_on_error,
+                self.diagnostic_registers.clone(),
+            ))
+    }
+
+    /// Checks that all the numeric arguments given in the CLI have an explicit radix prefix.
+    fn check_radix_prefixes(cli: &ArgMatches) -> Result<(), Error> {
+        let mut values = Vec::new();
+        if let Some(registers_str) = cli.value_of("diagnostic_registers") {
+            values.extend(registers_str.split(','));
+        }
+        if let Some(region_str) = cli.value_of("scratch_region").filter(|&s| s != "auto") {
+            values.extend(region_str.split(':').next());
+        }
+        if let (sub_name, Some(sub)) = cli.subcommand() {
+            let args: &[&str] = match sub_name {
+                "dump" => &["addr", "size", "align_output", "pad_byte"],
+                "write" => &["write_data", "offset", "length"],
+                "spl" => &["entry"],
+                "exec" => &[
+                    "addr",
+                    "arg0",
+                    "arg1",
+                    "arg2",
+                    "arg3",
+                    "wait_addr",
+                    "wait_value",
+                ],
+                "reset" | "reset64" | "watch" => &["addr"],
+                "crc32" => &["addr", "size", "expected"],
+                "hash" | "memtest" => &["addr", "size"],
+                "compare" => &["addr_a", "addr_b", "size"],
+                "copy" => &["src", "dst", "size"],
+                "clear" => &["addr", "num_bytes"],
+                "fill" => &["addr", "num_bytes", "fill_byte"],
+                _ => &[],
+            };
+            for arg in args {
+                // Default values are not given by the user, so they are not checked.
+                if sub.occurrences_of(arg) > 0 {
+                    values.extend(sub.values_of(arg).unwrap());
                 }
-                .context(CliError {
+            }
+        }
+        for value in values {
+            if !value.is_empty() && value.chars().all(|c| c.is_ascii_digit() || c == '_') {
+                return Err(CliError {
                     description: format!(
-                        "memory address must be an integer from 0x00000000 to {:#010x}",
-                        u32::max_value()
+                        "the numeric argument '{}' has no radix prefix, and `--strict-hex` \
+                         requires one (`0x`, `0o` or `0b`)",
+                        value
                     ),
-                })?;
-                let size = if let Some(size_str) = dump.value_of("size") {
-                    let size = if size_str.starts_with("0x") {
-                        u32::from_str_radix(size_str.trim_left_matches("0x"), 16)
-                    } else {
-                        u32::from_str_radix(size_str, 10)
-                    }
-                    .context(CliError {
-                        description: format!(
-                            "dump size must be an integer from 0x00000000 to {:#010x} (the \
-                             maximum size starting from the given address)",
-                            (u32::max_value() - addr).saturating_add(1)
-                        ),
-                    })?;
-                    if size > (u32::max_value() - addr).saturating_add(1) {
-                        return Err(CliError {
-                            description: format!(
-                                "dump size must be an integer from 0x00000000 to {:#010x} (the \
-                                 maximum size starting from the given address)",
-                                (u32::max_value() - addr).saturating_add(1)
-                            ),
-                        }
-                        .into());
-                    }
-                    Some(size)
-                } else {
-                    None
-                };
-                Ok(Some(Command::Dump {
-                    address: Some(addr),
-                    size,
-                    hex: dump.is_present("hex"),
-                    sid: false,
-                    out: dump.value_of("out").map(PathBuf::from),
-                }))
-            }
-        } else if let Some(write) = cli.subcommand_matches("write") {
-            let mut value_iter = write.values_of("write_data").unwrap();
-            let writes = (write.occurrences_of("write_data") / 2) as usize;
-            let mut addresses = Vec::with_capacity(writes);
-            let mut data = Vec::with_capacity(writes);
-            for _ in 0..writes {
-                let addr_str = value_iter.next().unwrap();
-                let addr = if addr_str.starts_with("0x") {
-                    u32::from_str_radix(addr_str.trim_left_matches("0x"), 16)
-                } else {
-                    u32::from_str_radix(addr_str, 10)
                 }
-                .context(CliError {
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Gets the device information from the CLI, the `FEL_DEVICE` environment variable or the
+    /// configuration file, in that order of precedence.
+    ///
+    /// The default devices are not used if a preferred device, a serial number or all the devices
+    /// are requested in the CLI.
+    fn get_devices_from_cli(
+        cli: &ArgMatches,
+        file_config: &FileConfig,
+    ) -> Result<Vec<(u8, u8)>, Error> {
+        match cli.value_of("device") {
+            Some(devices_str) => devices_str.split(',').map(Self::parse_device).collect(),
+            None if cli.is_present("prefer_device")
+                || cli.is_present("serial")
+                || cli.is_present("all_devices") =>
+            {
+                Ok(Vec::new())
+            }
+            None => match (env::var(DEVICE_ENV_VAR), &file_config.device) {
+                (Ok(devices_str), _) => devices_str.split(',').map(Self::parse_device).collect(),
+                (Err(_), Some(devices_str)) => Ok(devices_str
+                    .split(',')
+                    .map(Self::parse_device)
+                    .collect::<Result<_, _>>()
+                    .context("invalid device in the configuration file")?),
+                (Err(_), None) => Ok(Vec::new()),
+            },
+        }
+    }
+
+    /// Gets the preferred device from the CLI.
+    ///
+    /// Devices in `bus:addr` format are selected by USB bus and address, and any other value is a
+    /// USB serial number.
+    fn get_preferred_device_from_cli(cli: &ArgMatches) -> Result<Option<DeviceSelector>, Error> {
+        match cli.value_of("prefer_device") {
+            Some(device_str) if device_str.contains(':') => {
+                let (bus, addr) = Self::parse_device(device_str)?;
+                Ok(Some(DeviceSelector::BusAddr(bus, addr)))
+            }
+            Some("") => Err(CliError {
+                description: "the preferred device can't be empty".to_owned(),
+            }
+            .into()),
+            Some(serial) => Ok(Some(DeviceSelector::Serial(serial.to_owned()))),
+            None => Ok(None),
+        }
+    }
+
+    /// Parses a device selector in `bus:addr` format.
+    fn parse_device(device_str: &str) -> Result<(u8, u8), Error> {
+        let mut split = device_str.split(':');
+        let bus = split.next();
+        let addr = split.next();
+        if let (Some(bus), Some(addr), None) = (bus, addr, split.next()) {
+            Ok((
+                bus.parse::<u8>().context(CliError {
                     description: format!(
-                        "memory address must be an integer from 0x00000000 to {:#010x}, given \
-                         '{}'",
-                        u32::max_value(),
-                        addr_str
+                        "bus number must be an integer between 0 and {}",
+                        u8::max_value()
                     ),
-                })?;
-                let value_str = value_iter.next().unwrap();
-                let word = if value_str.starts_with("0x") {
-                    u32::from_str_radix(value_str.trim_left_matches("0x"), 16)
-                } else {
-                    u32::from_str_radix(value_str, 10)
-                };
-                let final_value = match word {
-                    Ok(word) => {
-                        if u32::max_value() - 4 >= addr {
-                            WriteData::Word(word)
-                        } else {
-                            let err_msg = format!(
-                                "cannot write a complete word at address {:#010x}, it would write \
-                                 past the end of the memory address space (limit: {:#010x})",
-                                addr,
-                                u32::max_value()
-                            );
-                            return Err(CliError {
-                                description: err_msg,
-                            }
-                            .into());
-                        }
-                    }
-                    Err(e) => {
-                        let path = PathBuf::from(value_str);
-                        if path.exists() {
-                            let metadata =
-                                path.metadata().context("could not read file metadata")?;
-                            let max_bytes = u64::from((u32::max_value() - addr).saturating_add(1));
-                            if metadata.len() > max_bytes {
-                                let err_msg = format!(
-                                    "the file '{}' is too big. The maximum file size to write to \
-                                     address {:#010x} is {} bytes, but the file had {} bytes",
-                                    path.display(),
-                                    addr,
-                                    max_bytes,
-                                    metadata.len()
-                                );
-                                return Err(CliError {
-                                    description: err_msg,
-                                }
-                                .into());
-                            }
-                            WriteData::File(Box::new(path))
-                        } else {
-                            return Err(CliError {
-                                description: format!(
-                                "the file '{}' does not exist.\nNote: If you were trying to \
-                                 provide a value, the integer conversion failed with this error: \
-                                 {}",
-                                path.display(),
-                                e
-                            ),
-                            }
-                            .into());
-                        }
-                    }
-                };
-                addresses.push(addr);
-                data.push(final_value);
-            }
-            Ok(Some(Command::Write { addresses, data }))
-        } else if let Some(exec) = cli.subcommand_matches("exec") {
-            let addr_str = exec.value_of("addr").unwrap();
-            let addr = if addr_str.starts_with("0x") {
-                u32::from_str_radix(addr_str.trim_left_matches("0x"), 16)
-            } else {
-                u32::from_str_radix(addr_str, 10)
+                })?,
+                addr.parse::<u8>().context(CliError {
+                    description: format!(
+                        "device address must be an integer between 0 and {}",
+                        u8::max_value()
+                    ),
+                })?,
+            ))
+        } else {
+            Err(CliError {
+                description: "Device must be in `bus:addr` format".to_owned(),
             }
+            .into())
+        }
+    }
+
+    /// Gets the number of file read retries from the CLI.
+    fn get_file_read_retries_from_cli(cli: &ArgMatches) -> Result<u32, Error> {
+        Ok(cli
+            .value_of("retry_file_read")
+            .unwrap()
+            .parse::<u32>()
             .context(CliError {
                 description: format!(
-                    "memory address must be an integer from 0x00000000 to {:#010x}, given '{}'",
-                    u32::max_value(),
-                    addr_str
+                    "the number of file read retries must be an integer between 0 and {}",
+                    u32::max_value()
                 ),
-            })?;
-            Ok(Some(Command::Execute { address: addr }))
-        } else if let Some(reset64) = cli.subcommand_matches("reset64") {
-            let addr_str = reset64.value_of("addr").unwrap();
-            let addr = if addr_str.starts_with("0x") {
-                u32::from_str_radix(addr_str.trim_left_matches("0x"), 16)
-            } else {
-                u32::from_str_radix(addr_str, 10)
-            }
-            .context(CliError {
-                description: format!(
-                    "memory address must be an integer from 0x00000000 to {:#010x}, given '{}'",
+            })?)
+    }
+
+    /// Gets the size of the chunks in which memory is transferred from the CLI.
+    fn get_chunk_size_from_cli(cli: &ArgMatches) -> Result<usize, Error> {
+        let size_str = cli.value_of("chunk_size").unwrap();
+        let size = parse_size(size_str).context(CliError {
+            description: format!("the chunk size '{}' is not a valid size", size_str),
+        })?;
+        CliError::check_chunk_size(u64::from(size))
+    }
+
+    /// Gets the base address and size of the scratch region from the CLI, or `None` if it's
+    /// chosen automatically.
+    fn get_scratch_region_from_cli(cli: &ArgMatches) -> Result<Option<(u32, u32)>, Error> {
+        let region_str = cli.value_of("scratch_region").unwrap();
+        if region_str == "auto" {
+            return Ok(None);
+        }
+        let description = format!(
+            "the scratch region must be `auto` or a base address and a non-zero size that fit in \
+             the memory address space, such as 0x40000000:1MiB, given '{}'",
+            region_str
+        );
+        let mut parts = region_str.splitn(2, ':');
+        let (address, size) = match (parts.next(), parts.next()) {
+            (Some(address_str), Some(size_str)) => (
+                parse_u32(address_str).context(CliError {
+                    description: description.clone(),
+                })?,
+                parse_size(size_str).context(CliError {
+                    description: description.clone(),
+                })?,
+            ),
+            _ => return Err(CliError { description }.into()),
+        };
+        if size == 0 || u64::from(address) + u64::from(size) > u64::from(u32::max_value()) + 1 {
+            return Err(CliError { description }.into());
+        }
+        Ok(Some((address, size)))
+    }
+
+    /// Gets the addresses of the diagnostic registers from the CLI.
+    fn get_diagnostic_registers_from_cli(cli: &ArgMatches) -> Result<Vec<u32>, Error> {
+        let mut registers = Vec::new();
+        if let Some(registers_str) = cli.value_of("diagnostic_registers") {
+            for addr_str in registers_str.split(',') {
+                let addr = parse_u32(addr_str).context(CliError {
+                    description: format!(
+                        "register address must be an integer from 0x00000000 to {:#010x}, given \
+                         '{}'",
+                        u32::max_value(),
+                        addr_str
+                    ),
+                })?;
+                registers.push(addr);
+            }
+        }
+        Ok(registers)
+    }
+
+    /// Gets the command used in te CLI, with the defaults of the configuration file.
+    fn get_command_from_cli(
+        cli: &ArgMatches,
+        file_config: &FileConfig,
+    ) -> Result<Option<Command>, Error> {
+        let command = match cli.subcommand() {
+            ("spl", Some(spl)) => Self::get_spl_from_cli(spl)?,
+            ("dump", Some(dump)) if dump.is_present("sid") => {
+                Self::get_sid_dump_from_cli(cli, dump)?
+            }
+            ("dump", Some(dump)) => Self::get_dump_from_cli(dump, file_config)?,
+            ("write", Some(write)) => Self::get_write_from_cli(write, file_config)?,
+            ("exec", Some(exec)) => Self::get_exec_from_cli(exec)?,
+            ("reset", Some(reset)) => Command::Reset {
+                address: match reset.value_of("addr") {
+                    Some(addr_str) => Some(parse_integer_address(addr_str)?),
+                    None => None,
+                },
+            },
+            ("reset64", Some(reset64)) => Command::Reset64 {
+                address: parse_integer_address(reset64.value_of("addr").unwrap())?,
+            },
+            ("version", Some(version)) => Command::Version {
+                json: version.is_present("json"),
+            },
+            ("map", _) => Command::Map,
+            ("meminfo", _) => Command::MemInfo,
+            ("list", Some(list)) => Command::List {
+                json: list.is_present("json"),
+            },
+            ("completions", Some(completions)) => Command::Completions {
+                shell: completions.value_of("shell").unwrap().parse().unwrap(),
+            },
+            ("crc32", Some(crc32)) => Self::get_crc32_from_cli(crc32)?,
+            ("hash", Some(hash)) => Self::get_hash_from_cli(hash)?,
+            ("compare", Some(compare)) => Self::get_compare_from_cli(compare)?,
+            ("copy", Some(copy)) => Self::get_copy_from_cli(copy)?,
+            ("watch", Some(watch)) => Self::get_watch_from_cli(watch)?,
+            ("replay", Some(replay)) => Command::Replay {
+                file: PathBuf::from(replay.value_of("file").unwrap()),
+            },
+            ("clear", Some(clear)) => Self::get_clear_from_cli(clear)?,
+            ("fill", Some(fill)) => Self::get_fill_from_cli(fill)?,
+            ("memtest", Some(memtest)) => Self::get_memtest_from_cli(memtest)?,
+            _ => return Ok(None),
+        };
+        Ok(Some(command))
+    }
+
+    /// Gets the `spl` command from the CLI.
+    fn get_spl_from_cli(spl: &ArgMatches) -> Result<Command, Error> {
+        let file = PathBuf::from(spl.value_of("file").unwrap());
+        let entry = match spl.value_of("entry") {
+            Some(entry_str) => Some(parse_address(entry_str)?),
+            None => None,
+        };
+        if file.exists() {
+            Ok(Command::Uboot {
+                file,
+                // `--no-exec` conflicts with `--exec`, and it's the default.
+                start_uboot: spl.is_present("exec") && !spl.is_present("no_exec"),
+                entry,
+                allow_small: spl.is_present("allow_small"),
+                check_dram: spl.is_present("check_dram"),
+                verify: spl.is_present("verify"),
+            })
+        } else {
+            Err(CliError {
+                description: format!("the file '{}' does not exist", file.display()),
+            }
+            .into())
+        }
+    }
+
+    /// Gets the output file and its compression of the `dump` command from the CLI.
+    ///
+    /// The extension of the compression is added to the output file if it doesn't have it.
+    fn get_dump_out_from_cli(dump: &ArgMatches) -> (Option<PathBuf>, Option<Compression>) {
+        let compress = match dump.value_of("compress") {
+            Some("gzip") => Some(Compression::Gzip),
+            Some("xz") => Some(Compression::Xz),
+            _ => None,
+        };
+        let out = dump.value_of("out").map(|out_str| match compress {
+            Some(compress) if !out_str.ends_with(&format!(".{}", compress.extension())) => {
+                PathBuf::from(format!("{}.{}", out_str, compress.extension()))
+            }
+            _ => PathBuf::from(out_str),
+        });
+        (out, compress)
+    }
+
+    /// Gets the `dump --sid` command from the CLI.
+    fn get_sid_dump_from_cli(cli: &ArgMatches, dump: &ArgMatches) -> Result<Command, Error> {
+        let (out, compress) = Self::get_dump_out_from_cli(dump);
+        let sid_format = match dump.value_of("sid_format") {
+            Some("hex") => SidFormat::Hex,
+            Some("raw") => SidFormat::Raw,
+            Some("json") => SidFormat::Json,
+            None if cli.is_present("json") => SidFormat::Json,
+            _ => SidFormat::Colon,
+        };
+        if sid_format != SidFormat::Raw && (out.is_some() || dump.is_present("force")) {
+            return Err(CliError {
+                description: "`--out` and `--force` can only be used with `--sid` in the `raw` \
+                              SID format"
+                    .to_owned(),
+            }
+            .into());
+        }
+        Ok(Command::Dump {
+            address: None,
+            size: None,
+            region: None,
+            words: 1,
+            endian: Endianness::Little,
+            hex: false,
+            width: HEX_DUMP_LINE,
+            color: ColorMode::Never,
+            sid: true,
+            sid_format,
+            out,
+            format: DataFormat::Binary,
+            array_name: DEFAULT_ARRAY_NAME.to_owned(),
+            append: dump.is_present("append"),
+            force: dump.is_present("force"),
+            compress,
+            align_output: None,
+            pad_byte: 0,
+            read_retries: 0,
+            partial_ok: false,
+            count: 1,
+            interval_ms: 0,
+        })
+    }
+
+    /// Gets the memory `dump` command from the CLI, with the defaults of the configuration file.
+    fn get_dump_from_cli(dump: &ArgMatches, file_config: &FileConfig) -> Result<Command, Error> {
+        let (out, compress) = Self::get_dump_out_from_cli(dump);
+        let region = match dump.value_of("region") {
+            Some("sram") => Some(MemoryRegion::Sram),
+            Some("sram-a1") => Some(MemoryRegion::SramA1),
+            Some("sram-c") => Some(MemoryRegion::SramC),
+            _ => None,
+        };
+        // The address of a region is only known once the device SoC is.
+        let address = match dump.value_of("addr") {
+            Some(addr_str) => Some(parse_address(addr_str)?),
+            None => None,
+        };
+        // Named addresses are checked again once resolved.
+        let addr = address.map_or(0, |address| address.get_offset());
+        let size = if let Some(size_str) = dump.value_of("size") {
+            Some(parse_region_size(size_str, addr, "dump")?)
+        } else {
+            None
+        };
+        if dump.is_present("format") && size.is_none() && region.is_none() {
+            return Err(CliError {
+                description: "`--format` requires the size or the region to dump".to_owned(),
+            }
+            .into());
+        }
+        let words = Self::get_dump_words_from_cli(dump, addr)?;
+        let color = match dump.value_of("color") {
+            Some("always") => ColorMode::Always,
+            Some("never") => ColorMode::Never,
+            Some(_) => ColorMode::Auto,
+            None => file_config.color.unwrap_or(ColorMode::Auto),
+        };
+        let format = match dump.value_of("format") {
+            Some("intel-hex") => DataFormat::IntelHex,
+            Some("srec") => DataFormat::Srec,
+            Some("carray") => DataFormat::CArray,
+            _ => DataFormat::Binary,
+        };
+        let array_name = Self::get_array_name_from_cli(dump, format)?;
+        let append = dump.is_present("append");
+        if append && (format == DataFormat::IntelHex || format == DataFormat::Srec) {
+            return Err(CliError {
+                description: "`--append` cannot be used with the `intel-hex` and `srec` formats, \
+                              since their end records would end up in the middle of the file"
+                    .to_owned(),
+            }
+            .into());
+        }
+        let (align_output, pad_byte) = Self::get_dump_alignment_from_cli(dump)?;
+        let read_retries = match dump.value_of("read_retries") {
+            Some(retries_str) => retries_str.parse::<u32>().context(CliError {
+                description: format!(
+                    "the number of chunk read retries must be an integer between 0 and {}",
+                    u32::max_value()
+                ),
+            })?,
+            None => 0,
+        };
+        let (count, interval_ms) = Self::get_dump_count_from_cli(dump)?;
+        Ok(Command::Dump {
+            address,
+            size,
+            region,
+            words,
+            endian: Endianness::from_cli(dump),
+            hex: dump.is_present("hex"),
+            width: dump
+                .value_of("width")
+                .map_or(file_config.dump_width.unwrap_or(HEX_DUMP_LINE), |width| {
+                    width.parse().unwrap()
+                }),
+            color,
+            sid: false,
+            sid_format: SidFormat::Colon,
+            out,
+            format,
+            array_name,
+            append,
+            force: dump.is_present("force"),
+            compress,
+            align_output,
+            pad_byte,
+            read_retries,
+            partial_ok: dump.is_present("partial_ok"),
+            count,
+            interval_ms,
+        })
+    }
+
+    /// Gets the number of dumps that the `dump` command takes and the interval between them, in
+    /// milliseconds, from the CLI.
+    fn get_dump_count_from_cli(dump: &ArgMatches) -> Result<(u32, u64), Error> {
+        let count = match dump.value_of("count") {
+            // A single dump is taken with a count of 0.
+            Some(count_str) => count_str.parse::<u32>().context(CliError {
+                description: format!(
+                    "the number of dumps must be an integer between 0 and {}",
+                    u32::max_value()
+                ),
+            })?,
+            None => 1,
+        }
+        .max(1);
+        let interval_ms = match dump.value_of("interval") {
+            Some(interval_str) => interval_str.parse::<u64>().context(CliError {
+                description: format!(
+                    "the interval must be an integer between 0 and {} milliseconds",
+                    u64::max_value()
+                ),
+            })?,
+            None => 0,
+        };
+        Ok((count, interval_ms))
+    }
+
+    /// Gets the number of words that the `dump` command reads at the given address from the CLI.
+    fn get_dump_words_from_cli(dump: &ArgMatches, addr: u32) -> Result<u32, Error> {
+        let words_str = match dump.value_of("words") {
+            Some(words_str) => words_str,
+            None => return Ok(1),
+        };
+        let words = words_str.parse::<u32>().context(CliError {
+            description: format!(
+                "the number of words must be an integer between 1 and {}",
+                u32::max_value()
+            ),
+        })?;
+        if words == 0 {
+            return Err(CliError {
+                description: "the number of words must be at least 1".to_owned(),
+            }
+            .into());
+        }
+        if u64::from(addr) + 4 * u64::from(words) > u64::from(TRANSFER_END) {
+            return Err(CliError {
+                description: format!(
+                    "reading {} words at {:#010x} goes past {:#010x}, the end of the memory that \
+                     can be read",
+                    words, addr, TRANSFER_END
+                ),
+            }
+            .into());
+        }
+        Ok(words)
+    }
+
+    /// Gets the name of the C array of a dump in the given format from the CLI.
+    fn get_array_name_from_cli(dump: &ArgMatches, format: DataFormat) -> Result<String, Error> {
+        match dump.value_of("array_name") {
+            Some(_) if format != DataFormat::CArray => Err(CliError {
+                description: "`--array-name` can only be used with the `carray` format".to_owned(),
+            }
+            .into()),
+            Some(name) if !carray::is_identifier(name) => Err(CliError {
+                description: format!(
+                    "the array name must be a valid C identifier, given '{}'",
+                    name
+                ),
+            }
+            .into()),
+            Some(name) => Ok(name.to_owned()),
+            None => Ok(DEFAULT_ARRAY_NAME.to_owned()),
+        }
+    }
+
+    /// Gets the alignment of the output of the `dump` command and the byte it's padded with from
+    /// the CLI.
+    fn get_dump_alignment_from_cli(dump: &ArgMatches) -> Result<(Option<u32>, u8), Error> {
+        let align_output = if let Some(align_str) = dump.value_of("align_output") {
+            let align = parse_size(align_str).context(CliError {
+                description: format!(
+                    "the output alignment must be an integer from 0x00000001 to {:#010x}, given \
+                     '{}'",
                     u32::max_value(),
-                    addr_str
+                    align_str
                 ),
             })?;
-            Ok(Some(Command::Reset64 { address: addr }))
-        } else if cli.subcommand_matches("version").is_some() {
-            Ok(Some(Command::Version))
-        } else if let Some(clear) = cli.subcommand_matches("clear") {
-            let addr_str = clear.value_of("addr").unwrap();
-            let address = if addr_str.starts_with("0x") {
-                u32::from_str_radix(addr_str.trim_left_matches("0x"), 16)
-            } else {
-                u32::from_str_radix(addr_str, 10)
+            if align == 0 {
+                return Err(CliError {
+                    description: "the output alignment must be at least 1 byte".to_owned(),
+                }
+                .into());
             }
-            .context(CliError {
+            Some(align)
+        } else {
+            None
+        };
+        let pad_byte = if let Some(pad_byte_str) = dump.value_of("pad_byte") {
+            parse_u8(pad_byte_str).context(CliError {
+                description: format!(
+                    "the padding byte must be an integer from 0x00 to {:#04x}, given '{}'",
+                    u8::max_value(),
+                    pad_byte_str
+                ),
+            })?
+        } else {
+            0
+        };
+        Ok((align_output, pad_byte))
+    }
+
+    /// Gets the `write` command from the CLI, with the defaults of the configuration file.
+    fn get_write_from_cli(write: &ArgMatches, file_config: &FileConfig) -> Result<Command, Error> {
+        let format = match write.value_of("format") {
+            Some("binary") => Some(DataFormat::Binary),
+            Some("intel-hex") => Some(DataFormat::IntelHex),
+            Some("srec") => Some(DataFormat::Srec),
+            _ => None,
+        };
+        let file_offset = match write.value_of("offset") {
+            Some(offset_str) => parse_size(offset_str).context(CliError {
                 description: format!(
-                    "memory address must be an integer from 0x00000000 to {:#010x}, given '{}'",
+                    "the file offset must be an integer from 0x00000000 to {:#010x}, given '{}'",
                     u32::max_value(),
-                    addr_str
+                    offset_str
                 ),
-            })?;
-            let num_bytes_str = clear.value_of("num_bytes").unwrap();
-            let num_bytes = if num_bytes_str.starts_with("0x") {
-                u32::from_str_radix(num_bytes_str.trim_left_matches("0x"), 16)
-            } else {
-                u32::from_str_radix(num_bytes_str, 10)
+            })?,
+            None => 0,
+        };
+        let file_length = match write.value_of("length") {
+            Some(length_str) => Some(parse_size(length_str).context(CliError {
+                description: format!(
+                    "the number of bytes to write from the files must be an integer from \
+                     0x00000000 to {:#010x}, given '{}'",
+                    u32::max_value(),
+                    length_str
+                ),
+            })?),
+            None => None,
+        };
+        let values = write.values_of("write_data").unwrap().collect::<Vec<_>>();
+        let (addresses, data) =
+            Self::get_write_list_from_cli(&values, format, (file_offset, file_length))?;
+        let stdin_writes = data
+            .iter()
+            .filter(|data| match **data {
+                WriteData::Stdin => true,
+                _ => false,
+            })
+            .count();
+        if stdin_writes > 1 {
+            return Err(CliError {
+                description: format!(
+                    "stdin (`{}`) can only be written once, but it was given {} times",
+                    STDIN_FILE, stdin_writes
+                ),
+            }
+            .into());
+        }
+        let has_files = data.iter().any(|data| match *data {
+            WriteData::File(_) => true,
+            _ => false,
+        });
+        if !has_files && (file_offset != 0 || file_length.is_some()) {
+            return Err(CliError {
+                description: "`--offset` and `--length` require a raw binary file to write"
+                    .to_owned(),
+            }
+            .into());
+        }
+        let byte_swap = write
+            .value_of("byteswap")
+            .map(|width| width.parse().unwrap());
+        let has_addressed_files = data.iter().any(|data| match *data {
+            WriteData::IntelHex(_) | WriteData::Srec(_) => true,
+            _ => false,
+        });
+        if byte_swap.is_some() && !has_addressed_files {
+            return Err(CliError {
+                description: "`--byteswap` requires an Intel HEX or S-record file to write"
+                    .to_owned(),
+            }
+            .into());
+        }
+        let repeat = Self::get_write_repeat_from_cli(write, &addresses, &data)?;
+        Ok(Command::Write {
+            addresses,
+            data,
+            file_offset,
+            file_length,
+            endian: Endianness::from_cli(write),
+            repeat,
+            byte_swap,
+            merge_writes: write.is_present("merge_writes"),
+            verify: write.is_present("verify")
+                || (!write.is_present("no_verify") && file_config.verify_on_write.unwrap_or(false)),
+            summary: write.is_present("summary"),
+        })
+    }
+
+    /// Gets the addresses and the data of the given values of the `write` command.
+    ///
+    /// All the problems of the list are reported at once.
+    fn get_write_list_from_cli(
+        values: &[&str],
+        format: Option<DataFormat>,
+        file_range: (u32, Option<u32>),
+    ) -> Result<(Vec<Address>, Vec<WriteData>), Error> {
+        let mut addresses = Vec::with_capacity(values.len() / 2);
+        let mut data = Vec::with_capacity(values.len() / 2);
+        let mut problems = Vec::new();
+        let mut i = 0;
+        while i < values.len() {
+            // Files with their own addresses can be given without a base address.
+            let path = Path::new(values[i]);
+            if parse_address(values[i]).is_err() && path.exists() {
+                if let Some(file) = addressed_file(path, file_format(path, format)) {
+                    addresses.push(Address::absolute(0));
+                    data.push(file);
+                    i += 1;
+                    continue;
+                }
+            }
+            match values.get(i + 1) {
+                Some(value_str) => {
+                    match Self::get_write_pair_from_cli(values[i], value_str, format, file_range) {
+                        Ok((addr, final_value)) => {
+                            addresses.push(addr);
+                            data.push(final_value);
+                        }
+                        Err(e) => problems.push(e),
+                    }
+                }
+                // Only Intel HEX and S-record files can go without address, so the rest of the
+                // values must come in pairs.
+                None => {
+                    let description = if parse_address(values[i]).is_ok() {
+                        format!(
+                            "`write` requires address and value pairs, but the last of the {} \
+                             given values is unpaired: missing the word or file to write at \
+                             address '{}'",
+                            values.len(),
+                            values[i]
+                        )
+                    } else {
+                        format!(
+                            "`write` requires address and value pairs, but the last of the {} \
+                             given values is unpaired: '{}' is neither an address nor an Intel \
+                             HEX or S-record file",
+                            values.len(),
+                            values[i]
+                        )
+                    };
+                    problems.push(CliError { description }.into());
+                }
+            }
+            i += 2;
+        }
+        CliError::check_problems(problems, "the write list")?;
+        Ok((addresses, data))
+    }
+
+    /// Gets the number of times the words of the `write` command are written from the CLI.
+    ///
+    /// Repeated words must fit before the end of the memory that can be written.
+    fn get_write_repeat_from_cli(
+        write: &ArgMatches,
+        addresses: &[Address],
+        data: &[WriteData],
+    ) -> Result<u32, Error> {
+        let repeat_str = match write.value_of("repeat") {
+            Some(repeat_str) => repeat_str,
+            None => return Ok(1),
+        };
+        let repeat = repeat_str.parse::<u32>().context(CliError {
+            description: format!(
+                "the number of repetitions must be an integer between 1 and {}",
+                u32::max_value()
+            ),
+        })?;
+        if repeat == 0 {
+            return Err(CliError {
+                description: "the number of repetitions must be at least 1".to_owned(),
+            }
+            .into());
+        }
+        if repeat > 1 {
+            let mut has_words = false;
+            for (address, data) in addresses.iter().zip(data) {
+                if let WriteData::Word(_) = *data {
+                    has_words = true;
+                    // Named addresses are checked again once resolved.
+                    let addr = address.get_offset();
+                    if u64::from(addr) + 4 * u64::from(repeat) > u64::from(TRANSFER_END) {
+                        return Err(CliError {
+                            description: format!(
+                                "writing a word {} times at {:#010x} goes past {:#010x}, the end \
+                                 of the memory that can be written",
+                                repeat, addr, TRANSFER_END
+                            ),
+                        }
+                        .into());
+                    }
+                }
+            }
+            if !has_words {
+                return Err(CliError {
+                    description: "`--repeat` requires a word to write".to_owned(),
+                }
+                .into());
             }
+        }
+        Ok(repeat)
+    }
+
+    /// Gets the `exec` command from the CLI.
+    fn get_exec_from_cli(exec: &ArgMatches) -> Result<Command, Error> {
+        let addr = parse_address(exec.value_of("addr").unwrap())?;
+        // Calls that don't return fail with the USB transfer, so a longer time would never pass.
+        let return_within_ms = match exec.value_of("return_within") {
+            Some(time_str) => match time_str.parse::<u64>() {
+                Ok(time_ms) if time_ms <= USB_TIMEOUT_MS => Some(time_ms),
+                _ => {
+                    return Err(CliError {
+                        description: format!(
+                            "the time to return must be an integer between 0 and {} \
+                             milliseconds, the timeout of the USB transfers",
+                            USB_TIMEOUT_MS
+                        ),
+                    }
+                    .into());
+                }
+            },
+            None => None,
+        };
+        let expect = if exec.is_present("expect_no_return") {
+            Some(ExecExpectation::NoReturn)
+        } else if exec.is_present("expect_return") {
+            Some(ExecExpectation::Return)
+        } else {
+            None
+        };
+        let repeat = exec
+            .value_of("repeat")
+            .unwrap()
+            .parse::<u32>()
             .context(CliError {
                 description: format!(
-                    "the number of bytes to clear must be an integer from 0x00000000 to {:#010x} \
-                     (the maximum size starting from the given address)",
-                    (u32::max_value() - address).saturating_add(1)
+                    "the number of calls must be an integer between 0 and {}",
+                    u32::max_value()
                 ),
             })?;
-            if num_bytes > (u32::max_value() - address).saturating_add(1) {
-                return Err(CliError {
+        let mut args = [None; 4];
+        for (i, arg) in args.iter_mut().enumerate() {
+            if let Some(arg_str) = exec.value_of(format!("arg{}", i)) {
+                *arg = Some(parse_u32(arg_str).context(CliError {
                     description: format!(
-                    "clear size must be an integer from 0x00000000 to {:#010x} (the maximum size \
-                     starting from the given address)",
-                    (u32::max_value() - address).saturating_add(1)
-                ),
-                }
-                .into());
+                        "the value of `r{}` must be an integer from 0x00000000 to {:#010x}, given \
+                         '{}'",
+                        i,
+                        u32::max_value(),
+                        arg_str
+                    ),
+                })?);
             }
+        }
+        let wait = Self::get_exec_wait_from_cli(exec)?;
+        let wait_timeout_ms = match exec.value_of("wait_timeout") {
+            Some(timeout_str) => Some(timeout_str.parse::<u64>().context(CliError {
+                description: format!(
+                    "the wait timeout must be an integer between 0 and {} milliseconds",
+                    u64::max_value()
+                ),
+            })?),
+            None => None,
+        };
+        Ok(Command::Execute {
+            address: addr,
+            return_within_ms,
+            expect,
+            repeat,
+            args,
+            wait,
+            wait_timeout_ms,
+        })
+    }
 
-            Ok(Some(Command::Clear { address, num_bytes }))
-        } else if let Some(fill) = cli.subcommand_matches("fill") {
-            let addr_str = fill.value_of("addr").unwrap();
-            let address = if addr_str.starts_with("0x") {
-                u32::from_str_radix(addr_str.trim_left_matches("0x"), 16)
-            } else {
-                u32::from_str_radix(addr_str, 10)
+    /// Gets the address that the `exec` command polls and the value it waits for from the CLI.
+    fn get_exec_wait_from_cli(exec: &ArgMatches) -> Result<Option<(Address, u32)>, Error> {
+        let (wait_addr_str, wait_value_str) =
+            match (exec.value_of("wait_addr"), exec.value_of("wait_value")) {
+                (Some(wait_addr_str), Some(wait_value_str)) => (wait_addr_str, wait_value_str),
+                _ => return Ok(None),
+            };
+        let wait_addr = parse_address(wait_addr_str)?;
+        if u64::from(wait_addr.get_offset()) + 4 > u64::from(TRANSFER_END) {
+            return Err(CliError {
+                description: format!(
+                    "cannot poll a complete word at address {:#010x}, it goes past {:#010x}, the \
+                     end of the memory that can be read",
+                    wait_addr.get_offset(),
+                    TRANSFER_END
+                ),
             }
-            .context(CliError {
+            .into());
+        }
+        let wait_value = parse_u32(wait_value_str).context(CliError {
+            description: format!(
+                "the value to wait for must be an integer from 0x00000000 to {:#010x}, given '{}'",
+                u32::max_value(),
+                wait_value_str
+            ),
+        })?;
+        Ok(Some((wait_addr, wait_value)))
+    }
+
+    /// Gets the `crc32` command from the CLI.
+    fn get_crc32_from_cli(crc32: &ArgMatches) -> Result<Command, Error> {
+        let address = parse_integer_address(crc32.value_of("addr").unwrap())?;
+        let size = parse_region_size(crc32.value_of("size").unwrap(), address, "CRC-32")?;
+        let expected = if let Some(expected_str) = crc32.value_of("expected") {
+            Some(parse_u32(expected_str).context(CliError {
                 description: format!(
-                    "memory address must be an integer from 0x00000000 to {:#010x}, given '{}'",
+                    "the expected CRC-32 must be an integer from 0x00000000 to {:#010x}, given \
+                     '{}'",
                     u32::max_value(),
-                    addr_str
+                    expected_str
                 ),
-            })?;
-            let num_bytes_str = fill.value_of("num_bytes").unwrap();
-            let num_bytes = if num_bytes_str.starts_with("0x") {
-                u32::from_str_radix(num_bytes_str.trim_left_matches("0x"), 16)
+            })?)
+        } else {
+            None
+        };
+        Ok(Command::Crc32 {
+            address,
+            size,
+            expected,
+        })
+    }
+
+    /// Gets the `hash` command from the CLI.
+    fn get_hash_from_cli(hash: &ArgMatches) -> Result<Command, Error> {
+        let address = parse_integer_address(hash.value_of("addr").unwrap())?;
+        let size = parse_region_size(hash.value_of("size").unwrap(), address, "hash")?;
+        let algorithm = match hash.value_of("algorithm").unwrap() {
+            "sha1" => HashAlgorithm::Sha1,
+            "md5" => HashAlgorithm::Md5,
+            "crc16" => HashAlgorithm::Crc16,
+            _ => HashAlgorithm::Sha256,
+        };
+        Ok(Command::Hash {
+            address,
+            size,
+            algorithm,
+        })
+    }
+
+    /// Gets the `compare` command from the CLI.
+    fn get_compare_from_cli(compare: &ArgMatches) -> Result<Command, Error> {
+        let addr_a = parse_integer_address(compare.value_of("addr_a").unwrap())?;
+        let addr_b = parse_integer_address(compare.value_of("addr_b").unwrap())?;
+        // The region that starts higher limits the size of both.
+        let size = parse_region_size(
+            compare.value_of("size").unwrap(),
+            addr_a.max(addr_b),
+            "compare",
+        )?;
+        Ok(Command::Compare {
+            addr_a,
+            addr_b,
+            size,
+            format: if compare.is_present("hex") {
+                DiffFormat::Hexdump
             } else {
-                u32::from_str_radix(num_bytes_str, 10)
-            }
+                match compare.value_of("diff_format").unwrap() {
+                    "unified" => DiffFormat::Unified,
+                    "hexdump" => DiffFormat::Hexdump,
+                    _ => DiffFormat::Offsets,
+                }
+            },
+        })
+    }
+
+    /// Gets the `copy` command from the CLI.
+    fn get_copy_from_cli(copy: &ArgMatches) -> Result<Command, Error> {
+        let src = parse_integer_address(copy.value_of("src").unwrap())?;
+        let dst = parse_integer_address(copy.value_of("dst").unwrap())?;
+        let size = parse_region_size(copy.value_of("size").unwrap(), src.max(dst), "copy")?;
+        Ok(Command::Copy { src, dst, size })
+    }
+
+    /// Gets the `watch` command from the CLI.
+    fn get_watch_from_cli(watch: &ArgMatches) -> Result<Command, Error> {
+        let address = parse_integer_address(watch.value_of("addr").unwrap())?;
+        let interval_ms = watch
+            .value_of("interval")
+            .unwrap()
+            .parse::<u64>()
             .context(CliError {
                 description: format!(
-                    "the number of bytes to fill must be an integer from 0x00000000 to {:#010x} \
-                     (the maximum size starting from the given address)",
-                    (u32::max_value() - address).saturating_add(1)
+                    "the interval must be an integer between 0 and {} milliseconds",
+                    u64::max_value()
                 ),
             })?;
-            let fill_byte_str = fill.value_of("fill_byte").unwrap();
-            let fill_byte = if fill_byte_str.starts_with("0x") {
-                u8::from_str_radix(fill_byte_str.trim_left_matches("0x"), 16)
-            } else {
-                u8::from_str_radix(fill_byte_str, 10)
-            }
+        let count = watch
+            .value_of("count")
+            .unwrap()
+            .parse::<u32>()
             .context(CliError {
                 description: format!(
-                    "the filling byte must be an integer from 0x00 to {:#04x}, given '{}'",
-                    u8::max_value(),
-                    fill_byte_str
+                    "the number of reads must be an integer between 0 and {}",
+                    u32::max_value()
                 ),
             })?;
-            Ok(Some(Command::Fill {
+        Ok(Command::Watch {
+            address,
+            interval_ms,
+            count,
+            all: watch.is_present("all"),
+            record: watch.value_of("record").map(PathBuf::from),
+        })
+    }
+
+    /// Gets the `clear` command from the CLI.
+    fn get_clear_from_cli(clear: &ArgMatches) -> Result<Command, Error> {
+        let address = parse_address(clear.value_of("addr").unwrap())?;
+        // Named addresses are checked again once resolved.
+        let offset = address.get_offset();
+        let num_bytes = parse_region_size(clear.value_of("num_bytes").unwrap(), offset, "clear")?;
+        Ok(Command::Clear {
+            address,
+            num_bytes,
+            verify: clear.is_present("verify"),
+        })
+    }
+
+    /// Gets the `fill` command from the CLI, with a single byte or with a pattern.
+    fn get_fill_from_cli(fill: &ArgMatches) -> Result<Command, Error> {
+        let address = parse_address(fill.value_of("addr").unwrap())?;
+        // Named addresses are checked again once resolved.
+        let offset = address.get_offset();
+        let num_bytes = parse_region_size(
+            fill.value_of("num_bytes").unwrap(),
+            offset,
+            "the filling region",
+        )?;
+        if let Some(pattern_str) = fill.value_of("pattern") {
+            let hex = if pattern_str.starts_with("0x") || pattern_str.starts_with("0X") {
+                &pattern_str[2..]
+            } else {
+                pattern_str
+            };
+            return match decode_hex(hex) {
+                Some(ref pattern) if pattern.is_empty() => Err(CliError {
+                    description: "the filling pattern must have at least one byte".to_owned(),
+                }
+                .into()),
+                Some(pattern) => Ok(Command::FillPattern {
+                    address,
+                    num_bytes,
+                    pattern,
+                    verify: fill.is_present("verify"),
+                }),
+                None => Err(CliError {
+                    description: format!(
+                        "the filling pattern must be an even number of hexadecimal digits, given \
+                         '{}'",
+                        pattern_str
+                    ),
+                }
+                .into()),
+            };
+        }
+        let fill_byte_str = fill.value_of("fill_byte").unwrap();
+        let fill_byte = parse_u8(fill_byte_str).context(CliError {
+            description: format!(
+                "the filling byte must be an integer from 0x00 to {:#04x}, given '{}'",
+                u8::max_value(),
+                fill_byte_str
+            ),
+        })?;
+        Ok(Command::Fill {
+            address,
+            num_bytes,
+            fill_byte,
+            verify: fill.is_present("verify"),
+        })
+    }
+
+    /// Gets the `memtest` command from the CLI.
+    fn get_memtest_from_cli(memtest: &ArgMatches) -> Result<Command, Error> {
+        let address = parse_address(memtest.value_of("addr").unwrap())?;
+        // Named addresses are checked again once resolved.
+        let size = parse_region_size(
+            memtest.value_of("size").unwrap(),
+            address.get_offset(),
+            "the memory test region",
+        )?;
+        if size == 0 || size % 4 != 0 {
+            return Err(CliError {
+                description: format!(
+                    "the memory test size must be a non-zero multiple of 4 bytes, given {}",
+                    size
+                ),
+            }
+            .into());
+        }
+        let pattern = match memtest.value_of("pattern") {
+            Some("walking") => MemTestPattern::Walking,
+            Some("random") => MemTestPattern::Random,
+            _ => MemTestPattern::Address,
+        };
+        Ok(Command::MemTest {
+            address,
+            size,
+            pattern,
+        })
+    }
+
+    /// Gets one address and data pair of the `write` command from the CLI.
+    ///
+    /// Files are written in the given format or, if none is given, in the one of their extension.
+    /// Only the given range, with its offset and optional length, is written from raw binary files.
+    fn get_write_pair_from_cli(
+        addr_str: &str,
+        value_str: &str,
+        format: Option<DataFormat>,
+        (file_offset, file_length): (u32, Option<u32>),
+    ) -> Result<(Address, WriteData), Error> {
+        let address = parse_address(addr_str)?;
+        // Named addresses are checked again once resolved.
+        let addr = address.get_offset();
+        let word = parse_u32(value_str);
+        let final_value = match word {
+            // The length of the data in stdin is only known when writing it.
+            Err(_) if value_str == STDIN_FILE => WriteData::Stdin,
+            Ok(word) => {
+                if u64::from(addr) + 4 <= u64::from(TRANSFER_END) {
+                    WriteData::Word(word)
+                } else {
+                    let err_msg = format!(
+                        "cannot write a complete word at address {:#010x}, it would write \
+                         past {:#010x}, the end of the memory that can be written",
+                        addr, TRANSFER_END
+                    );
+                    return Err(CliError {
+                        description: err_msg,
+                    }
+                    .into());
+                }
+            }
+            Err(e) => {
+                let path = PathBuf::from(value_str);
+                let addressed = addressed_file(&path, file_format(&path, format));
+                if let (true, Some(file)) = (path.exists(), addressed) {
+                    // The addresses are in the file, so they are checked when reading it.
+                    file
+                } else if path.exists() {
+                    let (_, len) = file_range(&path, file_offset, file_length)?;
+                    let max_bytes = u64::from(TRANSFER_END.saturating_sub(addr));
+                    if len > max_bytes {
+                        let err_msg = format!(
+                            "the file '{}' is too big. The maximum file size to write to \
+                             address {:#010x} is {} bytes, but {} bytes of the file were given",
+                            path.display(),
+                            addr,
+                            max_bytes,
+                            len
+                        );
+                        return Err(CliError {
+                            description: err_msg,
+                        }
+                        .into());
+                    }
+                    WriteData::File(Box::new(path))
+                } else {
+                    return Err(CliError {
+                        description: format!(
+                            "the file '{}' does not exist.\nNote: If you were trying to \
+                             provide a value, the integer conversion failed with this error: \
+                             {}",
+                            path.display(),
+                            e
+                        ),
+                    }
+                    .into());
+                }
+            }
+        };
+        Ok((address, final_value))
+    }
+}
+
+/// Gets the value of the given result, or adds its error to the given problems.
+fn check<T>(problems: &mut Vec<Error>, result: Result<T, Error>) -> Option<T> {
+    result.map_err(|e| problems.push(e)).ok()
+}
+
+/// Gets the format of the given input file, if not given, from its extension.
+///
+/// Files with the `.hex` extension are Intel HEX, the ones with `.srec`, `.s19`, `.s28` or `.s37`
+/// Motorola S-records, and the rest raw binary.
+fn file_format(path: &Path, format: Option<DataFormat>) -> DataFormat {
+    format.unwrap_or_else(|| match path.extension().and_then(|ext| ext.to_str()) {
+        Some("hex") => DataFormat::IntelHex,
+        Some("srec") | Some("s19") | Some("s28") | Some("s37") => DataFormat::Srec,
+        _ => DataFormat::Binary,
+    })
+}
+
+/// Gets the data to write from the given file if its format carries the addresses of the data.
+fn addressed_file(path: &Path, format: DataFormat) -> Option<WriteData> {
+    match format {
+        DataFormat::Binary | DataFormat::CArray => None,
+        DataFormat::IntelHex => Some(WriteData::IntelHex(Box::new(path.to_path_buf()))),
+        DataFormat::Srec => Some(WriteData::Srec(Box::new(path.to_path_buf()))),
+    }
+}
+
+/// Splits the radix prefix of a numeric argument, returning the digits and the radix.
+///
+/// Numbers prefixed with `0x` are hexadecimal, with `0o` octal, with `0b` binary, and the rest
+/// are decimal. Prefixes are case insensitive.
+fn split_radix(num_str: &str) -> (&str, u32) {
+    match num_str.get(..2) {
+        Some("0x") | Some("0X") => (&num_str[2..], 16),
+        Some("0o") | Some("0O") => (&num_str[2..], 8),
+        Some("0b") | Some("0B") => (&num_str[2..], 2),
+        _ => (num_str, 10),
+    }
+}
+
+/// Removes the `_` digit separators from the given digits.
+///
+/// Separators are only allowed between digits, as in Rust literals.
+fn strip_separators(digits: &str) -> Result<String, Error> {
+    if digits.starts_with('_') || digits.ends_with('_') {
+        bail!("digit separators (`_`) can only be placed between digits");
+    }
+    Ok(digits.replace('_', ""))
+}
+
+/// Size suffixes accepted in byte count arguments, with their multipliers.
+const SIZE_SUFFIXES: [(&str, u32); 6] = [
+    ("KiB", 1 << 10),
+    ("MiB", 1 << 20),
+    ("GiB", 1 << 30),
+    ("K", 1_000),
+    ("M", 1_000_000),
+    ("G", 1_000_000_000),
+];
+
+/// Parses a byte count argument, optionally followed by a size suffix.
+fn parse_size(size_str: &str) -> Result<u32, Error> {
+    for &(suffix, multiplier) in &SIZE_SUFFIXES {
+        if size_str.ends_with(suffix) {
+            let num_str = &size_str[..size_str.len() - suffix.len()];
+            if split_radix(num_str).1 != 10 {
+                bail!("size suffixes cannot be combined with a radix prefix");
+            }
+            return parse_u32(num_str)?
+                .checked_mul(multiplier)
+                .ok_or_else(|| format_err!("the size '{}' does not fit in 32 bits", size_str));
+        }
+    }
+    parse_u32(size_str)
+}
+
+/// Parses the size of the memory region starting at the given address.
+///
+/// The region is transferred in chunks, so it cannot go past the end of the memory that can be
+/// transferred.
+fn parse_region_size(size_str: &str, address: u32, region_name: &str) -> Result<u32, Error> {
+    let max_size = TRANSFER_END.saturating_sub(address);
+    let description = format!(
+        "{} size must be an integer from 0x00000000 to {:#010x} (the maximum size starting from \
+         the given address)",
+        region_name, max_size
+    );
+    let size = parse_size(size_str).context(CliError {
+        description: description.clone(),
+    })?;
+    if size > max_size {
+        return Err(CliError { description }.into());
+    }
+    Ok(size)
+}
+
+/// Parses a memory address argument, either an integer or a named address of the SoC.
+///
+/// Named addresses start with `@`, and can be followed by an offset, as in `@spl+0x10`.
+fn parse_address(addr_str: &str) -> Result<Address, Error> {
+    if !addr_str.starts_with('@') {
+        let address = parse_u32(addr_str).context(CliError {
+            description: format!(
+                "memory address must be an integer from 0x00000000 to {:#010x} or a named address, \
+                 given '{}'",
+                u32::max_value(),
+                addr_str
+            ),
+        })?;
+        return Ok(Address::absolute(address));
+    }
+    let mut parts = addr_str[1..].splitn(2, '+');
+    let name = parts.next().unwrap();
+    let base = NamedAddress::from_name(name).ok_or_else(|| CliError {
+        description: format!(
+            "unknown named address '{}', the known ones are `@scratch`, `@spl`, `@thunk`, `@sid` \
+             and `@dram`",
+            addr_str
+        ),
+    })?;
+    let offset = match parts.next() {
+        Some(offset_str) => parse_u32(offset_str).context(CliError {
+            description: format!(
+                "the offset of a named address must be an integer from 0x00000000 to {:#010x}, \
+                 given '{}'",
+                u32::max_value(),
+                offset_str
+            ),
+        })?,
+        None => 0,
+    };
+    Ok(Address {
+        base: Some(base),
+        offset,
+    })
+}
+
+/// Parses a memory address argument that must be an integer.
+fn parse_integer_address(addr_str: &str) -> Result<u32, Error> {
+    Ok(parse_u32(addr_str).context(CliError {
+        description: format!(
+            "memory address must be an integer from 0x00000000 to {:#010x}, given '{}'",
+            u32::max_value(),
+            addr_str
+        ),
+    })?)
+}
+
+/// Parses a 32-bit unsigned integer argument.
+fn parse_u32(num_str: &str) -> Result<u32, Error> {
+    let (digits, radix) = split_radix(num_str);
+    Ok(u32::from_str_radix(&strip_separators(digits)?, radix)?)
+}
+
+/// Parses an 8-bit unsigned integer argument.
+fn parse_u8(num_str: &str) -> Result<u8, Error> {
+    let (digits, radix) = split_radix(num_str);
+    Ok(u8::from_str_radix(&strip_separators(digits)?, radix)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use clap::Shell;
+    use failure::Error;
+
+    use super::{
+        parse_size, parse_u32, split_radix, strip_separators, Address, Command, Config, DataFormat,
+        DiffFormat, Endianness, HashAlgorithm, MemTestPattern, MemoryRegion, NamedAddress,
+        SidFormat, WriteData,
+    };
+    use crate::{cli, DeviceSelector, Options};
+
+    /// Parses the given CLI arguments, which must be accepted by the CLI definition.
+    fn parse(args: &[&str]) -> Result<Config, Error> {
+        let matches = cli::generate()
+            .get_matches_from_safe(Some("fel-cli").iter().chain(args))
+            .unwrap();
+        Config::from_cli(&matches)
+    }
+
+    /// Parses the given CLI arguments, and gets the resulting command.
+    fn command(args: &[&str]) -> Command {
+        parse(args).unwrap().command.unwrap()
+    }
+
+    #[test]
+    fn numeric_arguments() {
+        assert_eq!(parse_u32("16384").unwrap(), 16384);
+        assert_eq!(parse_u32("0x4000").unwrap(), 0x4000);
+        assert_eq!(parse_u32("0xffffffff").unwrap(), u32::max_value());
+        assert!(parse_u32("0x100000000").is_err());
+        assert!(parse_u32("").is_err());
+        assert!(parse_u32("0x").is_err());
+        assert!(parse_u32("-1").is_err());
+        assert!(parse_u32("0x40g0").is_err());
+    }
+
+    #[test]
+    fn digit_separators() {
+        assert_eq!(strip_separators("4000_0000").unwrap(), "40000000");
+        assert_eq!(parse_u32("0x4000_0000").unwrap(), 0x4000_0000);
+        assert_eq!(parse_u32("1_000").unwrap(), 1000);
+        for num_str in &["_40", "0x_4000", "4000_", "0x4000_"] {
+            assert!(parse_u32(num_str).is_err(), "'{}' was accepted", num_str);
+        }
+    }
+
+    #[test]
+    fn size_suffixes() {
+        assert_eq!(parse_size("4K").unwrap(), 4_000);
+        assert_eq!(parse_size("4KiB").unwrap(), 4 << 10);
+        assert_eq!(parse_size("16MiB").unwrap(), 16 << 20);
+        assert_eq!(parse_size("3GiB").unwrap(), 3 << 30);
+        assert_eq!(parse_size("4G").unwrap(), 4_000_000_000);
+        assert_eq!(parse_size("0x400").unwrap(), 0x400);
+        assert!(parse_size("5G").is_err());
+        assert!(parse_size("4GiB").is_err());
+        assert!(parse_size("0x4K").is_err());
+        assert!(parse_size("K").is_err());
+    }
+
+    #[test]
+    fn region_size_limit() {
+        assert!(parse(&["crc32", "0xffff0000", "0xfffe"]).is_ok());
+        assert!(parse(&["crc32", "0xffff0000", "0xffff"]).is_err());
+        assert!(parse(&["crc32", "0xffff0000", "0x10000"]).is_err());
+        assert!(parse(&["crc32", "0xffffffff", "1"]).is_err());
+    }
+
+    #[test]
+    fn radix_prefixes() {
+        assert_eq!(split_radix("0x10"), ("10", 16));
+        assert_eq!(split_radix("0o10"), ("10", 8));
+        assert_eq!(split_radix("0b10"), ("10", 2));
+        assert_eq!(split_radix("10"), ("10", 10));
+        assert_eq!(parse_u32("0b101").unwrap(), 5);
+        assert_eq!(parse_u32("0o17").unwrap(), 15);
+        assert!(parse_u32("0b2").is_err());
+        assert!(parse_u32("0o8").is_err());
+    }
+
+    #[test]
+    fn uppercase_radix_prefixes() {
+        assert_eq!(
+            parse_u32("0X4000000A").unwrap(),
+            parse_u32("0x4000000a").unwrap()
+        );
+        assert_eq!(parse_u32("0O17").unwrap(), 15);
+        assert_eq!(parse_u32("0B101").unwrap(), 5);
+    }
+
+    #[test]
+    fn device() {
+        let config = parse(&["--device", "1:5", "version"]).unwrap();
+        assert_eq!(config.get_devices(), &[(1, 5)]);
+
+        let config = parse(&["--device", "1:5,2:16", "version"]).unwrap();
+        assert_eq!(config.get_devices(), &[(1, 5), (2, 16)]);
+
+        let config = parse(&["--prefer-device", "3:4", "version"]).unwrap();
+        assert_eq!(
+            config.get_preferred_device(),
+            Some(&DeviceSelector::BusAddr(3, 4))
+        );
+
+        let config = parse(&["--prefer-device", "1234abcd", "version"]).unwrap();
+        assert_eq!(
+            config.get_preferred_device(),
+            Some(&DeviceSelector::Serial("1234abcd".to_owned()))
+        );
+        assert!(parse(&["--prefer-device", "1:x", "version"]).is_err());
+    }
+
+    #[test]
+    fn device_malformed() {
+        for device in &[
+            "1", "1:", ":5", "1:5:6", "a:5", "1:256", "256:1", "1:0x5", "1:5,",
+        ] {
+            assert!(
+                parse(&["--device", device, "version"]).is_err(),
+                "device '{}' was accepted",
+                device
+            );
+        }
+    }
+
+    #[test]
+    fn spl() {
+        match command(&["spl", "Cargo.toml", "--exec"]) {
+            Command::Uboot {
+                ref file,
+                start_uboot,
+                entry,
+                allow_small,
+                check_dram,
+                verify,
+            } => {
+                assert_eq!(file, Path::new("Cargo.toml"));
+                assert!(start_uboot);
+                assert_eq!(entry, None);
+                assert!(!allow_small);
+                assert!(!check_dram);
+                assert!(!verify);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["spl", "Cargo.toml", "--no-exec"]) {
+            Command::Uboot { start_uboot, .. } => assert!(!start_uboot),
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["spl", "Cargo.toml", "--verify"]) {
+            Command::Uboot { verify, .. } => assert!(verify),
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["spl", "Cargo.toml", "--exec", "--entry", "0x4a00_0000"]) {
+            Command::Uboot { entry, .. } => assert_eq!(entry, Some(Address::absolute(0x4A00_0000))),
+            other => panic!("unexpected command: {:?}", other),
+        }
+        assert!(parse(&["spl", "Cargo.toml", "--exec", "--entry", "@nowhere"]).is_err());
+
+        assert!(parse(&["spl", "does-not-exist.bin"]).is_err());
+    }
+
+    #[test]
+    fn dump_word() {
+        match command(&["dump", "0x4000_0000"]) {
+            Command::Dump {
+                address,
+                size,
+                words,
+                endian,
+                hex,
+                sid,
+                ..
+            } => {
+                assert_eq!(address, Some(Address::absolute(0x4000_0000)));
+                assert_eq!(size, None);
+                assert_eq!(words, 1);
+                assert_eq!(endian, Endianness::Little);
+                assert!(!hex);
+                assert!(!sid);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+
+        match command(&["dump", "16", "--words", "4", "--endian", "big"]) {
+            Command::Dump {
+                address,
+                words,
+                endian,
+                ..
+            } => {
+                assert_eq!(address, Some(Address::absolute(16)));
+                assert_eq!(words, 4);
+                assert_eq!(endian, Endianness::Big);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn dump_size() {
+        for &(size_str, size) in &[
+            ("0x10", 0x10),
+            ("16", 16),
+            ("0o20", 0o20),
+            ("0b1_0000", 0b1_0000),
+            ("1K", 1000),
+            ("1KiB", 1024),
+            ("2MiB", 2 * 1024 * 1024),
+        ] {
+            match command(&["dump", "0", size_str, "--hex"]) {
+                Command::Dump {
+                    size: Some(parsed),
+                    hex,
+                    ..
+                } => {
+                    assert_eq!(parsed, size, "wrong size for '{}'", size_str);
+                    assert!(hex);
+                }
+                other => panic!("unexpected command: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn dump_hex_to_file() {
+        match command(&["dump", "0", "0x100", "--hex", "-o", "dump.txt"]) {
+            Command::Dump { hex, ref out, .. } => {
+                assert!(hex);
+                assert_eq!(
+                    out.as_ref().map(|out| out.as_path()),
+                    Some(Path::new("dump.txt"))
+                );
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn dump_words_to_file() {
+        match command(&["dump", "0x1000", "--words", "8", "-o", "regs.bin"]) {
+            Command::Dump {
+                size,
+                words,
+                ref out,
+                ..
+            } => {
+                assert_eq!(size, None);
+                assert_eq!(words, 8);
+                assert_eq!(
+                    out.as_ref().map(|out| out.as_path()),
+                    Some(Path::new("regs.bin"))
+                );
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn dump_sid() {
+        match command(&["dump", "--sid"]) {
+            Command::Dump {
+                address,
+                sid,
+                sid_format,
+                ..
+            } => {
+                assert_eq!(address, None);
+                assert!(sid);
+                assert_eq!(sid_format, SidFormat::Colon);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["--json", "dump", "--sid"]) {
+            Command::Dump { sid_format, .. } => assert_eq!(sid_format, SidFormat::Json),
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["dump", "--sid", "--sid-format", "raw", "-o", "sid.bin"]) {
+            Command::Dump {
+                sid_format,
+                ref out,
+                ..
+            } => {
+                assert_eq!(sid_format, SidFormat::Raw);
+                assert_eq!(
+                    out.as_ref().map(|out| out.as_path()),
+                    Some(Path::new("sid.bin"))
+                );
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+        assert!(parse(&["dump", "--sid", "--sid-format", "hex", "-o", "sid.txt"]).is_err());
+    }
+
+    #[test]
+    fn dump_overflow() {
+        assert!(parse(&["dump", "0x1_0000_0000"]).is_err());
+        // aw-fel cannot transfer the last two bytes of the address space.
+        assert!(parse(&["dump", "0xFFFF_FFF0", "0xE"]).is_ok());
+        assert!(parse(&["dump", "0xFFFF_FFF0", "0xF"]).is_err());
+        assert!(parse(&["dump", "0xFFFF_FFF0", "0x10"]).is_err());
+        assert!(parse(&["dump", "0xFFFF_FFF0", "--words", "3"]).is_ok());
+        assert!(parse(&["dump", "0xFFFF_FFF0", "--words", "4"]).is_err());
+        assert!(parse(&["dump", "0xFFFF_FFFC", "--words", "1"]).is_err());
+        assert!(parse(&["dump", "0", "--words", "0"]).is_err());
+        assert!(parse(&["dump", "0", "5GiB"]).is_err());
+    }
+
+    #[test]
+    fn dump_format() {
+        match command(&["dump", "0", "16", "--format", "srec", "-o", "out.s19"]) {
+            Command::Dump {
+                format, ref out, ..
+            } => {
+                assert_eq!(format, DataFormat::Srec);
+                assert_eq!(
+                    out.as_ref().map(|out| out.as_path()),
+                    Some(Path::new("out.s19"))
+                );
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+        assert!(parse(&["dump", "0", "--format", "srec"]).is_err());
+    }
+
+    #[test]
+    fn dump_carray() {
+        match command(&[
+            "dump",
+            "0",
+            "16",
+            "--format",
+            "carray",
+            "--array-name",
+            "boot0",
+        ]) {
+            Command::Dump {
+                format,
+                ref array_name,
+                ..
+            } => {
+                assert_eq!(format, DataFormat::CArray);
+                assert_eq!(array_name, "boot0");
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["dump", "0", "16", "--format", "carray"]) {
+            Command::Dump { ref array_name, .. } => assert_eq!(array_name, "data"),
+            other => panic!("unexpected command: {:?}", other),
+        }
+        assert!(parse(&["dump", "0", "16", "--format", "srec", "--array-name", "x"]).is_err());
+        assert!(parse(&[
+            "dump",
+            "0",
+            "16",
+            "--format",
+            "carray",
+            "--array-name",
+            "0x"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn dump_count() {
+        match command(&[
+            "dump",
+            "0",
+            "16",
+            "-o",
+            "out.bin",
+            "--count",
+            "3",
+            "--interval",
+            "500",
+        ]) {
+            Command::Dump {
+                count, interval_ms, ..
+            } => {
+                assert_eq!(count, 3);
+                assert_eq!(interval_ms, 500);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["dump", "0", "16", "--count", "0"]) {
+            Command::Dump {
+                count, interval_ms, ..
+            } => {
+                assert_eq!(count, 1);
+                assert_eq!(interval_ms, 0);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+        assert!(parse(&["dump", "0", "16", "--count", "many"]).is_err());
+    }
+
+    #[test]
+    fn dump_append() {
+        match command(&["dump", "0", "16", "-o", "out.bin", "--append"]) {
+            Command::Dump { append, .. } => assert!(append),
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["dump", "0", "16", "-o", "out.bin"]) {
+            Command::Dump { append, .. } => assert!(!append),
+            other => panic!("unexpected command: {:?}", other),
+        }
+        assert!(
+            parse(&["dump", "0", "16", "-o", "out.s19", "--format", "srec", "--append"]).is_err()
+        );
+    }
+
+    #[test]
+    fn dump_region() {
+        match command(&["dump", "--region", "sram-c", "--format", "intel-hex"]) {
+            Command::Dump {
+                address,
+                size,
+                region,
+                format,
+                ..
+            } => {
+                assert_eq!(address, None);
+                assert_eq!(size, None);
+                assert_eq!(region, Some(MemoryRegion::SramC));
+                assert_eq!(format, DataFormat::IntelHex);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn memtest() {
+        match command(&["memtest", "@dram", "64MiB", "--pattern", "walking"]) {
+            Command::MemTest {
+                address,
+                size,
+                pattern,
+            } => {
+                assert_eq!(address.get_base(), Some(NamedAddress::Dram));
+                assert_eq!(size, 64 * 1024 * 1024);
+                assert_eq!(pattern, MemTestPattern::Walking);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["memtest", "0x4000_0000", "0x100"]) {
+            Command::MemTest { pattern, .. } => assert_eq!(pattern, MemTestPattern::Address),
+            other => panic!("unexpected command: {:?}", other),
+        }
+        assert!(parse(&["memtest", "0x4000_0000", "0x102"]).is_err());
+        assert!(parse(&["memtest", "0x4000_0000", "0"]).is_err());
+        assert!(parse(&["memtest", "0xFFFF_FFF0", "0x20"]).is_err());
+    }
+
+    #[test]
+    fn write_words() {
+        match command(&["write", "0x40000000", "0xff", "64", "0b11"]) {
+            Command::Write {
+                ref addresses,
+                ref data,
+                file_offset,
+                file_length,
+                endian,
+                repeat,
+                byte_swap,
+                merge_writes,
+                verify,
+                summary,
+            } => {
+                assert_eq!(
+                    addresses,
+                    &[Address::absolute(0x4000_0000), Address::absolute(64)]
+                );
+                assert_eq!(repeat, 1);
+                assert_eq!(byte_swap, None);
+                match data[..] {
+                    [WriteData::Word(0xff), WriteData::Word(0b11)] => {}
+                    ref other => panic!("unexpected data: {:?}", other),
+                }
+                assert_eq!(file_offset, 0);
+                assert_eq!(file_length, None);
+                assert_eq!(endian, Endianness::Little);
+                assert!(!merge_writes);
+                assert!(!verify);
+                assert!(!summary);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn write_pairs() {
+        match command(&["write", "0x1000", "1", "0x2000", "2"]) {
+            Command::Write { ref data, .. } => assert_eq!(data.len(), 2),
+            other => panic!("unexpected command: {:?}", other),
+        }
+
+        let e = parse(&["write", "0x1000", "1", "0x2000"]).unwrap_err();
+        assert!(
+            e.to_string()
+                .contains("missing the word or file to write at address '0x2000'"),
+            "unexpected error: {}",
+            e
+        );
+        let e = parse(&["write", "0x1000", "1", "foo"]).unwrap_err();
+        assert!(
+            e.to_string()
+                .contains("'foo' is neither an address nor an Intel HEX"),
+            "unexpected error: {}",
+            e
+        );
+    }
+
+    #[test]
+    fn endianness() {
+        assert_eq!(Endianness::Little.apply(0x1122_3344), 0x1122_3344);
+        assert_eq!(Endianness::Big.apply(0x1122_3344), 0x4433_2211);
+
+        // A big-endian word written to memory is read back as the same word by a big-endian dump.
+        let (endian, word) = match command(&["write", "0x1000", "0x11223344", "--endian", "big"]) {
+            Command::Write {
+                ref data, endian, ..
+            } => match data[..] {
+                [WriteData::Word(w)] => (endian, w),
+                ref other => panic!("unexpected data: {:?}", other),
+            },
+            other => panic!("unexpected command: {:?}", other),
+        };
+        let native = endian.apply(word);
+        // The SoC stores the native word in little-endian, so the first byte in memory is `0x11`.
+        assert_eq!(native & 0xff, 0x11);
+        match command(&["dump", "0x1000", "--words", "1", "--endian", "big"]) {
+            Command::Dump { endian, .. } => assert_eq!(endian.apply(native), word),
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn write_repeat() {
+        match command(&["write", "0x40000000", "0xAABBCCDD", "--repeat", "256"]) {
+            Command::Write {
+                ref data, repeat, ..
+            } => {
+                match data[..] {
+                    [WriteData::Word(0xAABB_CCDD)] => {}
+                    ref other => panic!("unexpected data: {:?}", other),
+                }
+                assert_eq!(repeat, 256);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+        assert!(parse(&["write", "0xFFFF_FF00", "0xff", "--repeat", "63"]).is_ok());
+        assert!(parse(&["write", "0xFFFF_FF00", "0xff", "--repeat", "64"]).is_err());
+        assert!(parse(&["write", "0x40000000", "0xff", "--repeat", "0"]).is_err());
+        assert!(parse(&["write", "0x40000000", "Cargo.toml", "--repeat", "2"]).is_err());
+    }
+
+    #[test]
+    fn write_stdin() {
+        match command(&["write", "0x40000000", "-"]) {
+            Command::Write { ref data, .. } => match data[..] {
+                [WriteData::Stdin] => {}
+                ref other => panic!("unexpected data: {:?}", other),
+            },
+            other => panic!("unexpected command: {:?}", other),
+        }
+        assert!(parse(&["write", "0x40000000", "-", "0x41000000", "-"]).is_err());
+        assert!(parse(&["write", "0x40000000", "-", "--offset", "4"]).is_err());
+    }
+
+    #[test]
+    fn write_byteswap() {
+        // The records are only parsed when writing them.
+        match command(&[
+            "write",
+            "0x40000000",
+            "Cargo.toml",
+            "--format",
+            "intel-hex",
+            "--byteswap",
+            "4",
+        ]) {
+            Command::Write { byte_swap, .. } => assert_eq!(byte_swap, Some(4)),
+            other => panic!("unexpected command: {:?}", other),
+        }
+        assert!(parse(&["write", "0x40000000", "0xff", "--byteswap", "2"]).is_err());
+    }
+
+    #[test]
+    fn write_summary() {
+        match command(&[
+            "write",
+            "0x40000000",
+            "0xff",
+            "0x40000004",
+            "Cargo.toml",
+            "--summary",
+        ]) {
+            Command::Write { summary, .. } => assert!(summary),
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn write_file() {
+        match command(&["write", "0x40000000", "Cargo.toml"]) {
+            Command::Write { ref data, .. } => match data[..] {
+                [WriteData::File(ref path)] => assert_eq!(path.as_path(), Path::new("Cargo.toml")),
+                ref other => panic!("unexpected data: {:?}", other),
+            },
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn write_file_range() {
+        let size = Path::new("Cargo.toml").metadata().unwrap().len() as u32;
+        match command(&[
+            "write",
+            "0x40000000",
+            "Cargo.toml",
+            "--offset",
+            "16",
+            "--length",
+            "8",
+        ]) {
+            Command::Write {
+                file_offset,
+                file_length,
+                ..
+            } => {
+                assert_eq!(file_offset, 16);
+                assert_eq!(file_length, Some(8));
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+        let size_str = size.to_string();
+        let past_end = (size + 1).to_string();
+        assert!(parse(&["write", "0x40000000", "Cargo.toml", "--offset", &size_str]).is_ok());
+        assert!(parse(&["write", "0x40000000", "Cargo.toml", "--offset", &past_end]).is_err());
+        assert!(parse(&["write", "0x40000000", "Cargo.toml", "--length", &past_end]).is_err());
+        assert!(parse(&["write", "0x40000000", "0xff", "--offset", "4"]).is_err());
+    }
+
+    #[test]
+    fn all_problems() {
+        let err = parse(&[
+            "--retries",
+            "many",
+            "--chunk-size",
+            "3",
+            "write",
+            "0x40000000",
+            "does-not-exist.bin",
+        ])
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("found 3 problems in the command line"));
+        assert!(err.contains("does-not-exist.bin"));
+    }
+
+    #[test]
+    fn write_rejected() {
+        assert!(parse(&["write", "0x40000000", "0xff", "0x41000000"]).is_err());
+        assert!(parse(&["write", "0x40000000", "does-not-exist.bin"]).is_err());
+        assert!(parse(&["write", "0xFFFF_FFFA", "0xff"]).is_ok());
+        assert!(parse(&["write", "0xFFFF_FFFB", "0xff"]).is_err());
+        assert!(parse(&["clear", "0xFFFF_FF00", "0xFE"]).is_ok());
+        assert!(parse(&["clear", "0xFFFF_FF00", "0xFF"]).is_err());
+        assert!(parse(&["write", "0x1_0000_0000", "0xff"]).is_err());
+    }
+
+    #[test]
+    fn exec() {
+        match command(&["exec", "0x2000", "--return-within", "500"]) {
+            Command::Execute {
+                address,
+                return_within_ms,
+                expect,
+                repeat,
+                args,
+                wait,
+                wait_timeout_ms,
+            } => {
+                assert_eq!(address, Address::absolute(0x2000));
+                assert_eq!(return_within_ms, Some(500));
+                assert_eq!(expect, None);
+                assert_eq!(repeat, 1);
+                assert_eq!(args, [None; 4]);
+                assert_eq!(wait, None);
+                assert_eq!(wait_timeout_ms, None);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+        assert!(parse(&["exec", "0x2000", "--return-within", "10000"]).is_ok());
+        assert!(parse(&["exec", "0x2000", "--return-within", "10001"]).is_err());
+        match command(&[
+            "exec",
+            "0x2000",
+            "--wait-addr",
+            "0x3000",
+            "--wait-value",
+            "1",
+            "--wait-timeout",
+            "100",
+        ]) {
+            Command::Execute {
+                wait,
+                wait_timeout_ms,
+                ..
+            } => {
+                assert_eq!(wait, Some((Address::absolute(0x3000), 1)));
+                assert_eq!(wait_timeout_ms, Some(100));
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+        for (wait_addr, accepted) in &[("0xFFFF_FFF8", true), ("0xFFFF_FFFC", false)] {
+            let args = [
+                "exec",
+                "0x2000",
+                "--wait-addr",
+                wait_addr,
+                "--wait-value",
+                "1",
+            ];
+            assert_eq!(parse(&args).is_ok(), *accepted);
+        }
+        match command(&["exec", "0x2000", "--repeat", "0"]) {
+            Command::Execute { repeat: 0, .. } => {}
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["exec", "0x2000", "--arg0", "0x10", "--arg2", "7"]) {
+            Command::Execute { args, .. } => assert_eq!(args, [Some(0x10), None, Some(7), None]),
+            other => panic!("unexpected command: {:?}", other),
+        }
+        assert!(parse(&["exec", "0x2000", "--arg1", "0x100000000"]).is_err());
+
+        assert!(parse(&["exec", "0x1_0000_0000"]).is_err());
+    }
+
+    #[test]
+    fn reset() {
+        match command(&["reset"]) {
+            Command::Reset { address: None } => {}
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["reset", "0x4a00_0000"]) {
+            Command::Reset {
+                address: Some(0x4a00_0000),
+            } => {}
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["reset64", "0x4a00_0000"]) {
+            Command::Reset64 {
+                address: 0x4a00_0000,
+            } => {}
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn info_commands() {
+        match command(&["version"]) {
+            Command::Version { json: false } => {}
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["version", "--json"]) {
+            Command::Version { json: true } => {}
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["map"]) {
+            Command::Map => {}
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["meminfo"]) {
+            Command::MemInfo => {}
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["list", "--json"]) {
+            Command::List { json: true } => {}
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn memory_region_commands() {
+        match command(&["crc32", "0x1000", "0x100", "--expected", "0xdeadbeef"]) {
+            Command::Crc32 {
+                address: 0x1000,
+                size: 0x100,
+                expected: Some(0xdead_beef),
+            } => {}
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["hash", "0x1000", "1KiB", "--algorithm", "md5"]) {
+            Command::Hash {
+                address: 0x1000,
+                size: 1024,
+                algorithm: HashAlgorithm::Md5,
+            } => {}
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["hash", "0x1000", "0x100", "--algo", "crc16"]) {
+            Command::Hash {
+                algorithm: HashAlgorithm::Crc16,
+                ..
+            } => {}
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["compare", "0x1000", "0x2000", "16"]) {
+            Command::Compare {
+                addr_a: 0x1000,
+                addr_b: 0x2000,
+                size: 16,
+                format: DiffFormat::Offsets,
+            } => {}
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&[
+            "compare",
+            "0x1000",
+            "0x2000",
+            "16",
+            "--diff-format",
+            "unified",
+        ]) {
+            Command::Compare {
+                format: DiffFormat::Unified,
+                ..
+            } => {}
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["compare", "0x1000", "0x2000", "16", "--hex"]) {
+            Command::Compare {
+                format: DiffFormat::Hexdump,
+                ..
+            } => {}
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["copy", "0x1000", "0x2000", "16"]) {
+            Command::Copy {
+                src: 0x1000,
+                dst: 0x2000,
+                size: 16,
+            } => {}
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["clear", "0x1000"]) {
+            Command::Clear {
                 address,
                 num_bytes,
-                fill_byte,
-            }))
-        } else {
-            Ok(None)
+                verify,
+            } => {
+                assert_eq!(address, Address::absolute(0x1000));
+                assert_eq!(num_bytes, 4);
+                assert!(!verify);
+            }
+            other => panic!("unexpected command: {:?}", other),
         }
     }
+
+    #[test]
+    fn clear_verify() {
+        match command(&["clear", "0x1000", "16", "--verify"]) {
+            Command::Clear {
+                num_bytes, verify, ..
+            } => {
+                assert_eq!(num_bytes, 16);
+                assert!(verify);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn named_addresses() {
+        match command(&["exec", "@spl"]) {
+            Command::Execute { address, .. } => {
+                assert_eq!(address.get_base(), Some(NamedAddress::Spl));
+                assert_eq!(address.get_offset(), 0);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["clear", "@dram+0x10", "16"]) {
+            Command::Clear { address, .. } => {
+                assert_eq!(address.get_base(), Some(NamedAddress::Dram));
+                assert_eq!(address.get_offset(), 0x10);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["write", "@scratch+4", "0xff"]) {
+            Command::Write { ref addresses, .. } => {
+                assert_eq!(addresses[0].get_base(), Some(NamedAddress::Scratch));
+                assert_eq!(addresses[0].get_offset(), 4);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+        assert!(parse(&["dump", "@sram"]).is_err());
+        assert!(parse(&["clear", "@spl+"]).is_err());
+        assert!(parse(&["write", "@spl+0xFFFF_FFFC", "0xff"]).is_err());
+    }
+
+    #[test]
+    fn memory_region_overflow() {
+        assert!(parse(&["crc32", "0xFFFF_FFF0", "0x11"]).is_err());
+        assert!(parse(&["hash", "0xFFFF_FFF0", "0x11"]).is_err());
+        assert!(parse(&["copy", "0", "0xFFFF_FFF0", "0x11"]).is_err());
+        assert!(parse(&["compare", "0xFFFF_FFF0", "0", "0x11"]).is_err());
+    }
+
+    #[test]
+    fn watch() {
+        match command(&["watch", "0x1000", "--interval", "10", "--count", "3"]) {
+            Command::Watch {
+                address: 0x1000,
+                interval_ms: 10,
+                count: 3,
+                all: false,
+                record: None,
+            } => {}
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["watch", "0x1000", "--record", "boot.rec"]) {
+            Command::Watch {
+                record: Some(ref record),
+                ..
+            } => assert_eq!(record, Path::new("boot.rec")),
+            other => panic!("unexpected command: {:?}", other),
+        }
+        match command(&["replay", "boot.rec"]) {
+            Command::Replay { ref file } => assert_eq!(file, Path::new("boot.rec")),
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn usb_retries() {
+        assert_eq!(parse(&["version"]).unwrap().get_usb_retries(), 0);
+        assert_eq!(
+            parse(&["--retries", "3", "version"])
+                .unwrap()
+                .get_usb_retries(),
+            3
+        );
+        assert!(parse(&["--retries", "many", "version"]).is_err());
+    }
+
+    #[test]
+    fn chunk_size() {
+        assert_eq!(parse(&["version"]).unwrap().get_chunk_size(), 0x1_0000);
+        assert_eq!(
+            parse(&["--chunk-size", "1MiB", "version"])
+                .unwrap()
+                .get_chunk_size(),
+            0x10_0000
+        );
+        assert!(parse(&["--chunk-size", "0x3000", "version"]).is_err());
+        assert!(parse(&["--chunk-size", "256", "version"]).is_err());
+        assert!(parse(&["--chunk-size", "32MiB", "version"]).is_err());
+
+        assert!(Options::default().with_chunk_size(0x200).is_ok());
+        assert!(Options::default().with_chunk_size(0).is_err());
+        assert!(Options::default().with_chunk_size(0x3000).is_err());
+        assert!(Options::default().with_chunk_size(0x200_0000).is_err());
+    }
+
+    #[test]
+    fn scratch_region() {
+        assert_eq!(parse(&["version"]).unwrap().get_scratch_region(), None);
+        assert_eq!(
+            parse(&["--scratch-region", "auto", "version"])
+                .unwrap()
+                .get_scratch_region(),
+            None
+        );
+        assert_eq!(
+            parse(&["--scratch-region", "0x4000_0000:1MiB", "version"])
+                .unwrap()
+                .get_scratch_region(),
+            Some((0x4000_0000, 0x10_0000))
+        );
+        assert_eq!(
+            parse(&["--scratch-region", "0xffff_f000:4KiB", "version"])
+                .unwrap()
+                .get_scratch_region(),
+            Some((0xFFFF_F000, 0x1000))
+        );
+        assert!(parse(&["--scratch-region", "0xffff_f000:8KiB", "version"]).is_err());
+        assert!(parse(&["--scratch-region", "0x1000:0", "version"]).is_err());
+        assert!(parse(&["--scratch-region", "0x1000", "version"]).is_err());
+        assert!(parse(&["--scratch-region", "sram", "version"]).is_err());
+        assert!(parse(&["--strict-hex", "--scratch-region", "4096:1KiB", "version"]).is_err());
+    }
+
+    #[test]
+    fn wait() {
+        let config = parse(&["--wait", "version"]).unwrap();
+        assert!(config.wait_for_device());
+        assert_eq!(config.get_wait_timeout_secs(), None);
+        let config = parse(&["--wait=30", "version"]).unwrap();
+        assert!(config.wait_for_device());
+        assert_eq!(config.get_wait_timeout_secs(), Some(30));
+        assert!(!parse(&["version"]).unwrap().wait_for_device());
+        assert!(parse(&["--wait=soon", "version"]).is_err());
+    }
+
+    #[test]
+    fn completions() {
+        match command(&["completions", "zsh"]) {
+            Command::Completions { shell: Shell::Zsh } => {}
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn strict_hex() {
+        assert!(parse(&["--strict-hex", "dump", "0x1000", "0x10"]).is_ok());
+        assert!(parse(&["--strict-hex", "dump", "4096"]).is_err());
+        assert!(parse(&["--strict-hex", "write", "0x1000", "255"]).is_err());
+        assert!(parse(&["--strict-hex", "exec", "0x0", "--arg0", "0x10"]).is_ok());
+        assert!(parse(&["--strict-hex", "exec", "0x0", "--arg0", "10"]).is_err());
+        assert!(parse(&["--strict-hex", "exec", "0x0", "--arg3", "10"]).is_err());
+    }
 }
@@ -0,0 +1,224 @@
+//! Intel HEX encoding and decoding.
+
+use std::io::{self, Write};
+
+use failure::{bail, Error};
+
+/// Maximum number of data bytes in a record.
+const RECORD_DATA_LEN: usize = 0x10;
+/// Data record type.
+const DATA_RECORD: u8 = 0x00;
+/// End of file record type.
+const END_OF_FILE_RECORD: u8 = 0x01;
+/// Extended segment address record type, with bits 4 to 19 of the following addresses.
+const EXTENDED_SEGMENT_ADDRESS_RECORD: u8 = 0x02;
+/// Start segment address record type.
+const START_SEGMENT_ADDRESS_RECORD: u8 = 0x03;
+/// Extended linear address record type, with the upper 16 bits of the following addresses.
+const EXTENDED_LINEAR_ADDRESS_RECORD: u8 = 0x04;
+/// Start linear address record type.
+const START_LINEAR_ADDRESS_RECORD: u8 = 0x05;
+
+/// Writes the given data, starting at the given address, as Intel HEX records.
+///
+/// An extended linear address record is written before the first data record and every time the
+/// upper 16 bits of the address change, and data records never cross a 64 KiB boundary.
+pub fn write<W: Write>(writer: &mut W, address: u32, data: &[u8]) -> io::Result<()> {
+    let mut upper_address = None;
+    let mut offset = 0;
+    while offset < data.len() {
+        let record_address = address.wrapping_add(offset as u32);
+        let upper = (record_address >> 16) as u16;
+        if upper_address != Some(upper) {
+            write_record(
+                writer,
+                0,
+                EXTENDED_LINEAR_ADDRESS_RECORD,
+                &[(upper >> 8) as u8, upper as u8],
+            )?;
+            upper_address = Some(upper);
+        }
+        let lower = record_address as u16;
+        let len = RECORD_DATA_LEN
+            .min(data.len() - offset)
+            .min(0x1_0000 - lower as usize);
+        write_record(writer, lower, DATA_RECORD, &data[offset..offset + len])?;
+        offset += len;
+    }
+    write_record(writer, 0, END_OF_FILE_RECORD, &[])
+}
+
+/// Writes one record, with its byte count and checksum.
+fn write_record<W: Write>(
+    writer: &mut W,
+    address: u16,
+    record_type: u8,
+    data: &[u8],
+) -> io::Result<()> {
+    write!(
+        writer,
+        ":{:02X}{:04X}{:02X}",
+        data.len(),
+        address,
+        record_type
+    )?;
+    let mut checksum = (data.len() as u8)
+        .wrapping_add((address >> 8) as u8)
+        .wrapping_add(address as u8)
+        .wrapping_add(record_type);
+    for byte in data {
+        write!(writer, "{:02X}", byte)?;
+        checksum = checksum.wrapping_add(*byte);
+    }
+    writeln!(writer, "{:02X}", checksum.wrapping_neg())
+}
+
+/// Parses the given Intel HEX records into contiguous segments of data, sorted by address.
+///
+/// Records must have valid checksums, the file must end with an end of file record, and data
+/// records cannot overlap.
+pub fn parse(text: &str) -> Result<Vec<(u32, Vec<u8>)>, Error> {
+    let mut records = Vec::new();
+    let mut base_address = 0_u64;
+    let mut end_of_file = false;
+    for (i, line) in text
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+    {
+        let line_number = i + 1;
+        if end_of_file {
+            bail!(
+                "found a record after the end of file record in line {}",
+                line_number
+            );
+        }
+        let bytes = match (
+            line.trim().get(..1),
+            line.trim().get(1..).and_then(decode_hex),
+        ) {
+            (Some(":"), Some(bytes)) => bytes,
+            _ => bail!("invalid Intel HEX record in line {}", line_number),
+        };
+        if bytes.len() < 5 || bytes.len() != usize::from(bytes[0]) + 5 {
+            bail!("invalid byte count in the record in line {}", line_number);
+        }
+        if bytes.iter().fold(0_u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            bail!("invalid checksum in the record in line {}", line_number);
+        }
+        let address = u64::from(bytes[1]) << 8 | u64::from(bytes[2]);
+        let data = &bytes[4..bytes.len() - 1];
+        match bytes[3] {
+            DATA_RECORD => {
+                let start = base_address + address;
+                if start + data.len() as u64 > u64::from(u32::max_value()) + 1 {
+                    bail!(
+                        "the record in line {} goes past the end of the memory address space",
+                        line_number
+                    );
+                }
+                records.push((start, data.to_vec()));
+            }
+            END_OF_FILE_RECORD => end_of_file = true,
+            EXTENDED_SEGMENT_ADDRESS_RECORD | EXTENDED_LINEAR_ADDRESS_RECORD if data.len() == 2 => {
+                let shift = if bytes[3] == EXTENDED_LINEAR_ADDRESS_RECORD {
+                    16
+                } else {
+                    4
+                };
+                base_address = (u64::from(data[0]) << 8 | u64::from(data[1])) << shift;
+            }
+            // Start address records are not needed to write the data.
+            START_SEGMENT_ADDRESS_RECORD | START_LINEAR_ADDRESS_RECORD if data.len() == 4 => {}
+            _ => bail!("invalid record type in line {}", line_number),
+        }
+    }
+    if !end_of_file {
+        bail!("the end of file record is missing");
+    }
+    merge_segments(records)
+}
+
+/// Merges the given records, with their start addresses, into contiguous segments of data.
+///
+/// Fails if any of the records overlap.
+pub fn merge_segments(mut records: Vec<(u64, Vec<u8>)>) -> Result<Vec<(u32, Vec<u8>)>, Error> {
+    records.sort_by_key(|&(start, _)| start);
+    let mut segments: Vec<(u32, Vec<u8>)> = Vec::new();
+    for (start, data) in records {
+        if let Some(&mut (segment_start, ref mut segment)) = segments.last_mut() {
+            let segment_end = u64::from(segment_start) + segment.len() as u64;
+            if start < segment_end {
+                bail!("there are overlapping records at address {:#010x}", start);
+            } else if start == segment_end {
+                segment.extend_from_slice(&data);
+                continue;
+            }
+        }
+        segments.push((start as u32, data));
+    }
+    Ok(segments)
+}
+
+/// Decodes the given hexadecimal string into bytes.
+pub fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, write};
+
+    /// Encodes the given data at the given address as Intel HEX records.
+    fn records(address: u32, data: &[u8]) -> String {
+        let mut records = Vec::new();
+        write(&mut records, address, data).unwrap();
+        String::from_utf8(records).unwrap()
+    }
+
+    #[test]
+    fn known_records() {
+        assert_eq!(
+            records(0x4000_0000, &[0x01, 0x02, 0x03]),
+            ":020000044000BA\n:03000000010203F7\n:00000001FF\n"
+        );
+    }
+
+    #[test]
+    fn upper_address_change() {
+        // The data records are split at the 64 KiB boundary, with a new extended address record.
+        assert_eq!(
+            records(0x0001_fffe, &[0x01, 0x02, 0x03, 0x04]),
+            ":020000040001F9\n:02FFFE000102FE\n:020000040002F8\n:020000000304F7\n:00000001FF\n"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = (0..0x25).collect::<Vec<u8>>();
+        let text = records(0x4000_fff8, &data);
+        assert_eq!(parse(&text).unwrap(), vec![(0x4000_fff8, data)]);
+    }
+
+    #[test]
+    fn overlapping_records() {
+        assert!(parse(":020000000102FB\n:020002000304F5\n:00000001FF\n").is_ok());
+        assert!(parse(":020000000102FB\n:020001000304F6\n:00000001FF\n").is_err());
+    }
+
+    #[test]
+    fn invalid_records() {
+        // Invalid checksum.
+        assert!(parse(":03000000010203F8\n:00000001FF\n").is_err());
+        // Missing end of file record.
+        assert!(parse(":03000000010203F7\n").is_err());
+        // Invalid byte count.
+        assert!(parse(":04000000010203F6\n:00000001FF\n").is_err());
+    }
+}
@@ -0,0 +1,499 @@
+//! Tools for Allwinner devices in FEL mode.
+//!
+//! The commands of the `fel-cli` binary can also be run programmatically: open a FEL device with
+//! [`open_device`] and [`execute`] a [`Command`] in it with the given [`Options`], getting its
+//! [`Output`]. Commands that run until interrupted, such as `watch`, need
+//! [`execute_with_report`] instead, which reports the output as it's produced.
+//!
+//! [`open_device`]: fn.open_device.html
+//! [`execute`]: fn.execute.html
+//! [`execute_with_report`]: fn.execute_with_report.html
+//! [`Command`]: config/enum.Command.html
+//! [`Options`]: struct.Options.html
+//! [`Output`]: output/enum.Output.html
+
+#![forbid(anonymous_parameters)]
+#![warn(clippy::pedantic)]
+#![deny(
+    clippy::all,
+    variant_size_differences,
+    unused_results,
+    unused_qualifications,
+    unused_import_braces,
+    unsafe_code,
+    trivial_numeric_casts,
+    trivial_casts,
+    missing_docs,
+    unused_extern_crates,
+    missing_debug_implementations,
+    missing_copy_implementations
+)]
+#![allow(clippy::cast_possible_truncation)]
+
+use std::{
+    sync::{atomic::AtomicBool, Arc},
+    time::Duration,
+};
+
+use aw_fel::{Fel, FelHandle};
+use failure::{bail, Error, Fail, ResultExt};
+use log::{debug, trace};
+
+pub mod carray;
+pub mod cli;
+mod commands;
+pub mod config;
+pub mod ihex;
+pub mod output;
+pub mod record;
+pub mod srec;
+
+use crate::{config::Command, output::Output};
+
+/// Default number of bytes in each line of a hexadecimal dump.
+const HEX_DUMP_LINE: usize = 0x10;
+/// Default size of the chunks in which memory is read from and written to the device.
+const DEFAULT_CHUNK_SIZE: usize = 0x1_0000;
+/// Minimum size of the chunks in which memory is transferred.
+const MIN_CHUNK_SIZE: usize = 0x200;
+/// Maximum size of the chunks in which memory is transferred.
+const MAX_CHUNK_SIZE: usize = 0x100_0000;
+/// End of the memory that can be transferred to or from the device, exclusive.
+///
+/// aw-fel panics on transfers that reach the last two bytes of the 32-bit address space.
+const TRANSFER_END: u32 = 0xFFFF_FFFE;
+/// USB vendor ID of the FEL devices.
+const AW_VENDOR_ID: u16 = 0x1f3a;
+/// USB product ID of the FEL devices.
+const AW_PRODUCT_ID: u16 = 0xefe8;
+/// Timeout of the USB string descriptor requests, in milliseconds.
+const USB_STRING_TIMEOUT_MS: u64 = 1000;
+
+/// CLI error.
+#[derive(Debug, Fail)]
+#[fail(display = "CLI error: {}", description)]
+pub struct CliError {
+    /// Description of the CLI error.
+    description: String,
+}
+
+impl CliError {
+    /// Creates a CLI error with the given description.
+    pub fn new(description: String) -> Self {
+        Self { description }
+    }
+
+    /// Checks that no problems were found in the given part of the input.
+    ///
+    /// A single problem is returned as is, and several of them are listed in one CLI error, with
+    /// their causes.
+    pub(crate) fn check_problems(mut problems: Vec<Error>, part: &str) -> Result<(), Error> {
+        if problems.len() > 1 {
+            let mut description = format!("found {} problems in {}:", problems.len(), part);
+            for e in problems {
+                description.push_str(&format!("\n  - {}", e));
+                for cause in e.iter_causes() {
+                    description.push_str(&format!(" (caused by: {})", cause));
+                }
+            }
+            Err(Self { description }.into())
+        } else if let Some(e) = problems.pop() {
+            Err(e)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks that the given size is valid for the chunks in which memory is transferred.
+    pub(crate) fn check_chunk_size(size: u64) -> Result<usize, Error> {
+        if size >= MIN_CHUNK_SIZE as u64 && size <= MAX_CHUNK_SIZE as u64 && size.is_power_of_two()
+        {
+            Ok(size as usize)
+        } else {
+            Err(Self {
+                description: format!(
+                    "the chunk size must be a power of two from {} to {} bytes, given {}",
+                    MIN_CHUNK_SIZE, MAX_CHUNK_SIZE, size
+                ),
+            }
+            .into())
+        }
+    }
+}
+
+/// Error when the selected FEL device, or any FEL device, is not found.
+#[derive(Debug, Fail)]
+#[fail(display = "{}", description)]
+pub struct DeviceNotFoundError {
+    /// Description of the device that was not found.
+    description: String,
+}
+
+impl DeviceNotFoundError {
+    /// Creates an error for the FEL device with the given description, which was not found.
+    pub fn new(description: String) -> Self {
+        Self { description }
+    }
+}
+
+/// Selection of the FEL device to open.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceSelector {
+    /// The first available device.
+    Default,
+    /// The device in the given USB bus and address.
+    BusAddr(u8, u8),
+    /// The device with the given index in the list of available devices.
+    Index(usize),
+    /// The device with the given USB serial number.
+    Serial(String),
+}
+
+/// Options of the execution of the commands.
+///
+/// By default, failed transfers are not retried, memory is transferred in chunks of 64 KiB, and
+/// neither the progress nor the throughput of the transfers are reported.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Number of times a memory operation that failed because of a USB error is retried.
+    usb_retries: u32,
+    /// Size of the chunks in which memory is read from and written to the device.
+    chunk_size: usize,
+    /// Number of times a failed input file read is retried.
+    file_read_retries: u32,
+    /// Report the progress of big memory transfers.
+    show_progress: bool,
+    /// Report the throughput of every memory transfer.
+    show_stats: bool,
+    /// Use uppercase hexadecimal digits in the messages.
+    hex_uppercase: bool,
+    /// Prime the USB link with a throwaway read before running the command.
+    prewarm: bool,
+    /// Report the SID of the device, read before running the command.
+    show_sid: bool,
+    /// Check that the device still answers FEL requests after running the command.
+    require_clean_exit: bool,
+    /// Report the diagnostic registers of the device when a mutating command fails.
+    dump_registers_on_error: bool,
+    /// Addresses of the diagnostic registers, or empty for the default ones of the SoC.
+    diagnostic_registers: Vec<u32>,
+    /// Base address and size of the region where scratch buffers are allocated, or `None` to
+    /// choose one from the SRAM of the SoC.
+    scratch_region: Option<(u32, u32)>,
+    /// Set to stop the repeated commands before their next iteration.
+    interrupted: Arc<AtomicBool>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            usb_retries: 0,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            file_read_retries: 0,
+            show_progress: false,
+            show_stats: false,
+            hex_uppercase: false,
+            prewarm: false,
+            show_sid: false,
+            require_clean_exit: false,
+            dump_registers_on_error: false,
+            diagnostic_registers: Vec::new(),
+            scratch_region: None,
+            interrupted: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+impl Options {
+    /// Sets the number of times a memory operation that failed because of a USB error is retried.
+    pub fn with_usb_retries(mut self, retries: u32) -> Self {
+        self.usb_retries = retries;
+        self
+    }
+
+    /// Sets the size of the chunks in which memory is read from and written to the device.
+    ///
+    /// The size must be a power of two from 512 bytes to 16 MiB.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Result<Self, Error> {
+        self.chunk_size = CliError::check_chunk_size(chunk_size as u64)?;
+        Ok(self)
+    }
+
+    /// Sets the number of times a failed input file read is retried.
+    pub fn with_file_read_retries(mut self, retries: u32) -> Self {
+        self.file_read_retries = retries;
+        self
+    }
+
+    /// Sets whether the progress of big memory transfers is reported.
+    pub fn with_progress(mut self, show_progress: bool) -> Self {
+        self.show_progress = show_progress;
+        self
+    }
+
+    /// Sets whether the throughput of every memory transfer is reported.
+    pub fn with_stats(mut self, show_stats: bool) -> Self {
+        self.show_stats = show_stats;
+        self
+    }
+
+    /// Sets whether the messages use uppercase hexadecimal digits.
+    pub fn with_hex_uppercase(mut self, hex_uppercase: bool) -> Self {
+        self.hex_uppercase = hex_uppercase;
+        self
+    }
+
+    /// Sets whether the USB link is primed with a throwaway read before running the command.
+    pub fn with_prewarm(mut self, prewarm: bool) -> Self {
+        self.prewarm = prewarm;
+        self
+    }
+
+    /// Sets whether the SID of the device is read before running the command and reported.
+    pub fn with_sid(mut self, show_sid: bool) -> Self {
+        self.show_sid = show_sid;
+        self
+    }
+
+    /// Sets whether the device is checked to still answer FEL requests after running the command.
+    pub fn with_clean_exit(mut self, require_clean_exit: bool) -> Self {
+        self.require_clean_exit = require_clean_exit;
+        self
+    }
+
+    /// Sets whether the given diagnostic registers are reported when a mutating command fails.
+    ///
+    /// The default registers of the SoC are reported if no address is given.
+    pub fn with_registers_on_error(mut self, dump: bool, registers: Vec<u32>) -> Self {
+        self.dump_registers_on_error = dump;
+        self.diagnostic_registers = registers;
+        self
+    }
+
+    /// Sets the base address and size of the region where the commands allocate the scratch
+    /// buffers they need in the device.
+    ///
+    /// If it's `None`, the region is chosen from the free SRAM of the SoC.
+    pub fn with_scratch_region(mut self, region: Option<(u32, u32)>) -> Self {
+        self.scratch_region = region;
+        self
+    }
+
+    /// Gets the flag that stops the repeated commands, such as `watch`, before their next
+    /// iteration, so that it can be set when they are interrupted.
+    ///
+    /// It's cleared when each repeated command starts.
+    pub fn get_interrupt_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.interrupted)
+    }
+}
+
+/// Finds the connected FEL devices, with their USB bus and address.
+pub fn find_devices(fel: &Fel) -> Result<Vec<(u8, u8, FelHandle)>, Error> {
+    let context = libusb::Context::new().context("unable to create libUSB context")?;
+    let mut devices = Vec::new();
+    for usb_device in context
+        .devices()
+        .context("unable to list USB devices")?
+        .iter()
+    {
+        let (bus, addr) = (usb_device.bus_number(), usb_device.address());
+        if let Some(device) = fel.get_device(bus, addr)? {
+            debug!("Found a FEL device in {}:{}", bus, addr);
+            devices.push((bus, addr, device));
+        } else {
+            trace!("The USB device in {}:{} is not a FEL device", bus, addr);
+        }
+    }
+    Ok(devices)
+}
+
+/// Finds the USB bus and address of the FEL device with the given USB serial number.
+///
+/// If the device is not found, the error is a `DeviceNotFoundError`.
+pub fn find_device_by_serial(serial: &str) -> Result<(u8, u8), Error> {
+    let context = libusb::Context::new().context("unable to create libUSB context")?;
+    let mut found = Vec::new();
+    for usb_device in context
+        .devices()
+        .context("unable to list USB devices")?
+        .iter()
+    {
+        let (bus, addr) = (usb_device.bus_number(), usb_device.address());
+        let descriptor = usb_device
+            .device_descriptor()
+            .context("unable to get the USB device descriptor")?;
+        if descriptor.vendor_id() != AW_VENDOR_ID || descriptor.product_id() != AW_PRODUCT_ID {
+            trace!("The USB device in {}:{} is not a FEL device", bus, addr);
+            continue;
+        }
+        match read_serial_number(&usb_device, &descriptor) {
+            Ok(Some(ref device_serial)) if device_serial == serial => {
+                debug!(
+                    "Found the FEL device with serial number '{}' in {}:{}",
+                    serial, bus, addr
+                );
+                found.push((bus, addr));
+            }
+            Ok(Some(device_serial)) => debug!(
+                "The FEL device in {}:{} has serial number '{}'",
+                bus, addr, device_serial
+            ),
+            Ok(None) => debug!("The FEL device in {}:{} has no serial number", bus, addr),
+            Err(e) => debug!(
+                "Unable to read the serial number of the FEL device in {}:{}: {}",
+                bus, addr, e
+            ),
+        }
+    }
+    match *found {
+        [device] => Ok(device),
+        [] => Err(DeviceNotFoundError::new(format!(
+            "no FEL device found with serial number '{}'",
+            serial
+        ))
+        .into()),
+        _ => bail!(
+            "found {} FEL devices with serial number '{}', select one with `--device`",
+            found.len(),
+            serial
+        ),
+    }
+}
+
+/// Reads the USB serial number of the given device, if it has one.
+fn read_serial_number(
+    device: &libusb::Device,
+    descriptor: &libusb::DeviceDescriptor,
+) -> Result<Option<String>, Error> {
+    if descriptor.serial_number_string_index().is_none() {
+        return Ok(None);
+    }
+    let timeout = Duration::from_millis(USB_STRING_TIMEOUT_MS);
+    let handle = device.open().context("unable to open the USB device")?;
+    let languages = handle
+        .read_languages(timeout)
+        .context("unable to read the languages of the USB device")?;
+    match languages.first() {
+        Some(&language) => Ok(Some(
+            handle
+                .read_serial_number_string(language, descriptor, timeout)
+                .context("unable to read the USB serial number")?,
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Opens the selected FEL device.
+///
+/// If the device is not found, the error is a `DeviceNotFoundError`.
+pub fn open_device(fel: &Fel, selector: DeviceSelector) -> Result<FelHandle, Error> {
+    match selector {
+        DeviceSelector::Default => {
+            let mut dev_list = fel
+                .list_devices()
+                .context("unable to list the FEL devices")?;
+            debug!("Found {} FEL devices, using the first one", dev_list.len());
+            if dev_list.is_empty() {
+                return Err(DeviceNotFoundError::new("no FEL devices found".to_owned()).into());
+            }
+            Ok(dev_list.swap_remove(0))
+        }
+        DeviceSelector::BusAddr(bus, addr) => {
+            debug!("Opening the FEL device in {}:{}", bus, addr);
+            if let Some(device) = fel.get_device(bus, addr)? {
+                Ok(device)
+            } else {
+                Err(DeviceNotFoundError::new(format!(
+                    "no FEL device found in bus {} with address {}",
+                    bus, addr
+                ))
+                .into())
+            }
+        }
+        DeviceSelector::Index(index) => {
+            let mut dev_list = fel
+                .list_devices()
+                .context("unable to list the FEL devices")?;
+            if index >= dev_list.len() {
+                return Err(DeviceNotFoundError::new(format!(
+                    "no FEL device found with index {}, there are {} devices",
+                    index,
+                    dev_list.len()
+                ))
+                .into());
+            }
+            debug!("Using the FEL device with index {}", index);
+            Ok(dev_list.swap_remove(index))
+        }
+        DeviceSelector::Serial(serial) => {
+            let (bus, addr) = find_device_by_serial(&serial)?;
+            open_device(fel, DeviceSelector::BusAddr(bus, addr))
+        }
+    }
+}
+
+/// Executes the given command in the given device, returning its output.
+///
+/// The output of the command is lost if it fails, and commands that run until interrupted only
+/// return once the interrupt flag of the options is set, so [`execute_with_report`] should be
+/// used for them.
+///
+/// The `list` and `completions` commands don't run in a device, so they can't be executed.
+///
+/// [`execute_with_report`]: fn.execute_with_report.html
+pub fn execute(
+    device: &FelHandle,
+    command: &Command,
+    opts: &Options,
+) -> Result<Vec<Output>, Error> {
+    let mut outputs = Vec::new();
+    execute_with_report(device, command, opts, &mut |output| {
+        outputs.push(output);
+        Ok(())
+    })?;
+    Ok(outputs)
+}
+
+/// Executes the given command in the given device, passing its output to the given function as
+/// soon as it's produced.
+///
+/// If the function fails, the command stops with its error. The `list` and `completions` commands
+/// don't run in a device, so they can't be executed.
+pub fn execute_with_report(
+    device: &FelHandle,
+    command: &Command,
+    opts: &Options,
+    report: &mut dyn FnMut(Output) -> Result<(), Error>,
+) -> Result<(), Error> {
+    commands::execute(device, command, opts, report)
+}
+
+/// Describes what the given command would do, without touching any device, reporting each of its
+/// steps as [`Output::DryRun`] to the given function.
+///
+/// The input files are read to describe the data written from them. The `completions` command
+/// cannot be described, since it does not run in a device.
+///
+/// [`Output::DryRun`]: output/enum.Output.html#variant.DryRun
+pub fn describe(
+    command: &Command,
+    opts: &Options,
+    report: &mut dyn FnMut(Output) -> Result<(), Error>,
+) -> Result<(), Error> {
+    commands::describe(command, opts, report)
+}
+
+/// Checks the input files of the given command without touching any device, reporting the
+/// problems while reading them, such as retries, to the given function.
+///
+/// The Intel HEX and S-record files are parsed and their segments relocated, to check that they
+/// fit in the memory that can be transferred. The rest of the files are checked when parsing the
+/// CLI.
+pub fn validate(
+    command: &Command,
+    opts: &Options,
+    report: &mut dyn FnMut(Output) -> Result<(), Error>,
+) -> Result<(), Error> {
+    commands::validate(command, opts, report)
+}
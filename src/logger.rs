@@ -0,0 +1,44 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Logger that prints the log records to `stderr`.
+struct StderrLogger;
+
+/// Global logger instance.
+static LOGGER: StderrLogger = StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let level = match record.level() {
+                Level::Error => "error",
+                Level::Warn => "warning",
+                Level::Info => "info",
+                Level::Debug => "debug",
+                Level::Trace => "trace",
+            };
+            eprintln!("[{}] {}", level, record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Initializes the logger with the given verbosity level.
+///
+/// Only warnings are printed by default, and each extra level enables the info, debug and trace
+/// logs.
+pub fn init(verbosity: u64) {
+    // The logger can only fail to be set if another one was set before.
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(match verbosity {
+            0 => LevelFilter::Warn,
+            1 => LevelFilter::Info,
+            2 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        });
+    }
+}
@@ -0,0 +1,393 @@
+//! Output of the commands, reported as it's produced for the caller to print it.
+
+use std::{
+    fmt,
+    io::{self, Write},
+    str::FromStr,
+    time::Duration,
+};
+
+use ansi_term::{
+    Colour::{Green, Yellow},
+    Style,
+};
+use aw_fel::FelHandle;
+
+use crate::config::{Address, ColorMode, DiffFormat, HashAlgorithm, SidFormat};
+
+/// Friendly names of the known SoC IDs.
+const SOC_NAMES: &[(u32, &str)] = &[
+    (0x1623, "A10"),
+    (0x1625, "A10s/A13/R8"),
+    (0x1633, "A31"),
+    (0x1639, "A80"),
+    (0x1650, "A23"),
+    (0x1651, "A20"),
+    (0x1663, "F1C100s"),
+    (0x1667, "A33/R16"),
+    (0x1673, "A83T"),
+    (0x1680, "H3/H2+"),
+    (0x1681, "V3s"),
+    (0x1689, "A64"),
+    (0x1701, "R40"),
+    (0x1718, "H5"),
+    (0x1719, "A63"),
+    (0x1728, "H6"),
+];
+
+/// Output of a command.
+#[derive(Debug, Clone)]
+pub enum Output {
+    /// Status of a long running command, such as the step it's running.
+    Status(String),
+    /// Confirmation that the command, or one of its steps, succeeded.
+    Confirmation(String),
+    /// Problem that did not stop the command.
+    Warning(String),
+    /// Description of one of the steps of the command, in a dry run.
+    DryRun(String),
+    /// Progress of a memory transfer.
+    Progress {
+        /// Number of bytes transferred so far.
+        done: usize,
+        /// Total number of bytes to transfer.
+        total: usize,
+        /// Time since the start of the transfer.
+        elapsed: Duration,
+    },
+    /// End of the memory transfer whose progress was reported.
+    ProgressEnd,
+    /// Throughput of a memory transfer, as in `Read 4.00 MiB in 1.83s (2.19 MiB/s)`.
+    Stats(String),
+    /// Time the read that primed the USB link took.
+    Prewarm(Duration),
+    /// Dumped data, for the standard output.
+    Data(Vec<u8>),
+    /// Dumped memory, to print in hexadecimal.
+    HexDump {
+        /// Address of the first byte.
+        address: u32,
+        /// Dumped bytes.
+        data: Vec<u8>,
+        /// Number of bytes in each line.
+        width: usize,
+        /// When to color the dump.
+        color: ColorMode,
+    },
+    /// Header of one of several repeated dumps.
+    DumpHeader {
+        /// Number of the dump, starting at 1.
+        number: u32,
+        /// Total number of dumps.
+        count: u32,
+    },
+    /// Dumped words.
+    Words {
+        /// Address of the first word.
+        address: u32,
+        /// Values of the words, in the requested byte order.
+        words: Vec<u32>,
+    },
+    /// Dumped SID registers.
+    Sid {
+        /// Values of the SID registers.
+        sid: [u32; 4],
+        /// Format to print them in.
+        format: SidFormat,
+    },
+    /// SID registers of the device, read before running the command, if it has them.
+    DeviceSid(Option<[u32; 4]>),
+    /// Entry point of the written U-Boot image, when it's not executed.
+    EntryPoint(u32),
+    /// Address, description and result of each of the writes of a `write` command.
+    WriteSummary(Vec<(u32, String, WriteStatus)>),
+    /// SoC version information.
+    Version(VersionInfo),
+    /// Memory information of the SoC.
+    MemInfo {
+        /// SoC version information.
+        version: VersionInfo,
+        /// DRAM information.
+        dram: DramInfo,
+    },
+    /// Start of the memory map, before probing its regions.
+    MapHeader,
+    /// Probed region of the memory map.
+    MapRegion {
+        /// Name of the region.
+        name: &'static str,
+        /// Base address of the region.
+        base: u32,
+        /// Whether the region could be read.
+        accessible: bool,
+    },
+    /// CRC-32 of a memory region.
+    Crc32(u32),
+    /// Hash digest of a memory region.
+    Digest {
+        /// Hash algorithm.
+        algorithm: HashAlgorithm,
+        /// Start address of the region.
+        address: u32,
+        /// Size of the region, in bytes.
+        size: u32,
+        /// Digest bytes.
+        digest: Vec<u8>,
+    },
+    /// Line of two compared memory regions that differs.
+    Difference {
+        /// Offset of the line in the regions.
+        offset: u32,
+        /// Address of the line in the first region.
+        addr_a: u32,
+        /// Bytes of the line in the first region.
+        line_a: Vec<u8>,
+        /// Address of the line in the second region.
+        addr_b: u32,
+        /// Bytes of the line in the second region.
+        line_b: Vec<u8>,
+        /// Number of bytes in each line.
+        width: usize,
+        /// Rendering of the difference.
+        format: DiffFormat,
+    },
+    /// Read of a watched word.
+    Watch {
+        /// Time since the start of the watch.
+        elapsed: Duration,
+        /// Address of the word.
+        address: u32,
+        /// Value of the word.
+        value: u32,
+    },
+    /// Diagnostic registers, with their names, addresses and values, if they could be read.
+    Registers(Vec<(&'static str, u32, Option<u32>)>),
+}
+
+/// Result of one of the writes of a `write` command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteStatus {
+    /// The data was written.
+    Ok,
+    /// The write failed, so the rest were skipped.
+    Failed,
+    /// The write was skipped after a previous one failed.
+    Skipped,
+}
+
+/// Hexadecimal formatting of the output values.
+#[derive(Debug, Clone, Copy)]
+pub struct HexFormat {
+    /// Use uppercase digits.
+    uppercase: bool,
+}
+
+impl HexFormat {
+    /// Creates a new hexadecimal formatter.
+    pub fn new(uppercase: bool) -> Self {
+        Self { uppercase }
+    }
+
+    /// Formats the given value as zero-padded hexadecimal digits, without prefix.
+    pub fn digits(self, value: u32, width: usize) -> String {
+        if self.uppercase {
+            format!("{:0width$X}", value, width = width)
+        } else {
+            format!("{:0width$x}", value, width = width)
+        }
+    }
+
+    /// Formats the given 32-bit word as `0x` prefixed hexadecimal.
+    pub fn word(self, value: u32) -> String {
+        format!("0x{}", self.digits(value, 8))
+    }
+
+    /// Formats the given byte as `0x` prefixed hexadecimal.
+    pub fn byte(self, value: u8) -> String {
+        format!("0x{}", self.digits(u32::from(value), 2))
+    }
+
+    /// Formats the given address, keeping the name of named addresses.
+    pub fn address(self, address: Address) -> String {
+        match (address.get_base(), address.get_offset()) {
+            (Some(base), 0) => format!("@{}", base.name()),
+            (Some(base), offset) => format!("@{}+{}", base.name(), self.word(offset)),
+            (None, address) => self.word(address),
+        }
+    }
+
+    /// Formats the given bytes as a single `0x` prefixed hexadecimal number.
+    pub fn bytes(self, values: &[u8]) -> String {
+        let digits = values
+            .iter()
+            .map(|value| self.digits(u32::from(*value), 2))
+            .collect::<String>();
+        format!("0x{}", digits)
+    }
+}
+
+/// SoC version information of a device.
+#[derive(Debug, Clone, Copy)]
+pub struct VersionInfo {
+    /// SoC ID.
+    soc_id: u32,
+    /// FEL protocol version, if it could be retrieved.
+    protocol: Option<u16>,
+    /// Scratchpad address, if it could be retrieved.
+    scratchpad: Option<u32>,
+}
+
+impl VersionInfo {
+    /// Gets the version information of the given device.
+    ///
+    /// `aw_fel` only exposes the SoC ID, so the rest of the fields are parsed from the debug
+    /// representation of the version.
+    pub fn from_device(device: &FelHandle) -> Self {
+        let version = device.get_version_info();
+        let debug = format!("{:?}", version);
+        Self {
+            soc_id: version.get_id(),
+            protocol: debug_field(&debug, "protocol"),
+            scratchpad: debug_field(&debug, "scratchpad"),
+        }
+    }
+
+    /// Gets the SoC ID.
+    pub fn get_soc_id(&self) -> u32 {
+        self.soc_id
+    }
+
+    /// Gets the FEL protocol version, if it could be retrieved.
+    pub fn get_protocol(&self) -> Option<u16> {
+        self.protocol
+    }
+
+    /// Gets the scratchpad address, if it could be retrieved.
+    pub fn get_scratchpad(&self) -> Option<u32> {
+        self.scratchpad
+    }
+
+    /// Gets the friendly name of the SoC, if it's known.
+    pub fn soc_name(&self) -> Option<&'static str> {
+        SOC_NAMES
+            .iter()
+            .find(|&&(id, _)| id == self.soc_id)
+            .map(|&(_, name)| name)
+    }
+
+    /// Gets the SoC ID, followed by its friendly name if it's known.
+    pub fn soc_label(&self, hex_fmt: HexFormat) -> String {
+        let soc_id = format!("0x{}", hex_fmt.digits(self.soc_id, 4));
+        match self.soc_name() {
+            Some(name) => format!("{} ({})", soc_id, name),
+            None => soc_id,
+        }
+    }
+}
+
+/// DRAM information of an SoC.
+#[derive(Debug, Clone, Copy)]
+pub enum DramInfo {
+    /// DRAM was initialized, with the given base address and size in bytes.
+    Initialized {
+        /// Base address.
+        base: u32,
+        /// Size, in bytes.
+        size: u64,
+    },
+    /// The DRAM controller was not configured, so DRAM was not initialized.
+    NotInitialized,
+    /// The DRAM controller of the SoC is not known.
+    Unknown,
+}
+
+impl DramInfo {
+    /// Gets the status of DRAM, as shown in the JSON output.
+    pub fn status(self) -> &'static str {
+        match self {
+            DramInfo::Initialized { .. } => "initialized",
+            DramInfo::NotInitialized => "not-initialized",
+            DramInfo::Unknown => "unknown",
+        }
+    }
+}
+
+/// Parses the decimal value of the given field from a debug representation of a structure.
+fn debug_field<T: FromStr>(debug: &str, name: &str) -> Option<T> {
+    let start = debug.find(&format!("{}: ", name))? + name.len() + 2;
+    debug[start..]
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Displays the given value, or `unknown` if it's not available.
+pub fn display_or_unknown<T: fmt::Display>(value: Option<T>) -> String {
+    value.map_or_else(|| "unknown".to_owned(), |value| value.to_string())
+}
+
+/// Pretty prints the given hexadecimal dump to the given writer.
+pub fn hex_dump<W: Write>(
+    writer: &mut W,
+    data: &[u8],
+    offset: u32,
+    width: usize,
+    color: bool,
+    hex_fmt: HexFormat,
+) -> io::Result<()> {
+    for (i, chunk) in data.chunks(width).enumerate() {
+        let start_address = offset + (i * width) as u32;
+        writeln!(
+            writer,
+            "{}",
+            hex_dump_line(chunk, start_address, width, color, hex_fmt)
+        )?;
+    }
+    Ok(())
+}
+
+/// Formats one line of a hexadecimal dump, starting at the given address.
+///
+/// With color, zero bytes are dimmed, and printable and non-printable ASCII characters are shown
+/// in green and yellow, both in the bytes and in the ASCII columns.
+pub fn hex_dump_line(
+    chunk: &[u8],
+    start_address: u32,
+    width: usize,
+    color: bool,
+    hex_fmt: HexFormat,
+) -> String {
+    let extra = width - chunk.len();
+    let mut bytes = String::with_capacity(width * 3);
+    let mut ascii = String::with_capacity(width);
+    for byte in chunk {
+        let byte = *byte;
+        let printable = match byte {
+            0x20..=0x7E => true,
+            _ => false,
+        };
+        let digits = hex_fmt.digits(u32::from(byte), 2);
+        let character = if printable { char::from(byte) } else { '.' };
+        if color {
+            let style = if byte == 0 {
+                Style::new().dimmed()
+            } else if printable {
+                Green.normal()
+            } else {
+                Yellow.normal()
+            };
+            bytes.push_str(&style.paint(digits).to_string());
+            ascii.push_str(&style.paint(character.to_string()).to_string());
+        } else {
+            bytes.push_str(&digits);
+            ascii.push(character);
+        }
+        bytes.push(' ');
+    }
+    for _ in 0..extra {
+        bytes.push_str("__ ");
+        ascii.push('.');
+    }
+    format!("{}: {} {}", hex_fmt.digits(start_address, 8), bytes, ascii)
+}
@@ -0,0 +1,417 @@
+//! Printing of the output of the commands.
+
+use std::io::{self, Write};
+
+use ansi_term::{
+    Colour::{Green, Red},
+    Style,
+};
+use atty::Stream;
+use failure::Error;
+use fel_cli::{
+    config::{ColorMode, Command, Config, DiffFormat, SidFormat},
+    output::{
+        display_or_unknown, hex_dump, hex_dump_line, DramInfo, HexFormat, Output, VersionInfo,
+        WriteStatus,
+    },
+};
+use serde_json::{json, Value};
+
+/// Printer of the output of the configured command.
+#[derive(Debug, Clone, Copy)]
+pub struct Printer<'a> {
+    /// Configuration of the run.
+    config: &'a Config,
+    /// Hexadecimal formatting of the output.
+    hex_fmt: HexFormat,
+}
+
+impl<'a> Printer<'a> {
+    /// Creates the printer of the output of the given configuration.
+    pub fn new(config: &'a Config) -> Self {
+        Self {
+            config,
+            hex_fmt: HexFormat::new(config.is_hex_uppercase()),
+        }
+    }
+
+    /// Prints the status of a long running command to `stderr`, unless it should be quiet.
+    pub fn status(&self, message: &str) {
+        if !self.config.is_quiet() {
+            eprintln!("{}", message);
+        }
+    }
+
+    /// Prints the confirmation message of a command, wrapped in a JSON object if JSON output is
+    /// used.
+    ///
+    /// Nothing is printed if the command should be quiet.
+    pub fn confirmation(&self, message: &str) {
+        if self.config.is_quiet() {
+            return;
+        }
+        if self.config.is_json() {
+            println!("{}", json!({ "status": "ok", "message": message }));
+        } else {
+            println!("{}", message);
+        }
+    }
+
+    /// Prints the given output of the command.
+    pub fn print(&self, output: Output) -> Result<(), Error> {
+        let hex_fmt = self.hex_fmt;
+        let json = self.config.is_json();
+        match output {
+            Output::Status(message) => self.status(&message),
+            Output::Confirmation(message) => self.confirmation(&message),
+            Output::Warning(message) => {
+                eprintln!("{} {}", Style::new().bold().paint("Warning:"), message)
+            }
+            Output::DryRun(step) => println!("{}", step),
+            Output::Progress {
+                done,
+                total,
+                elapsed,
+            } => {
+                if atty::is(Stream::Stderr) {
+                    print_progress(done, total, elapsed.as_secs(), elapsed.subsec_millis());
+                }
+            }
+            Output::ProgressEnd => {
+                if atty::is(Stream::Stderr) {
+                    eprintln!();
+                }
+            }
+            Output::Stats(message) => eprintln!("{}", message),
+            Output::Prewarm(elapsed) => eprintln!(
+                "Prewarm read took {} µs",
+                elapsed.as_secs() * 1_000_000 + u64::from(elapsed.subsec_micros())
+            ),
+            Output::Data(data) => {
+                let stdout = io::stdout();
+                let mut stdout = stdout.lock();
+                stdout.write_all(&data)?;
+                stdout.flush()?;
+            }
+            Output::HexDump {
+                address,
+                data,
+                width,
+                color,
+            } => {
+                let color = match color {
+                    ColorMode::Auto => atty::is(Stream::Stdout),
+                    ColorMode::Always => true,
+                    ColorMode::Never => false,
+                };
+                let stdout = io::stdout();
+                let mut stdout = stdout.lock();
+                hex_dump(&mut stdout, &data, address, width, color, hex_fmt)?;
+                stdout.flush()?;
+            }
+            Output::DumpHeader { number, count } => {
+                if !json {
+                    println!("--- Dump {} of {} ---", number, count);
+                }
+            }
+            Output::Words { address, words } => self.print_words(address, &words),
+            Output::Sid { sid, format } => match format {
+                SidFormat::Colon => println!("{}", format_sid(&sid, hex_fmt)),
+                SidFormat::Hex => println!(
+                    "{}",
+                    sid.iter()
+                        .map(|word| {
+                            (0..4_u32)
+                                .map(|i| hex_fmt.digits((word >> (8 * i)) & 0xFF, 2))
+                                .collect::<String>()
+                        })
+                        .collect::<String>()
+                ),
+                SidFormat::Json => println!("{}", sid_json(&sid, hex_fmt)),
+                SidFormat::Raw => unreachable!("raw SIDs are reported as data"),
+            },
+            Output::DeviceSid(sid) => match sid {
+                Some(sid) if json => println!("{}", sid_json(&sid, hex_fmt)),
+                Some(sid) => println!("SID: {}", format_sid(&sid, hex_fmt)),
+                None if json => println!("{}", json!({ "sid": null })),
+                None => println!("SID: not available in this device"),
+            },
+            Output::EntryPoint(entry_point) => {
+                if json {
+                    println!("{}", json!({ "entry_point": hex_fmt.word(entry_point) }));
+                } else {
+                    println!("{}", hex_fmt.word(entry_point));
+                }
+            }
+            Output::WriteSummary(rows) => {
+                println!("{:<10} {:<40} Result", "Address", "Data");
+                for (addr, description, status) in rows {
+                    let result = match status {
+                        WriteStatus::Ok => Green.paint("ok").to_string(),
+                        WriteStatus::Failed => Red.paint("failed").to_string(),
+                        WriteStatus::Skipped => "skipped".to_owned(),
+                    };
+                    println!("{} {:<40} {}", hex_fmt.word(addr), description, result);
+                }
+            }
+            Output::Version(version) => {
+                let json = json
+                    || match self.config.get_command() {
+                        Some(&Command::Version { json }) => json,
+                        _ => false,
+                    };
+                if json {
+                    println!("{}", version_json(&version, hex_fmt));
+                } else {
+                    print_version(&version, hex_fmt);
+                }
+            }
+            Output::MemInfo { version, dram } => self.print_meminfo(&version, dram),
+            Output::MapHeader => println!("{:<12} {:<10} Status", "Region", "Base"),
+            Output::MapRegion {
+                name,
+                base,
+                accessible,
+            } => println!(
+                "{:<12} {} {}",
+                name,
+                hex_fmt.word(base),
+                if accessible {
+                    "accessible"
+                } else {
+                    "not accessible"
+                }
+            ),
+            Output::Crc32(crc) => println!("{}", hex_fmt.word(crc)),
+            Output::Digest {
+                algorithm,
+                address,
+                size,
+                digest,
+            } => println!(
+                "{}({}..+0x{}) = {}",
+                algorithm.name(),
+                hex_fmt.word(address),
+                hex_fmt.digits(size, 1),
+                digest
+                    .iter()
+                    .map(|b| hex_fmt.digits(u32::from(*b), 2))
+                    .collect::<String>()
+            ),
+            Output::Difference {
+                offset,
+                addr_a,
+                line_a,
+                addr_b,
+                line_b,
+                width,
+                format,
+            } => match format {
+                DiffFormat::Offsets => {
+                    let bytes = line_a.iter().zip(line_b.iter()).enumerate();
+                    for (i, (byte_a, byte_b)) in bytes.filter(|&(_, (a, b))| a != b) {
+                        println!(
+                            "+0x{}: {} {}",
+                            hex_fmt.digits(offset + i as u32, 1),
+                            hex_fmt.byte(*byte_a),
+                            hex_fmt.byte(*byte_b)
+                        );
+                    }
+                }
+                DiffFormat::Unified => {
+                    println!(
+                        "@@ -{},{} +{},{} @@",
+                        hex_fmt.word(addr_a),
+                        line_a.len(),
+                        hex_fmt.word(addr_b),
+                        line_b.len()
+                    );
+                    println!("-{}", hex_dump_line(&line_a, addr_a, width, false, hex_fmt));
+                    println!("+{}", hex_dump_line(&line_b, addr_b, width, false, hex_fmt));
+                }
+                DiffFormat::Hexdump => println!(
+                    "{} | {}",
+                    hex_dump_line(&line_a, addr_a, width, false, hex_fmt),
+                    hex_dump_line(&line_b, addr_b, width, false, hex_fmt)
+                ),
+            },
+            // `stdout` is line buffered, so each value reaches the consumer as soon as it is read.
+            Output::Watch {
+                elapsed,
+                address,
+                value,
+            } => {
+                if json {
+                    println!(
+                        "{}",
+                        json!({
+                            "elapsed_ms": elapsed.as_secs() * 1000
+                                + u64::from(elapsed.subsec_millis()),
+                            "address": hex_fmt.word(address),
+                            "value": hex_fmt.word(value),
+                        })
+                    );
+                } else {
+                    println!(
+                        "[{:>5}.{:03}] {}",
+                        elapsed.as_secs(),
+                        elapsed.subsec_millis(),
+                        hex_fmt.word(value)
+                    );
+                }
+            }
+            Output::Registers(registers) => {
+                eprintln!("{}", Style::new().bold().paint("Diagnostic registers:"));
+                for (name, addr, value) in registers {
+                    match value {
+                        Some(value) => eprintln!(
+                            "  {:<10} {}: {}",
+                            name,
+                            hex_fmt.word(addr),
+                            hex_fmt.word(value)
+                        ),
+                        None => eprintln!("  {:<10} {}: unreadable", name, hex_fmt.word(addr)),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Prints the dumped words at the given address.
+    fn print_words(&self, addr: u32, words: &[u32]) {
+        let hex_fmt = self.hex_fmt;
+        if words.len() == 1 {
+            if self.config.is_json() {
+                println!(
+                    "{}",
+                    json!({ "address": hex_fmt.word(addr), "value": hex_fmt.word(words[0]) })
+                );
+            } else {
+                println!("{}", hex_fmt.word(words[0]));
+            }
+        } else if self.config.is_json() {
+            let list = words
+                .iter()
+                .enumerate()
+                .map(|(i, word)| {
+                    json!({
+                        "address": hex_fmt.word(addr + 4 * i as u32),
+                        "value": hex_fmt.word(*word),
+                    })
+                })
+                .collect::<Vec<_>>();
+            println!("{}", Value::Array(list));
+        } else {
+            for (i, word) in words.iter().enumerate() {
+                let word_addr = addr + 4 * i as u32;
+                println!("{}: {}", hex_fmt.word(word_addr), hex_fmt.word(*word));
+            }
+        }
+    }
+
+    /// Prints the memory information of the SoC.
+    fn print_meminfo(&self, version: &VersionInfo, dram: DramInfo) {
+        let hex_fmt = self.hex_fmt;
+        let scratchpad = version.get_scratchpad().map(|addr| hex_fmt.word(addr));
+        if self.config.is_json() {
+            let (base, size) = match dram {
+                DramInfo::Initialized { base, size } => (Some(hex_fmt.word(base)), Some(size)),
+                _ => (None, None),
+            };
+            println!(
+                "{}",
+                json!({
+                    "soc_id": format!("0x{}", hex_fmt.digits(version.get_soc_id(), 4)),
+                    "soc_name": version.soc_name(),
+                    "scratchpad": scratchpad,
+                    "dram_status": dram.status(),
+                    "dram_base": base,
+                    "dram_size": size,
+                })
+            );
+        } else {
+            println!("SoC: {}", version.soc_label(hex_fmt));
+            println!("Scratchpad: {}", display_or_unknown(scratchpad));
+            match dram {
+                DramInfo::Initialized { base, size } => {
+                    println!("DRAM base: {}", hex_fmt.word(base));
+                    println!("DRAM size: {} bytes ({} MiB)", size, size >> 20);
+                }
+                DramInfo::NotInitialized => println!("DRAM: not initialized"),
+                DramInfo::Unknown => {
+                    println!("DRAM: unknown, the DRAM controller of the SoC is not supported")
+                }
+            }
+        }
+    }
+}
+
+/// Prints the number of bytes transferred so far in a single line of `stderr`, with the estimated
+/// time left.
+fn print_progress(done: usize, total: usize, elapsed_secs: u64, elapsed_millis: u32) {
+    let elapsed_ms = elapsed_secs * 1000 + u64::from(elapsed_millis);
+    let eta = if done == 0 {
+        "unknown".to_owned()
+    } else {
+        format!(
+            "{} s",
+            elapsed_ms * (total - done) as u64 / done as u64 / 1000
+        )
+    };
+    eprint!(
+        "\r{:>3}% ({} of {} bytes, ETA {})    ",
+        done as u64 * 100 / total as u64,
+        done,
+        total,
+        eta
+    );
+}
+
+/// Prints the version information as labeled lines.
+fn print_version(version: &VersionInfo, hex_fmt: HexFormat) {
+    println!("SoC: {}", version.soc_label(hex_fmt));
+    println!("Protocol: {}", display_or_unknown(version.get_protocol()));
+    println!(
+        "Scratchpad: {}",
+        display_or_unknown(version.get_scratchpad().map(|addr| hex_fmt.word(addr)))
+    );
+}
+
+/// Formats the version information as a JSON object.
+///
+/// All the fields are always present, so that scripts can rely on them, with `null` for the values
+/// that could not be retrieved.
+fn version_json(version: &VersionInfo, hex_fmt: HexFormat) -> Value {
+    json!({
+        "soc_id": format!("0x{}", hex_fmt.digits(version.get_soc_id(), 4)),
+        "soc_name": version.soc_name(),
+        "protocol": version.get_protocol(),
+        "scratchpad": version.get_scratchpad().map(|addr| hex_fmt.word(addr)),
+    })
+}
+
+/// Formats the SID registers of a device as a JSON object.
+fn sid_json(sid: &[u32; 4], hex_fmt: HexFormat) -> Value {
+    json!({ "sid": sid.iter().map(|w| hex_fmt.word(*w)).collect::<Vec<_>>() })
+}
+
+/// Formats the SID registers of a device.
+fn format_sid(sid: &[u32; 4], hex_fmt: HexFormat) -> String {
+    format!(
+        "{}:{}:{}:{}",
+        hex_fmt.digits(sid[0], 8),
+        hex_fmt.digits(sid[1], 8),
+        hex_fmt.digits(sid[2], 8),
+        hex_fmt.digits(sid[3], 8)
+    )
+}
+
+/// Prints the given error and its causes to `stderr`.
+pub fn print_error(e: &Error) {
+    eprintln!("{} {}\n", Red.bold().paint("error:"), e);
+
+    for e in e.iter_causes() {
+        eprintln!("  {} {}\n", Style::new().bold().paint("caused_by:"), e);
+    }
+}
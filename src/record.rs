@@ -0,0 +1,111 @@
+//! Timestamped records of the words observed by `watch`.
+//!
+//! Each record is a frame of 16 bytes, all of them little endian: the time since the start of the
+//! `watch` command in microseconds (64 bits), the address of the word (32 bits) and its value (32
+//! bits). Records are appended, so a file can hold the frames of many runs.
+
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
+
+use failure::{bail, Error};
+
+/// Number of bytes in each record.
+const FRAME_LEN: usize = 16;
+
+/// Writes the record of the given value of the word at the given address, observed after the
+/// given time.
+pub fn write<W: Write>(
+    writer: &mut W,
+    elapsed: Duration,
+    address: u32,
+    value: u32,
+) -> io::Result<()> {
+    let micros = elapsed.as_secs() * 1_000_000 + u64::from(elapsed.subsec_micros());
+    let mut frame = [0_u8; FRAME_LEN];
+    for (i, byte) in frame[..8].iter_mut().enumerate() {
+        *byte = (micros >> (8 * i)) as u8;
+    }
+    for (i, byte) in frame[8..12].iter_mut().enumerate() {
+        *byte = (address >> (8 * i)) as u8;
+    }
+    for (i, byte) in frame[12..].iter_mut().enumerate() {
+        *byte = (value >> (8 * i)) as u8;
+    }
+    // The whole frame is written at once, so that an interrupted `watch` leaves whole records.
+    writer.write_all(&frame)
+}
+
+/// Parses the given records into the time, the address and the value of each observed word, in
+/// the order they were written.
+pub fn parse(data: &[u8]) -> Result<Vec<(Duration, u32, u32)>, Error> {
+    if data.len() % FRAME_LEN != 0 {
+        bail!(
+            "the last record is truncated, the records are {} bytes long",
+            FRAME_LEN
+        );
+    }
+    Ok(data
+        .chunks(FRAME_LEN)
+        .map(|frame| {
+            let micros = frame[..8]
+                .iter()
+                .rev()
+                .fold(0_u64, |micros, byte| micros << 8 | u64::from(*byte));
+            let word = |bytes: &[u8]| {
+                bytes
+                    .iter()
+                    .rev()
+                    .fold(0_u32, |word, byte| word << 8 | u32::from(*byte))
+            };
+            (
+                Duration::from_micros(micros),
+                word(&frame[8..12]),
+                word(&frame[12..]),
+            )
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{parse, write};
+
+    #[test]
+    fn records() {
+        let mut records = Vec::new();
+        write(
+            &mut records,
+            Duration::from_millis(1500),
+            0x01c2_0000,
+            0x1234_5678,
+        )
+        .unwrap();
+        assert_eq!(
+            records,
+            [
+                0x60, 0xE3, 0x16, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xC2, 0x01, 0x78, 0x56,
+                0x34, 0x12
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut records = Vec::new();
+        write(&mut records, Duration::from_micros(10), 0x1000, 0).unwrap();
+        write(&mut records, Duration::from_secs(3600), 0x1000, 0xffff_ffff).unwrap();
+        assert_eq!(
+            parse(&records).unwrap(),
+            vec![
+                (Duration::from_micros(10), 0x1000, 0),
+                (Duration::from_secs(3600), 0x1000, 0xffff_ffff),
+            ]
+        );
+        assert!(parse(&records[..20]).is_err());
+        assert_eq!(parse(&[]).unwrap(), vec![]);
+    }
+}
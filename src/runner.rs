@@ -0,0 +1,505 @@
+//! Selection of the FEL devices in which the configured command runs.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+use ansi_term::{
+    Colour::{Green, Red},
+    Style,
+};
+use atty::Stream;
+use aw_fel::{Fel, FelHandle};
+use crossbeam_utils::thread as scoped_thread;
+use failure::{bail, format_err, Error, ResultExt};
+use fel_cli::{
+    cli,
+    config::{Command, Config, DataFormat, SidFormat},
+    describe, execute_with_report, find_device_by_serial, find_devices, open_device,
+    output::{HexFormat, Output, VersionInfo},
+    record, validate, CliError, DeviceNotFoundError, DeviceSelector, Options,
+};
+use log::{debug, info};
+use serde_json::{json, Value};
+
+use crate::printer::{print_error, Printer};
+
+/// Time between checks for a connected FEL device while waiting for one, in milliseconds.
+const DEVICE_POLL_INTERVAL_MS: u64 = 200;
+
+/// Runs the configured command in the FEL devices selected in the configuration.
+pub fn run(config: &Config, options: &Options) -> Result<(), Error> {
+    let printer = Printer::new(config);
+    if config.get_command().is_none() {
+        println!(
+            "{} no command specified",
+            Style::new().bold().paint("Warning:")
+        );
+        return Ok(());
+    }
+    if config.is_validate_only() {
+        validate(config.get_command().unwrap(), options, &mut |output| {
+            printer.print(output)
+        })?;
+        printer.confirmation("The command is valid");
+        return Ok(());
+    }
+    if let Some(&Command::Completions { shell }) = config.get_command() {
+        cli::generate().gen_completions_to("fel-cli", shell, &mut io::stdout());
+        return Ok(());
+    }
+    if let Some(Command::Replay { file }) = config.get_command() {
+        return replay(&printer, file);
+    }
+    let is_list = match config.get_command() {
+        Some(&Command::List { .. }) => true,
+        _ => false,
+    };
+    if config.is_dry_run() && (config.no_device() || is_list) {
+        return print_dry_run(config, options);
+    }
+    let fel = Fel::initialize().context("unable to initialize the tool")?;
+    if config.wait_for_device() {
+        wait_for_device(config, &fel)?;
+    }
+    if let Some(&Command::List { json }) = config.get_command() {
+        return list_devices(config, &fel, json || config.is_json());
+    }
+    if config.all_devices() {
+        return execute_in_all_devices(config, options, &fel);
+    }
+
+    // The device with the serial number is selected by its bus and address, to show them in the
+    // summary.
+    let serial_device;
+    let devices = match config.get_serial() {
+        Some(serial) => {
+            serial_device = [find_device_by_serial(serial)?];
+            &serial_device[..]
+        }
+        None => config.get_devices(),
+    };
+    match *devices {
+        [] => {
+            let start = Instant::now();
+            let result = execute_in_default_device(config, options, &fel);
+            write_summary(config, &[summary_entry(config, None, start, &result)])?;
+            result
+        }
+        [(bus, addr)] => {
+            let start = Instant::now();
+            let result = open_device(&fel, DeviceSelector::BusAddr(bus, addr))
+                .and_then(|dev| execute(config, options, &dev));
+            write_summary(
+                config,
+                &[summary_entry(config, Some((bus, addr)), start, &result)],
+            )?;
+            result
+        }
+        ref devices => {
+            let mut summary = Vec::with_capacity(devices.len());
+            let mut failed = 0;
+            let mut error = None;
+            for &(bus, addr) in devices {
+                println!("{} {}:{}", Style::new().bold().paint("Device"), bus, addr);
+                let start = Instant::now();
+                let result = open_device(&fel, DeviceSelector::BusAddr(bus, addr))
+                    .and_then(|dev| execute(config, options, &dev));
+                summary.push(summary_entry(config, Some((bus, addr)), start, &result));
+                if let Err(e) = result {
+                    if !config.keep_going() {
+                        error = Some(e);
+                        break;
+                    }
+                    print_error(&e);
+                    failed += 1;
+                }
+            }
+            write_summary(config, &summary)?;
+            if let Some(e) = error {
+                return Err(e);
+            }
+            if failed > 0 {
+                bail!(
+                    "the command failed in {} of {} devices",
+                    failed,
+                    devices.len()
+                );
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Prints the values recorded by `watch --record` in the given file, in order.
+fn replay(printer: &Printer, path: &Path) -> Result<(), Error> {
+    let data = fs::read(path).context(format!(
+        "could not read the record file '{}'",
+        path.display()
+    ))?;
+    let records = record::parse(&data)
+        .context(format!("the record file '{}' is not valid", path.display()))?;
+    for (elapsed, address, value) in records {
+        printer.print(Output::Watch {
+            elapsed,
+            address,
+            value,
+        })?;
+    }
+    Ok(())
+}
+
+/// Waits until a FEL device is connected, for at most the configured timeout, if any.
+fn wait_for_device(config: &Config, fel: &Fel) -> Result<(), Error> {
+    let start = Instant::now();
+    let timeout = config.get_wait_timeout_secs().map(Duration::from_secs);
+    let mut waiting = false;
+    while fel
+        .list_devices()
+        .context("unable to list the FEL devices")?
+        .is_empty()
+    {
+        if timeout.map_or(false, |timeout| start.elapsed() >= timeout) {
+            if waiting {
+                eprintln!();
+            }
+            return Err(DeviceNotFoundError::new(format!(
+                "no FEL device was connected within {} seconds",
+                timeout.unwrap().as_secs()
+            ))
+            .into());
+        }
+        if !waiting && !config.is_quiet() {
+            eprint!("Waiting for FEL device...");
+            waiting = true;
+        }
+        thread::sleep(Duration::from_millis(DEVICE_POLL_INTERVAL_MS));
+    }
+    if waiting {
+        // Clears the waiting line.
+        eprint!("\r{:1$}\r", "", "Waiting for FEL device...".len());
+    }
+    Ok(())
+}
+
+/// Prints the USB bus and address and the SoC of each connected FEL device.
+fn list_devices(config: &Config, fel: &Fel, json: bool) -> Result<(), Error> {
+    let devices = find_devices(fel)?;
+    if json {
+        let list = devices
+            .iter()
+            .map(|&(bus, addr, ref device)| {
+                json!({
+                    "bus": bus,
+                    "address": addr,
+                    "soc_id": device.get_soc_info().get_soc_id(),
+                    "soc_name": device.get_soc_info().get_name(),
+                })
+            })
+            .collect::<Vec<_>>();
+        println!("{}", serde_json::to_string_pretty(&list)?);
+    } else if devices.is_empty() {
+        println!("No FEL devices found");
+    } else {
+        let hex_fmt = HexFormat::new(config.is_hex_uppercase());
+        println!("{:<4} {:<7} {:<10} Name", "Bus", "Address", "SoC ID");
+        for (bus, addr, device) in devices {
+            println!(
+                "{:<4} {:<7} {} {}",
+                bus,
+                addr,
+                hex_fmt.word(device.get_soc_info().get_soc_id()),
+                device.get_soc_info().get_name()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Executes the configured command concurrently in all the connected FEL devices.
+///
+/// A failure in one of the devices does not stop the rest, and a summary table of the results is
+/// printed at the end.
+fn execute_in_all_devices(config: &Config, options: &Options, fel: &Fel) -> Result<(), Error> {
+    let devices = find_devices(fel)?;
+    if devices.is_empty() {
+        if config.exit_zero_on_no_device() {
+            println!("No FEL devices found");
+            return Ok(());
+        }
+        return Err(DeviceNotFoundError::new("no FEL devices found".to_owned()).into());
+    }
+    if config.is_dry_run() {
+        println!("Would run in {} devices:", devices.len());
+        return print_dry_run(config, options);
+    }
+    confirm_command(config)?;
+
+    let command = config.get_command().unwrap();
+    let results = scoped_thread::scope(|scope| {
+        let handles = devices
+            .iter()
+            .map(|&(bus, addr, ref device)| {
+                scope.spawn(move |_| {
+                    let printer = Printer::new(config);
+                    let start = Instant::now();
+                    let result = execute_with_report(device, command, options, &mut |output| {
+                        printer.print(output)
+                    });
+                    let entry = summary_entry(config, Some((bus, addr)), start, &result);
+                    (result, entry)
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle.join().unwrap_or_else(|_| {
+                    (Err(format_err!("the device thread panicked")), Value::Null)
+                })
+            })
+            .collect::<Vec<_>>()
+    })
+    .map_err(|_| format_err!("a device thread panicked"))?;
+
+    println!("{:<8} Result", "Device");
+    let mut summary = Vec::with_capacity(results.len());
+    let mut failed = 0;
+    for (&(bus, addr, _), (result, entry)) in devices.iter().zip(results) {
+        let device = format!("{}:{}", bus, addr);
+        match result {
+            Ok(()) => println!("{:<8} {}", device, Green.paint("ok")),
+            Err(e) => {
+                let error = e
+                    .iter_chain()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(": ");
+                println!("{:<8} {} {}", device, Red.paint("failed:"), error);
+                failed += 1;
+            }
+        }
+        summary.push(entry);
+    }
+    write_summary(config, &summary)?;
+    if failed > 0 {
+        bail!(
+            "the command failed in {} of {} devices",
+            failed,
+            devices.len()
+        );
+    }
+    Ok(())
+}
+
+/// Executes the configured command in the preferred device, or in the first available one.
+fn execute_in_default_device(config: &Config, options: &Options, fel: &Fel) -> Result<(), Error> {
+    if let Some(selector) = config.get_preferred_device() {
+        let preferred = match *selector {
+            DeviceSelector::BusAddr(bus, addr) => format!("{}:{}", bus, addr),
+            DeviceSelector::Serial(ref serial) => format!("with serial number '{}'", serial),
+            DeviceSelector::Default | DeviceSelector::Index(_) => {
+                unreachable!("the preferred device is selected by bus and address or by serial")
+            }
+        };
+        match open_device(fel, selector.clone()) {
+            Ok(device) => {
+                debug!("Using the preferred FEL device {}", preferred);
+                return execute(config, options, &device);
+            }
+            Err(ref e) if e.downcast_ref::<DeviceNotFoundError>().is_some() => eprintln!(
+                "{} preferred device {} not found, using the first available device",
+                Style::new().bold().paint("Warning:"),
+                preferred
+            ),
+            Err(e) => return Err(e),
+        }
+    }
+    match open_device(fel, DeviceSelector::Default) {
+        Err(ref e)
+            if config.exit_zero_on_no_device()
+                && e.downcast_ref::<DeviceNotFoundError>().is_some() =>
+        {
+            println!("No FEL devices found");
+            Ok(())
+        }
+        device => execute(config, options, &device?),
+    }
+}
+
+/// Generates the JSON summary entry of the command run in the given device.
+///
+/// The device is `None` if it was not selected in the CLI.
+fn summary_entry(
+    config: &Config,
+    device: Option<(u8, u8)>,
+    start: Instant,
+    result: &Result<(), Error>,
+) -> Value {
+    let elapsed = start.elapsed();
+    let error = result.as_ref().err().map(|e| {
+        e.iter_chain()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(": ")
+    });
+    json!({
+        "device": device.map(|(bus, addr)| format!("{}:{}", bus, addr)),
+        "command": config.get_command().unwrap().name(),
+        "status": if result.is_ok() { "ok" } else { "failed" },
+        "duration_ms": elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis()),
+        "error": error,
+    })
+}
+
+/// Writes the JSON summary of the run to the file provided in the CLI, if any.
+fn write_summary(config: &Config, summary: &[Value]) -> Result<(), Error> {
+    if let Some(path) = config.get_summary_json() {
+        let mut file =
+            BufWriter::new(File::create(path).context("unable to create the JSON summary file")?);
+        serde_json::to_writer_pretty(&mut file, summary)
+            .context("unable to write the JSON summary file")?;
+        file.flush()
+            .context("unable to write the JSON summary file")?;
+    }
+    Ok(())
+}
+
+/// Executes the configured command in the given device, printing its output.
+fn execute(config: &Config, options: &Options, device: &FelHandle) -> Result<(), Error> {
+    let version = VersionInfo::from_device(device);
+    info!(
+        "Detected SoC 0x{} ({})",
+        HexFormat::new(config.is_hex_uppercase()).digits(version.get_soc_id(), 4),
+        version.soc_name().unwrap_or("unknown")
+    );
+    if config.is_dry_run() {
+        return print_dry_run(config, options);
+    }
+    confirm_command(config)?;
+    let command = config.get_command().unwrap();
+    check_terminal_output(command)?;
+    let printer = Printer::new(config);
+    execute_with_report(device, command, options, &mut |output| {
+        printer.print(output)
+    })
+}
+
+/// Prints what the configured command would do, without touching the device.
+fn print_dry_run(config: &Config, options: &Options) -> Result<(), Error> {
+    let printer = Printer::new(config);
+    describe(config.get_command().unwrap(), options, &mut |output| {
+        printer.print(output)
+    })
+}
+
+/// Asks the user to confirm the configured command, if it overwrites memory.
+fn confirm_command(config: &Config) -> Result<(), Error> {
+    let hex_fmt = HexFormat::new(config.is_hex_uppercase());
+    let question = match *config.get_command().unwrap() {
+        Command::Clear {
+            address, num_bytes, ..
+        } => format!(
+            "About to clear {} bytes at {}, continue?",
+            num_bytes,
+            hex_fmt.address(address)
+        ),
+        Command::Fill {
+            address,
+            num_bytes,
+            fill_byte,
+            ..
+        } => format!(
+            "About to fill {} bytes at {} with byte {}, continue?",
+            num_bytes,
+            hex_fmt.address(address),
+            hex_fmt.byte(fill_byte)
+        ),
+        Command::FillPattern {
+            address,
+            num_bytes,
+            ref pattern,
+            ..
+        } => format!(
+            "About to fill {} bytes at {} with pattern {}, continue?",
+            num_bytes,
+            hex_fmt.address(address),
+            hex_fmt.bytes(pattern)
+        ),
+        Command::MemTest { address, size, .. } => format!(
+            "About to overwrite {} bytes at {} to test them, continue?",
+            size,
+            hex_fmt.address(address)
+        ),
+        _ => return Ok(()),
+    };
+    confirm(config, &question)
+}
+
+/// Asks the user to confirm a destructive operation in the terminal, failing if it's not confirmed.
+///
+/// The question is skipped if it was already confirmed in the CLI, and it cannot be answered if
+/// `stdin` is not a terminal.
+fn confirm(config: &Config, question: &str) -> Result<(), Error> {
+    if config.assume_yes() {
+        return Ok(());
+    }
+    if !atty::is(Stream::Stdin) {
+        bail!(
+            "stdin is not a terminal to confirm the operation, use `--yes` to confirm it in the \
+             CLI"
+        );
+    }
+    eprint!("{} [y/N] ", question);
+    let mut answer = String::new();
+    let _ = io::stdin()
+        .read_line(&mut answer)
+        .context("unable to read the confirmation")?;
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => Ok(()),
+        _ => bail!("the operation was not confirmed"),
+    }
+}
+
+/// Checks that the command does not dump raw binary data to the terminal, unless it's forced.
+///
+/// It's checked before running the command, since reading the memory can take a while.
+fn check_terminal_output(command: &Command) -> Result<(), Error> {
+    if let Command::Dump {
+        sid,
+        sid_format,
+        size,
+        region,
+        hex,
+        format,
+        ref out,
+        force,
+        ..
+    } = *command
+    {
+        if out.is_some() || force || !atty::is(Stream::Stdout) {
+            return Ok(());
+        }
+        if sid && sid_format == SidFormat::Raw {
+            return Err(CliError::new(
+                "refusing to write the raw SID to the terminal, use `--out` to write it to a \
+                 file or `--force` to write it anyway"
+                    .to_owned(),
+            )
+            .into());
+        }
+        if !sid && (size.is_some() || region.is_some()) && !hex && format == DataFormat::Binary {
+            return Err(CliError::new(
+                "refusing to write raw binary data to the terminal, use `--hex` to print it in \
+                 hexadecimal, `--out` to write it to a file or `--force` to write it anyway"
+                    .to_owned(),
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
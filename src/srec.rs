@@ -0,0 +1,128 @@
+//! Motorola S-record encoding and decoding.
+
+use std::io::{self, Write};
+
+use failure::{bail, Error};
+
+use crate::ihex::{decode_hex, merge_segments};
+
+/// Maximum number of data bytes in a record.
+const RECORD_DATA_LEN: usize = 0x10;
+
+/// Writes the given data, starting at the given address, as Motorola S-records.
+///
+/// Data goes in `S3` records, with 32-bit addresses, and it's terminated with an `S7` record
+/// pointing to the start address.
+pub fn write<W: Write>(writer: &mut W, address: u32, data: &[u8]) -> io::Result<()> {
+    for (i, chunk) in data.chunks(RECORD_DATA_LEN).enumerate() {
+        let record_address = address.wrapping_add((i * RECORD_DATA_LEN) as u32);
+        write_record(writer, 3, record_address, chunk)?;
+    }
+    write_record(writer, 7, address, &[])
+}
+
+/// Writes one record with a 32-bit address, with its byte count and checksum.
+fn write_record<W: Write>(
+    writer: &mut W,
+    record_type: u8,
+    address: u32,
+    data: &[u8],
+) -> io::Result<()> {
+    // The byte count includes the address and the checksum.
+    let count = data.len() as u8 + 5;
+    write!(writer, "S{}{:02X}{:08X}", record_type, count, address)?;
+    let mut sum = count
+        .wrapping_add((address >> 24) as u8)
+        .wrapping_add((address >> 16) as u8)
+        .wrapping_add((address >> 8) as u8)
+        .wrapping_add(address as u8);
+    for byte in data {
+        write!(writer, "{:02X}", byte)?;
+        sum = sum.wrapping_add(*byte);
+    }
+    writeln!(writer, "{:02X}", !sum)
+}
+
+/// Parses the given Motorola S-records into contiguous segments of data, sorted by address.
+///
+/// Data can come in `S1`, `S2` or `S3` records, with 16, 24 or 32-bit addresses. Records must have
+/// valid checksums, and data records cannot overlap nor go past the end of the memory address
+/// space.
+pub fn parse(text: &str) -> Result<Vec<(u32, Vec<u8>)>, Error> {
+    let mut records = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let line_number = i + 1;
+        let (record_type, bytes) = match (line.get(..1), line.get(1..2), line.get(2..)) {
+            (Some("S"), Some(record_type), Some(hex)) => match decode_hex(hex) {
+                Some(bytes) => (record_type, bytes),
+                None => bail!("invalid S-record in line {}", line_number),
+            },
+            _ => bail!("invalid S-record in line {}", line_number),
+        };
+        if bytes.len() < 2 || bytes.len() != usize::from(bytes[0]) + 1 {
+            bail!("invalid byte count in the record in line {}", line_number);
+        }
+        if bytes.iter().fold(0_u8, |sum, b| sum.wrapping_add(*b)) != 0xFF {
+            bail!("invalid checksum in the record in line {}", line_number);
+        }
+        let address_len = match record_type {
+            "1" => 2,
+            "2" => 3,
+            "3" => 4,
+            // Header, record count and termination records are not needed to write the data.
+            "0" | "5" | "6" | "7" | "8" | "9" => continue,
+            _ => bail!("invalid record type in line {}", line_number),
+        };
+        if bytes.len() < address_len + 2 {
+            bail!("invalid byte count in the record in line {}", line_number);
+        }
+        let start = bytes[1..=address_len]
+            .iter()
+            .fold(0_u64, |address, b| address << 8 | u64::from(*b));
+        let data = &bytes[address_len + 1..bytes.len() - 1];
+        if start + data.len() as u64 > u64::from(u32::max_value()) + 1 {
+            bail!(
+                "the record in line {} goes past the end of the memory address space",
+                line_number
+            );
+        }
+        records.push((start, data.to_vec()));
+    }
+    merge_segments(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, write};
+
+    #[test]
+    fn records() {
+        let mut records = Vec::new();
+        write(&mut records, 0x4000_0000, &[0x01, 0x02, 0x03]).unwrap();
+        assert_eq!(
+            String::from_utf8(records).unwrap(),
+            "S30840000000010203B1\nS70540000000BA\n"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = (0..0x25).collect::<Vec<u8>>();
+        let mut records = Vec::new();
+        write(&mut records, 0x4000_fff8, &data).unwrap();
+        let records = String::from_utf8(records).unwrap();
+        // Three data records and the termination record.
+        assert_eq!(records.lines().count(), 4);
+        assert_eq!(parse(&records).unwrap(), vec![(0x4000_fff8, data)]);
+    }
+
+    #[test]
+    fn invalid_checksum() {
+        assert!(parse("S30840000000010203B1\n").is_ok());
+        assert!(parse("S30840000000010203B2\n").is_err());
+    }
+}